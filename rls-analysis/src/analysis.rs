@@ -47,7 +47,21 @@ pub struct PerCrateAnalysis {
 
     pub ref_spans: HashMap<Id, Vec<Span>>,
     pub globs: HashMap<Span, Glob>,
+    // Maps a file to the spans of the names imported by its `use` declarations
+    // (excluding globs).
+    pub imports: HashMap<PathBuf, Vec<Span>>,
+    // Maps a def to the `use` declarations importing it by its own name, with
+    // the module containing the declaration and the span of the name.
+    pub imported_by: HashMap<Id, Vec<(Id, Span)>>,
     pub impls: HashMap<Id, Vec<Span>>,
+    // Maps a type to the traits it implements, with the span of each impl.
+    pub impl_traits: HashMap<Id, Vec<(Id, Span)>>,
+    // Maps a trait to the types implementing it, with the span of each impl.
+    pub trait_impls: HashMap<Id, Vec<(Id, Span)>>,
+    // Maps a trait to its supertraits.
+    pub super_traits: HashMap<Id, Vec<Id>>,
+    // Maps a trait to the traits which have it as a supertrait.
+    pub sub_traits: HashMap<Id, Vec<Id>>,
     pub idents: HashMap<PathBuf, IdentsByLine>,
 
     pub root_id: Option<Id>,
@@ -100,7 +114,7 @@ pub struct Def {
     pub parent: Option<Id>,
     pub value: String,
     pub docs: String,
-    // pub sig: Option<Signature>,
+    pub sig: Option<Signature>,
 }
 
 pub type IdentsByLine = BTreeMap<Row<ZeroIndexed>, IdentsByColumn>;
@@ -136,12 +150,14 @@ pub struct Ident {
     pub kind: IdentKind,
 }
 
+/// A signature of an item, e.g., `fn foo(x: u32) -> bool`.
+///
+/// `defs` and `refs` point into `text` (by byte offsets) for any definitions
+/// (e.g., the item's name, generic parameters) or references (e.g., types of
+/// arguments) which occur in the signature.
 #[derive(Debug, Clone)]
 pub struct Signature {
-    pub span: Span,
     pub text: String,
-    pub ident_start: u32,
-    pub ident_end: u32,
     pub defs: Vec<SigElement>,
     pub refs: Vec<SigElement>,
 }
@@ -171,7 +187,13 @@ impl PerCrateAnalysis {
             def_fst_values: Vec::new(),
             ref_spans: HashMap::new(),
             globs: HashMap::new(),
+            imports: HashMap::new(),
+            imported_by: HashMap::new(),
             impls: HashMap::new(),
+            impl_traits: HashMap::new(),
+            trait_impls: HashMap::new(),
+            super_traits: HashMap::new(),
+            sub_traits: HashMap::new(),
             idents: HashMap::new(),
             root_id: None,
             timestamp,
@@ -180,16 +202,6 @@ impl PerCrateAnalysis {
         }
     }
 
-    // Returns true if there is a def in this crate with the same crate-local id
-    // and span as `def`.
-    pub(crate) fn has_congruent_def(&self, local_id: u32, span: &Span) -> bool {
-        let id = Id::from_crate_and_local(self.global_crate_num, local_id);
-        match self.defs.get(&id) {
-            Some(existing) => span == &existing.span,
-            None => false,
-        }
-    }
-
     // Returns all identifiers which overlap with `span`. There is no guarantee about
     // the ordering of identifiers in the result, but they will probably be roughly
     // in order of appearance.
@@ -368,7 +380,13 @@ impl Analysis {
     where
         F: Fn(&Vec<Span>) -> Option<T>,
     {
-        self.for_each_crate(|c| c.ref_spans.get(&id).and_then(&f))
+        // Defs compiled in several crates (e.g., with and without `cfg(test)`)
+        // are merged during lowering, so their refs are spread across crates.
+        let mut refs = self.per_crate.values().filter_map(|c| c.ref_spans.get(&id)).peekable();
+        refs.peek()?;
+        let mut seen = HashSet::new();
+        let refs: Vec<Span> = refs.flatten().filter(|span| seen.insert(*span)).cloned().collect();
+        f(&refs)
     }
 
     pub fn with_defs_per_file<F, T>(&self, file: &Path, f: F) -> Option<T>
@@ -411,4 +429,66 @@ impl Analysis {
     {
         self.for_all_crates(|c| c.def_names.get(name).map(&f))
     }
+
+    /// Returns the definitions in the file of `span` which start before it,
+    /// closest first. Since definition spans only cover the name of an item,
+    /// this is a superset of the definitions enclosing `span`.
+    pub fn defs_preceding(&self, span: &Span) -> Vec<(Id, Def)> {
+        let start = span_start(span);
+        let mut result = self.for_all_crates(|c| {
+            c.defs_per_file.get(&span.file).map(|ids| {
+                ids.iter()
+                    .filter_map(|id| c.defs.get(id).map(|def| (*id, def.clone())))
+                    .filter(|(_, def)| span_start(&def.span) <= start)
+                    .collect()
+            })
+        });
+        result.sort_by(|(_, a), (_, b)| span_start(&b.span).cmp(&span_start(&a.span)));
+        result.dedup_by_key(|(id, _)| *id);
+        result
+    }
+
+    /// Returns the spans of the names imported by the `use` declarations of
+    /// `file`, in source order.
+    pub fn imports(&self, file: &Path) -> Vec<Span> {
+        let mut result = self.for_all_crates(|c| c.imports.get(file).cloned());
+        result.sort_by_key(span_start);
+        result.dedup();
+        result
+    }
+
+    /// Returns the modules whose `use` declarations import the def `id` by its
+    /// own name, with the span of the imported name.
+    pub fn imported_by(&self, id: Id) -> Vec<(Id, Span)> {
+        let mut result = self.for_all_crates(|c| c.imported_by.get(&id).cloned());
+        result.dedup();
+        result
+    }
+
+    /// Returns the spans of all references and definitions within `span`,
+    /// together with the id of the definition, in source order.
+    pub fn refs_within(&self, span: &Span) -> Vec<(Span, Id)> {
+        let (start, end) = (span_start(span), span_end(span));
+        let mut result = self.for_all_crates(|c| {
+            Some(
+                c.def_id_for_span
+                    .iter()
+                    .filter(|(s, _)| s.file == span.file)
+                    .filter(|(s, _)| start <= span_start(s) && span_end(s) <= end)
+                    .map(|(s, r)| (s.clone(), r.some_id()))
+                    .collect(),
+            )
+        });
+        result.sort_by_key(|(s, _)| span_start(s));
+        result.dedup_by(|(a, _), (b, _)| a == b);
+        result
+    }
+}
+
+fn span_start(span: &Span) -> (u32, u32) {
+    (span.range.row_start.0, span.range.col_start.0)
+}
+
+fn span_end(span: &Span) -> (u32, u32) {
+    (span.range.row_end.0, span.range.col_end.0)
 }
@@ -19,11 +19,12 @@ mod test;
 mod util;
 
 use analysis::Analysis;
-pub use analysis::{Def, Ident, IdentKind, Ref};
+pub use analysis::{Def, Ident, IdentKind, Ref, SigElement, Signature};
 pub use loader::{AnalysisLoader, CargoAnalysisLoader, SearchDirectory, Target};
 pub use raw::{name_space_for_def_kind, read_analysis_from_files, Crate, CrateId, DefKind};
 pub use symbol_query::SymbolQuery;
 
+use itertools::Itertools;
 use std::collections::HashMap;
 use std::fmt::Debug;
 use std::path::{Path, PathBuf};
@@ -421,11 +422,83 @@ impl<L: AnalysisLoader> AnalysisHost<L> {
         self.with_analysis(|a| Some(a.for_all_crates(|c| c.impls.get(&id).cloned())))
     }
 
+    /// Returns the traits implemented by the type `id`, with the spans of the
+    /// impls.
+    pub fn implemented_traits(&self, id: Id) -> AResult<Vec<(Id, Span)>> {
+        self.with_analysis(|a| {
+            Some(
+                a.for_all_crates(|c| c.impl_traits.get(&id).cloned())
+                    .into_iter()
+                    .unique()
+                    .collect(),
+            )
+        })
+    }
+
+    /// Returns the types implementing the trait `id`, with the spans of the
+    /// impls.
+    pub fn implementors(&self, id: Id) -> AResult<Vec<(Id, Span)>> {
+        self.with_analysis(|a| {
+            Some(
+                a.for_all_crates(|c| c.trait_impls.get(&id).cloned())
+                    .into_iter()
+                    .unique()
+                    .collect(),
+            )
+        })
+    }
+
+    /// Returns the supertraits of the trait `id`.
+    pub fn super_traits(&self, id: Id) -> AResult<Vec<Id>> {
+        self.with_analysis(|a| {
+            Some(
+                a.for_all_crates(|c| c.super_traits.get(&id).cloned())
+                    .into_iter()
+                    .unique()
+                    .collect(),
+            )
+        })
+    }
+
+    /// Returns the traits which have the trait `id` as a supertrait.
+    pub fn sub_traits(&self, id: Id) -> AResult<Vec<Id>> {
+        self.with_analysis(|a| {
+            Some(
+                a.for_all_crates(|c| c.sub_traits.get(&id).cloned()).into_iter().unique().collect(),
+            )
+        })
+    }
+
     /// Search for a symbol name, returning a list of def_ids for that name.
     pub fn search_for_id(&self, name: &str) -> AResult<Vec<Id>> {
         self.with_analysis(|a| Some(a.with_def_names(name, Clone::clone)))
     }
 
+    /// Returns the definitions in the same file as `span` which start before
+    /// it, closest first.
+    pub fn defs_preceding(&self, span: &Span) -> AResult<Vec<(Id, Def)>> {
+        self.with_analysis(|a| Some(a.defs_preceding(span)))
+    }
+
+    /// Returns all references and definitions within `span`, together with
+    /// the id of the definition.
+    pub fn refs_within(&self, span: &Span) -> AResult<Vec<(Span, Id)>> {
+        self.with_analysis(|a| Some(a.refs_within(span)))
+    }
+
+    /// Returns the spans of the names imported by the `use` declarations of
+    /// `file`, in source order.
+    pub fn imports(&self, file: &Path) -> AResult<Vec<Span>> {
+        self.with_analysis(|a| Some(a.imports(file)))
+    }
+
+    /// Returns the modules whose `use` declarations import the def `id` by its
+    /// own name (i.e., without renaming it), with the span of the imported
+    /// name. Whether the declarations are `pub` is not recorded.
+    pub fn imported_by(&self, id: Id) -> AResult<Vec<(Id, Span)>> {
+        self.with_analysis(|a| Some(a.imported_by(id)))
+    }
+
     /// Returns all identifiers which overlap the given span.
     #[cfg(feature = "idents")]
     pub fn idents(&self, span: &Span) -> AResult<Vec<Ident>> {
@@ -1,7 +1,7 @@
 //! For processing the raw save-analysis data from rustc into the rls
 //! in-memory representation.
 
-use crate::analysis::{Def, Glob, PerCrateAnalysis, Ref};
+use crate::analysis::{Def, Glob, PerCrateAnalysis, Ref, SigElement, Signature};
 #[cfg(feature = "idents")]
 use crate::analysis::{IdentBound, IdentKind, IdentsByColumn, IdentsByLine};
 use crate::loader::AnalysisLoader;
@@ -109,6 +109,9 @@ struct CrateReader<'a> {
     /// not be taken into account when checking if we need to ignore duplicated
     /// item.
     invalidated_crates: &'a [CrateId],
+    /// Maps the crate-local ids of defs which are already defined by a
+    /// homonym crate to the ids of those defs.
+    congruent_defs: HashMap<u32, Id>,
 }
 
 impl<'a> CrateReader<'a> {
@@ -154,6 +157,7 @@ impl<'a> CrateReader<'a> {
             crate_name: crate_id.name,
             path_rewrite,
             invalidated_crates,
+            congruent_defs: HashMap::new(),
         }
     }
 
@@ -164,7 +168,7 @@ impl<'a> CrateReader<'a> {
         base_dir: &Path,
         invalidated_crates: &[CrateId],
     ) -> (PerCrateAnalysis, CrateId) {
-        let reader = CrateReader::from_prelude(
+        let mut reader = CrateReader::from_prelude(
             krate.analysis.prelude.unwrap(),
             &mut project_analysis.master_crate_map.lock().unwrap(),
             base_dir,
@@ -175,10 +179,11 @@ impl<'a> CrateReader<'a> {
         let mut per_crate = PerCrateAnalysis::new(krate.timestamp, krate.path);
 
         let is_distro_crate = krate.analysis.config.distro_crate;
-        reader.read_defs(krate.analysis.defs, &mut per_crate, is_distro_crate, project_analysis);
+        reader.congruent_defs = reader.find_congruent_defs(&krate.analysis.defs, project_analysis);
+        reader.read_defs(krate.analysis.defs, &mut per_crate, is_distro_crate);
         reader.read_imports(krate.analysis.imports, &mut per_crate, project_analysis);
         reader.read_refs(krate.analysis.refs, &mut per_crate, project_analysis);
-        reader.read_impls(krate.analysis.relations, &mut per_crate, project_analysis);
+        reader.read_relations(krate.analysis.relations, &mut per_crate, project_analysis);
         per_crate.global_crate_num = reader.crate_map[0];
 
         {
@@ -203,6 +208,9 @@ impl<'a> CrateReader<'a> {
     ) {
         for i in imports {
             let span = lower_span(&i.span, &self.base_dir, &self.path_rewrite);
+            if i.kind == raw::ImportKind::Use {
+                analysis.imports.entry(span.file.clone()).or_default().push(span.clone());
+            }
             if !i.value.is_empty() {
                 // A glob import.
                 if !self.has_congruent_glob(&span, project_analysis) {
@@ -213,6 +221,16 @@ impl<'a> CrateReader<'a> {
             } else if let Some(ref ref_id) = i.ref_id {
                 // Import where we know the referred def.
                 let def_id = self.id_from_compiler_id(*ref_id);
+                if i.kind == raw::ImportKind::Use && i.alias_span.is_none() && def_id != NULL {
+                    if let Some(parent) = i.parent {
+                        let module = self.id_from_compiler_id(parent);
+                        analysis
+                            .imported_by
+                            .entry(def_id)
+                            .or_default()
+                            .push((module, span.clone()));
+                    }
+                }
                 self.record_ref(def_id, span, analysis, project_analysis);
                 if let Some(alias_span) = i.alias_span {
                     let alias_span = lower_span(&alias_span, &self.base_dir, &self.path_rewrite);
@@ -269,20 +287,49 @@ impl<'a> CrateReader<'a> {
     }
 
     // We are sometimes asked to analyze the same crate twice. This can happen due to duplicate data,
-    // but more frequently is due to compiling it twice with different Cargo targets (e.g., bin and test).
-    // In that case there will be two crates with the same names, but different disambiguators. We
-    // want to ensure that we only record defs once, even if the defintion is in multiple crates.
-    // So we compare the crate-local id and span and skip any subsequent defs which match already
-    // present defs.
-    fn has_congruent_def<L: AnalysisLoader>(
+    // but more frequently is due to compiling it twice with different Cargo targets (e.g., bin and
+    // test) or configurations (with and without `cfg(test)`). In that case there will be two crates
+    // with the same names, but different disambiguators. We want to ensure that we only record defs
+    // once, even if the defintion is in multiple crates. The crate-local ids of the same def differ
+    // between configurations, so we match defs by span, kind and name, and map any subsequent defs
+    // to the already present ones. This also merges the refs of both crates under a single def.
+    fn find_congruent_defs<L: AnalysisLoader>(
         &self,
-        local_id: u32,
-        span: &Span,
+        defs: &[raw::Def],
         project_analysis: &AnalysisHost<L>,
-    ) -> bool {
-        self.has_congruent_item(project_analysis, |per_crate| {
-            per_crate.has_congruent_def(local_id, span)
-        })
+    ) -> HashMap<u32, Id> {
+        if self.crate_homonyms.is_empty() {
+            return HashMap::new();
+        }
+
+        let project_analysis = project_analysis.analysis.lock().unwrap();
+        let project_analysis = project_analysis.as_ref().unwrap();
+
+        let mut defs_by_span = HashMap::<&Span, Vec<(Id, &Def)>>::new();
+        let homonyms = self
+            .crate_homonyms
+            .iter()
+            .filter(|c| !self.invalidated_crates.contains(c))
+            .filter_map(|c| project_analysis.per_crate.get(c));
+        for per_crate in homonyms {
+            for (id, def) in &per_crate.defs {
+                defs_by_span.entry(&def.span).or_default().push((*id, def));
+            }
+        }
+        if defs_by_span.is_empty() {
+            return HashMap::new();
+        }
+
+        defs.iter()
+            .filter_map(|d| {
+                let span = lower_span(&d.span, &self.base_dir, &self.path_rewrite);
+                let (id, _) = defs_by_span
+                    .get(&span)?
+                    .iter()
+                    .find(|(_, def)| def.kind == d.kind && def.name == d.name)?;
+                Some((d.id.index, *id))
+            })
+            .collect()
     }
 
     fn has_congruent_glob<L: AnalysisLoader>(
@@ -315,23 +362,17 @@ impl<'a> CrateReader<'a> {
         homonyms_to_consider.filter_map(|ch| project_analysis.per_crate.get(ch)).any(pred)
     }
 
-    fn read_defs<L: AnalysisLoader>(
-        &self,
-        defs: Vec<raw::Def>,
-        analysis: &mut PerCrateAnalysis,
-        distro_crate: bool,
-        project_analysis: &AnalysisHost<L>,
-    ) {
+    fn read_defs(&self, defs: Vec<raw::Def>, analysis: &mut PerCrateAnalysis, distro_crate: bool) {
         let mut defs_to_index = Vec::new();
         for d in defs {
             if bad_span(&d.span, d.kind == DefKind::Mod) {
                 continue;
             }
-            let span = lower_span(&d.span, &self.base_dir, &self.path_rewrite);
-            if self.has_congruent_def(d.id.index, &span, project_analysis) {
-                trace!("read_defs: has_congruent_def({}, {:?}), skipping", d.id.index, span);
+            if let Some(id) = self.congruent_defs.get(&d.id.index) {
+                trace!("read_defs: {} is congruent to {}, skipping", d.id.index, id);
                 continue;
             }
+            let span = lower_span(&d.span, &self.base_dir, &self.path_rewrite);
 
             let id = self.id_from_compiler_id(d.id);
             if id != NULL && !analysis.defs.contains_key(&id) {
@@ -391,7 +432,7 @@ impl<'a> CrateReader<'a> {
                     distro_crate,
                     parent,
                     docs: d.docs,
-                    // sig: d.sig.map(|ref s| self.lower_sig(s, &self.base_dir)),
+                    sig: d.sig.map(|ref s| self.lower_sig(s)),
                 };
                 trace!(
                     "record def: {:?}/{:?} ({}): {:?}",
@@ -441,53 +482,74 @@ impl<'a> CrateReader<'a> {
         }
     }
 
-    fn read_impls<L: AnalysisLoader>(
+    fn read_relations<L: AnalysisLoader>(
         &self,
         relations: Vec<raw::Relation>,
         analysis: &mut PerCrateAnalysis,
         project_analysis: &AnalysisHost<L>,
     ) {
         for r in relations {
-            match r.kind {
-                RelationKind::Impl { .. } => {}
-                _ => continue,
-            }
-            let self_id = self.id_from_compiler_id(r.from);
-            let trait_id = self.id_from_compiler_id(r.to);
+            let from = self.id_from_compiler_id(r.from);
+            let to = self.id_from_compiler_id(r.to);
             let span = lower_span(&r.span, &self.base_dir, &self.path_rewrite);
-            if self_id != NULL {
-                if let Some(self_id) = abs_ref_id(self_id, analysis, project_analysis) {
-                    trace!("record impl for self type {:?} {}", span, self_id);
-                    analysis.impls.entry(self_id).or_insert_with(Vec::new).push(span.clone());
+            let resolve = |id: Id, analysis: &PerCrateAnalysis| {
+                if id == NULL {
+                    None
+                } else {
+                    abs_ref_id(id, analysis, project_analysis)
                 }
-            }
-            if trait_id != NULL {
-                if let Some(trait_id) = abs_ref_id(trait_id, analysis, project_analysis) {
-                    trace!("record impl for trait {:?} {}", span, trait_id);
-                    analysis.impls.entry(trait_id).or_insert_with(Vec::new).push(span);
+            };
+
+            match r.kind {
+                RelationKind::Impl { .. } => {
+                    let self_id = resolve(from, analysis);
+                    let trait_id = resolve(to, analysis);
+                    if let Some(self_id) = self_id {
+                        trace!("record impl for self type {:?} {}", span, self_id);
+                        analysis.impls.entry(self_id).or_insert_with(Vec::new).push(span.clone());
+                    }
+                    if let Some(trait_id) = trait_id {
+                        trace!("record impl for trait {:?} {}", span, trait_id);
+                        analysis.impls.entry(trait_id).or_insert_with(Vec::new).push(span.clone());
+                    }
+                    if let (Some(self_id), Some(trait_id)) = (self_id, trait_id) {
+                        analysis
+                            .impl_traits
+                            .entry(self_id)
+                            .or_insert_with(Vec::new)
+                            .push((trait_id, span.clone()));
+                        analysis
+                            .trait_impls
+                            .entry(trait_id)
+                            .or_insert_with(Vec::new)
+                            .push((self_id, span));
+                    }
+                }
+                // The relation is from the supertrait to the trait declaring it.
+                RelationKind::SuperTrait => {
+                    if let (Some(super_id), Some(sub_id)) =
+                        (resolve(from, analysis), resolve(to, analysis))
+                    {
+                        trace!("record supertrait {} of {}", super_id, sub_id);
+                        analysis.super_traits.entry(sub_id).or_insert_with(Vec::new).push(super_id);
+                        analysis.sub_traits.entry(super_id).or_insert_with(Vec::new).push(sub_id);
+                    }
                 }
             }
         }
     }
 
-    // fn lower_sig(&self, raw_sig: &raw::Signature, base_dir: &Path) -> Signature {
-    //     Signature {
-    //         span: lower_span(&raw_sig.span, base_dir, &self.path_rewrite),
-    //         text: raw_sig.text.clone(),
-    //         ident_start: raw_sig.ident_start as u32,
-    //         ident_end: raw_sig.ident_end as u32,
-    //         defs: raw_sig.defs.iter().map(|se| self.lower_sig_element(se)).collect(),
-    //         refs: raw_sig.refs.iter().map(|se| self.lower_sig_element(se)).collect(),
-    //     }
-    // }
-
-    // fn lower_sig_element(&self, raw_se: &raw::SigElement) -> SigElement {
-    //     SigElement {
-    //         id: self.id_from_compiler_id(raw_se.id),
-    //         start: raw_se.start,
-    //         end: raw_se.end,
-    //     }
-    // }
+    fn lower_sig(&self, raw_sig: &raw::Signature) -> Signature {
+        Signature {
+            text: raw_sig.text.clone(),
+            defs: raw_sig.defs.iter().map(|se| self.lower_sig_element(se)).collect(),
+            refs: raw_sig.refs.iter().map(|se| self.lower_sig_element(se)).collect(),
+        }
+    }
+
+    fn lower_sig_element(&self, raw_se: &raw::SigElement) -> SigElement {
+        SigElement { id: self.id_from_compiler_id(raw_se.id), start: raw_se.start, end: raw_se.end }
+    }
 
     /// Recreates resulting crate-local (`u32`, `u32`) id from compiler
     /// to a global `u64` `Id`, mapping from a local to global crate id.
@@ -495,6 +557,12 @@ impl<'a> CrateReader<'a> {
         if id.krate == u32::MAX || id.index == u32::MAX {
             return NULL;
         }
+        // Defs of this crate may have been merged into ones of a homonym crate.
+        if id.krate == 0 {
+            if let Some(id) = self.congruent_defs.get(&id.index) {
+                return *id;
+            }
+        }
 
         let krate = self.crate_map[id.krate as usize];
         Id::from_crate_and_local(krate, id.index)
@@ -2,8 +2,8 @@ use fst::{self, Streamer};
 
 /// `SymbolQuery` specifies the preficate for filtering symbols by name.
 ///
-/// All matching is case-insensitive. Filtering by prefix or by subsequence
-/// is supported, subsequence being a good default choice.
+/// All matching is case-insensitive. Filtering by exact name, by prefix or by
+/// subsequence is supported, subsequence being a good default choice.
 ///
 /// As the number of results might be huge, consider the `limit` hint,
 /// which serves as *approximate* limit on the number of results returned.
@@ -20,6 +20,7 @@ pub struct SymbolQuery {
 
 #[derive(Debug, Clone, Copy)]
 enum Mode {
+    Exact,
     Prefix,
     Subsequence,
 }
@@ -29,6 +30,10 @@ impl SymbolQuery {
         SymbolQuery { query_string, mode, limit: usize::max_value(), greater_than: String::new() }
     }
 
+    pub fn exact(query_string: &str) -> SymbolQuery {
+        SymbolQuery::new(query_string.to_lowercase(), Mode::Exact)
+    }
+
     pub fn subsequence(query_string: &str) -> SymbolQuery {
         SymbolQuery::new(query_string.to_lowercase(), Mode::Subsequence)
     }
@@ -107,13 +112,16 @@ impl<'a> fst::Automaton for QueryAutomaton<'a> {
             return state;
         }
         if state == self.query.len() {
-            return state;
+            return match self.mode {
+                Mode::Exact => NO_MATCH,
+                Mode::Prefix | Mode::Subsequence => state,
+            };
         }
         if byte == self.query.as_bytes()[state] {
             return state + 1;
         }
         match self.mode {
-            Mode::Prefix => NO_MATCH,
+            Mode::Exact | Mode::Prefix => NO_MATCH,
             Mode::Subsequence => state,
         }
     }
@@ -123,7 +131,10 @@ impl<'a> fst::Automaton for QueryAutomaton<'a> {
     }
 
     fn will_always_match(&self, &state: &usize) -> bool {
-        state == self.query.len()
+        match self.mode {
+            Mode::Exact => false,
+            Mode::Prefix | Mode::Subsequence => state == self.query.len(),
+        }
     }
 }
 
@@ -147,6 +158,10 @@ mod tests {
 
     #[test]
     fn test_automaton() {
+        check(SymbolQuery::exact("vega"), &["vega"]);
+        check(SymbolQuery::exact("veg"), &[]);
+        check(SymbolQuery::exact("Spica"), &["spica"]);
+
         check(SymbolQuery::prefix("an"), &["anektor", "antares"]);
 
         check(
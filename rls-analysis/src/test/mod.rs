@@ -1,6 +1,6 @@
 use crate::loader::SearchDirectory;
 use crate::raw::DefKind;
-use crate::{AnalysisHost, AnalysisLoader};
+use crate::{AnalysisHost, AnalysisLoader, Id, Span};
 
 use std::collections::HashSet;
 use std::path::{Path, PathBuf};
@@ -243,6 +243,179 @@ fn test_hello() {
     assert_eq!(all_matches, expected_matches);
 }
 
+#[test]
+fn test_containment_queries() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/hello/save-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/hello"), Path::new("test_data/hello")).unwrap();
+
+    let id = host.search_for_id("print_hello").unwrap()[0];
+    let call = host.find_all_refs_by_id(id).unwrap()[1].clone();
+    assert_eq!(call.range.row_start.0, 6);
+
+    let names = host
+        .defs_preceding(&call)
+        .unwrap()
+        .into_iter()
+        .map(|(_, def)| def.name)
+        .collect::<Vec<_>>();
+    assert_eq!(&names[..2], &["main", "name"]);
+    assert!(names.contains(&"print_hello".to_owned()));
+
+    let main = host.get_def(host.search_for_id("main").unwrap()[0]).unwrap();
+    let body = Span::from_positions(main.span.range.start(), call.range.end(), call.file.clone());
+    let refs = host.refs_within(&body).unwrap();
+    assert_eq!(refs[0].0, main.span);
+    assert_eq!(refs.last(), Some(&(call, id)));
+}
+
+#[test]
+fn test_trait_relations() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/rust-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/rust-analysis"), Path::new("test_data/rust-analysis"))
+        .unwrap();
+
+    let find = |name: &str, kind: DefKind| {
+        host.search_for_id(name)
+            .unwrap()
+            .into_iter()
+            .find(|id| host.get_def(*id).map(|def| def.kind == kind).unwrap_or(false))
+            .unwrap()
+    };
+    let names = |ids: Vec<Id>| {
+        ids.into_iter().map(|id| host.get_def(id).unwrap().name).collect::<HashSet<_>>()
+    };
+
+    // `trait DecodableFloat: RawFloat + Copy`
+    let decodable_float = find("DecodableFloat", DefKind::Trait);
+    let super_traits = names(host.super_traits(decodable_float).unwrap());
+    assert!(super_traits.contains("RawFloat"));
+    assert!(super_traits.contains("Copy"));
+    let raw_float = find("RawFloat", DefKind::Trait);
+    assert!(host.sub_traits(raw_float).unwrap().contains(&decodable_float));
+
+    // `impl FusedIterator for Chars<'_>`
+    let chars = find("Chars", DefKind::Struct);
+    let fused_iterator = find("FusedIterator", DefKind::Trait);
+    let traits = host.implemented_traits(chars).unwrap();
+    assert!(traits.iter().any(|(id, _)| *id == fused_iterator));
+    let implementors = host.implementors(fused_iterator).unwrap();
+    let (_, span) = implementors.iter().find(|(id, _)| *id == chars).unwrap();
+    assert!(span.file.ends_with("str/mod.rs"));
+}
+
+#[test]
+fn test_imported_by() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/rust-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/rust-analysis"), Path::new("test_data/rust-analysis"))
+        .unwrap();
+
+    // `pub use self::hash_map::HashMap;` in `std::collections`.
+    let hash_map = host
+        .search_for_id("HashMap")
+        .unwrap()
+        .into_iter()
+        .find(|id| host.get_def(*id).map(|def| def.kind == DefKind::Struct).unwrap_or(false))
+        .unwrap();
+    assert_eq!(host.get_def(hash_map).unwrap().qualname, "std::collections::hash::map::HashMap");
+    let imported_by = host.imported_by(hash_map).unwrap();
+    let (_, span) = imported_by
+        .iter()
+        .find(|(id, _)| host.get_def(*id).unwrap().qualname == "std::collections")
+        .unwrap();
+    assert!(span.file.ends_with("collections/mod.rs"));
+}
+
+#[test]
+fn test_homonym_crates() {
+    use data::config::Config;
+    use data::{Analysis, CratePreludeData, Def, GlobalCrateId, Ref, RefKind, SpanData};
+    use span::{Column, Row};
+
+    fn span_data(line: u32) -> SpanData {
+        SpanData {
+            file_name: PathBuf::from("src/lib.rs"),
+            byte_start: line * 10,
+            byte_end: line * 10 + 3,
+            line_start: Row::new_one_indexed(line),
+            line_end: Row::new_one_indexed(line),
+            column_start: Column::new_one_indexed(4),
+            column_end: Column::new_one_indexed(7),
+        }
+    }
+    fn def(index: u32, name: &str, line: u32) -> Def {
+        Def {
+            kind: data::DefKind::Function,
+            id: data::Id { krate: 0, index },
+            span: span_data(line),
+            name: name.to_owned(),
+            qualname: format!("::{}", name),
+            value: String::new(),
+            parent: None,
+            children: vec![],
+            decl_id: None,
+            docs: String::new(),
+            sig: None,
+            attributes: vec![],
+        }
+    }
+    // The same crate compiled twice, e.g. as a lib and with `cfg(test)`.
+    fn analysis(disambiguator: u64, foo: u32, defs: Vec<Def>, ref_lines: &[u32]) -> Analysis {
+        let mut analysis = Analysis::new(Config::default());
+        analysis.prelude = Some(CratePreludeData {
+            crate_id: GlobalCrateId { name: "foo".to_owned(), disambiguator: (disambiguator, 0) },
+            crate_root: "src".to_owned(),
+            external_crates: vec![],
+            span: span_data(1),
+        });
+        analysis.defs = defs;
+        analysis.refs = ref_lines
+            .iter()
+            .map(|line| Ref {
+                kind: RefKind::Function,
+                span: span_data(*line),
+                ref_id: data::Id { krate: 0, index: foo },
+            })
+            .collect();
+        analysis
+    }
+
+    let base_dir = Path::new("test_data/homonyms");
+    let lib = analysis(1, 1, vec![def(1, "foo", 2)], &[4]);
+    let test = analysis(2, 7, vec![def(3, "bar", 8), def(7, "foo", 2)], &[4, 6]);
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(base_dir.to_owned()));
+    host.reload_from_analysis(vec![lib, test], base_dir, base_dir, &[] as &[&str]).unwrap();
+
+    // `foo` is only recorded once, while `bar` is still lowered.
+    let foo = host.search_for_id("foo").unwrap();
+    assert_eq!(foo.len(), 1);
+    assert_eq!(host.search_for_id("bar").unwrap().len(), 1);
+
+    // The ref from the second crate points at the def from the first one.
+    let file = base_dir.join("src/lib.rs");
+    let ref_span = Span::new(
+        Row::new_zero_indexed(5),
+        Row::new_zero_indexed(5),
+        Column::new_zero_indexed(3),
+        Column::new_zero_indexed(6),
+        file.clone(),
+    );
+    assert_eq!(host.id(&ref_span).unwrap(), foo[0]);
+
+    // Refs are merged from both crates, without duplicates.
+    let refs = host.find_all_refs_by_id(foo[0]).unwrap();
+    assert!(refs.iter().all(|span| span.file == file));
+    assert_eq!(refs[0].range.row_start.0, 1);
+    let mut rows: Vec<_> = refs[1..].iter().map(|span| span.range.row_start.0).collect();
+    rows.sort();
+    assert_eq!(rows, vec![3, 5]);
+}
+
 // TODO
 // check span functions
 // check complex programs
@@ -0,0 +1,479 @@
+//! Auto-import: `use` declarations for names which rustc could not resolve.
+//!
+//! Candidates are found among the definitions of all loaded crates, and are
+//! ranked by how close their crate is to the current one: the crate itself,
+//! then its dependencies, then the standard library. Items of other crates are
+//! imported by their public paths: through public modules, or through the
+//! modules re-exporting them with `pub use`.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use log::trace;
+use rls_analysis::{Def, DefKind, Id, SymbolQuery};
+use rls_vfs::FileContents;
+
+use crate::actions::diagnostics::Diagnostic;
+use crate::actions::lexical::{
+    is_ident_char, sort_deglob_str, top_level_uses, Lexed, OffsetRange, SourceFiles,
+};
+use crate::actions::InitActionContext;
+use crate::build::Edition;
+use crate::lsp_data::*;
+use crate::Span;
+
+/// The codes of rustc's errors for unresolved names.
+const UNRESOLVED_NAME_CODES: &[&str] = &["E0412", "E0422", "E0425", "E0433", "E0531"];
+
+/// The maximum number of imports offered for a name.
+const MAX_CANDIDATES: usize = 5;
+
+/// The maximum number of modules (parents or re-exporting modules) followed
+/// to find the public paths of an item.
+const MAX_PATH_DEPTH: usize = 8;
+
+/// `true` if the diagnostic reports a name which could not be resolved.
+pub fn is_unresolved_name(diagnostic: &Diagnostic) -> bool {
+    match diagnostic.code {
+        Some(NumberOrString::String(ref code)) => UNRESOLVED_NAME_CODES.contains(&&**code),
+        _ => false,
+    }
+}
+
+/// Computes the paths which the unresolved name of `diagnostic` could be
+/// imported from, best first, with the edit adding the `use` declaration for
+/// each.
+pub fn import_candidates(
+    ctx: &InitActionContext,
+    file_path: &Path,
+    diagnostic: &Diagnostic,
+) -> Vec<(String, TextEdit)> {
+    let name = match unresolved_name(&diagnostic.message) {
+        Some(name) => name,
+        None => return vec![],
+    };
+    // The query is case-insensitive.
+    let defs = ctx.analysis.query_defs(SymbolQuery::exact(&name)).unwrap_or_else(|_| vec![]);
+    let crates = CratesInScope::new(ctx, file_path);
+    let mut sources = SourceFiles::new(&ctx.vfs);
+
+    let mut paths: Vec<(u32, String)> = defs
+        .iter()
+        .filter(|def| def.name == name && is_importable(def))
+        .flat_map(|def| crates.paths(ctx, &mut sources, def))
+        .collect();
+    paths.sort_by(|(rank_a, a), (rank_b, b)| {
+        let segments = |path: &str| path.matches("::").count();
+        (rank_a, segments(a), a).cmp(&(rank_b, segments(b), b))
+    });
+    paths.dedup_by(|(_, a), (_, b)| a == b);
+    trace!("import_candidates for `{}`: {:?}", name, paths);
+
+    let text = match ctx.vfs.load_file(file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return vec![],
+    };
+    let lexed = Lexed::new(&text);
+    let chars: Vec<char> = text.chars().collect();
+    let position = |offset| {
+        let (row, col) = lexed.position(offset);
+        Position::new(row as u64, col as u64)
+    };
+    paths
+        .into_iter()
+        .take(MAX_CANDIDATES)
+        .filter_map(|(_, path)| {
+            let ((start, end), new_text) = use_edit(&chars, &lexed, &path)?;
+            let range = Range::new(position(start), position(end));
+            Some((path, TextEdit { range, new_text }))
+        })
+        .collect()
+}
+
+/// The name in a message such as "cannot find type `HashMap` in this scope"
+/// or "failed to resolve: use of undeclared type or module `HashMap`".
+fn unresolved_name(message: &str) -> Option<String> {
+    let first_line = message.lines().next()?;
+    let name = first_line.split('`').nth(1)?;
+    if !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        Some(name.to_owned())
+    } else {
+        None
+    }
+}
+
+fn is_importable(def: &Def) -> bool {
+    match def.kind {
+        DefKind::Mod
+        | DefKind::Struct
+        | DefKind::Tuple
+        | DefKind::Union
+        | DefKind::Enum
+        | DefKind::Trait
+        | DefKind::Type
+        | DefKind::Function
+        | DefKind::Const
+        | DefKind::Static
+        | DefKind::Macro => {}
+        _ => return false,
+    }
+    // Items in function bodies or impls can't be imported.
+    !def.qualname.contains('$') && !def.qualname.contains('{') && !def.qualname.contains('<')
+}
+
+/// The crates whose items can be imported into a file.
+struct CratesInScope {
+    /// The names of the crates the file belongs to.
+    local: HashSet<String>,
+    /// The dependencies of the file's package, by the name of their crate
+    /// in the analysis data, with the name they are imported by.
+    deps: HashMap<String, String>,
+    edition: Option<Edition>,
+}
+
+impl CratesInScope {
+    fn new(ctx: &InitActionContext, file_path: &Path) -> CratesInScope {
+        let local = ctx
+            .file_to_crates
+            .lock()
+            .unwrap()
+            .get(file_path)
+            .map(|crates| crates.iter().map(|c| c.name.clone()).collect())
+            .unwrap_or_default();
+
+        let mut deps = HashMap::new();
+        if let Ok(project) = ctx.project_model() {
+            let package = file_path
+                .ancestors()
+                .find_map(|dir| project.package_for_manifest(&dir.join("Cargo.toml")));
+            if let Some(package) = package {
+                for dep in package.deps(&project) {
+                    let lib_name = dep.pkg.lib_name(&project).unwrap_or(&dep.crate_name);
+                    deps.insert(lib_name.to_owned(), dep.crate_name.clone());
+                }
+            }
+        }
+
+        CratesInScope { local, deps, edition: ctx.file_edition(file_path.to_owned()) }
+    }
+
+    /// The paths to import the item by, with their rank (lower is better).
+    /// There are none if its crate is not in scope, or if it is not public.
+    fn paths(
+        &self,
+        ctx: &InitActionContext,
+        sources: &mut SourceFiles,
+        def: &Def,
+    ) -> Vec<(u32, String)> {
+        let mut segments = def.qualname.splitn(2, "::");
+        let (krate, rest) = match (segments.next(), segments.next()) {
+            (Some(krate), Some(rest)) => (krate, rest),
+            _ => return vec![],
+        };
+        if self.local.contains(krate) {
+            let path = match self.edition {
+                Some(Edition::Edition2015) => rest.to_owned(),
+                _ => format!("crate::{}", rest),
+            };
+            return vec![(0, path)];
+        }
+
+        let id = match ctx.analysis.id(&def.span) {
+            Ok(id) => id,
+            Err(_) => return vec![],
+        };
+        // The item may be re-exported by another crate, e.g., from `core` by
+        // `std`.
+        public_paths(ctx, sources, id, def, 0)
+            .into_iter()
+            .filter_map(|path| {
+                let mut segments = path.splitn(2, "::");
+                let (krate, rest) = (segments.next()?, segments.next()?);
+                let (rank, name) = if let Some(name) = self.deps.get(krate) {
+                    (1, name.as_str())
+                } else if def.distro_crate {
+                    match krate {
+                        // `std` re-exports the public modules of `core` and
+                        // `alloc`.
+                        "std" | "core" | "alloc" => (2, "std"),
+                        _ => (3, krate),
+                    }
+                } else {
+                    return None;
+                };
+                Some((rank, format!("{}::{}", name, rest)))
+            })
+            .collect()
+    }
+}
+
+/// The paths by which the item `id` can be named from other crates, starting
+/// with the name of its crate: through its parent module if that is public,
+/// and through the modules re-exporting the item with `pub use`.
+fn public_paths(
+    ctx: &InitActionContext,
+    sources: &mut SourceFiles,
+    id: Id,
+    def: &Def,
+    depth: usize,
+) -> Vec<String> {
+    if depth > MAX_PATH_DEPTH {
+        return vec![];
+    }
+    let mut modules: Vec<Id> = def.parent.into_iter().collect();
+    for (module, span) in ctx.analysis.imported_by(id).unwrap_or_else(|_| vec![]) {
+        if is_pub_use(sources, &span) {
+            modules.push(module);
+        }
+    }
+
+    let mut paths = vec![];
+    for module in modules {
+        let module_def = match ctx.analysis.get_def(module) {
+            Ok(def) if def.kind == DefKind::Mod => def,
+            _ => continue,
+        };
+        if module_def.parent.is_none() {
+            // The root module of a crate, its qualname is `crate_name::`.
+            paths.push(format!("{}{}", module_def.qualname, def.name));
+        } else if is_pub_mod(ctx, sources, &module_def) {
+            paths.extend(
+                public_paths(ctx, sources, module, &module_def, depth + 1)
+                    .into_iter()
+                    .map(|path| format!("{}::{}", path, def.name)),
+            );
+        }
+    }
+    paths
+}
+
+/// `true` if the module is declared `pub`. A module whose declaration can't
+/// be found (e.g., without the source of the standard library) is assumed to
+/// be public.
+fn is_pub_mod(ctx: &InitActionContext, sources: &mut SourceFiles, def: &Def) -> bool {
+    // An inline module is defined by its name in `mod foo { .. }`, but the
+    // span of a module in its own file is in that file, and it is declared
+    // by `mod foo;` in its parent's file.
+    if let Some(offset) = sources.offset(&def.span) {
+        let lexed = sources.lexed(&def.span.file).unwrap();
+        if let Some(keyword) = keyword_before(&lexed.code, offset, "mod") {
+            return is_pub(&lexed.code, keyword);
+        }
+    }
+    let parent = match def.parent.and_then(|id| ctx.analysis.get_def(id).ok()) {
+        Some(parent) => parent,
+        None => return true,
+    };
+    let code = match sources.lexed(&parent.span.file) {
+        Some(lexed) => &lexed.code,
+        None => return true,
+    };
+    let name: Vec<char> = def.name.chars().collect();
+    let declaration = (0..code.len()).find(|&i| {
+        code[i..].starts_with(&name)
+            && (i == 0 || !is_ident_char(code[i - 1]))
+            && code[i + name.len()..].iter().find(|c| !c.is_whitespace()) == Some(&';')
+            && keyword_before(code, i, "mod").is_some()
+    });
+    match declaration {
+        Some(i) => is_pub(code, keyword_before(code, i, "mod").unwrap()),
+        None => true,
+    }
+}
+
+/// `true` if the name imported at `span` is imported by a `pub use`
+/// declaration.
+fn is_pub_use(sources: &mut SourceFiles, span: &Span) -> bool {
+    let offset = match sources.offset(span) {
+        Some(offset) => offset,
+        None => return false,
+    };
+    let code = &sources.lexed(&span.file).unwrap().code;
+    match use_keyword(code, offset) {
+        Some(keyword) => is_pub(code, keyword),
+        None => false,
+    }
+}
+
+/// The offset of the `use` keyword of the declaration importing the name at
+/// `offset`, skipping back over its use tree.
+fn use_keyword(code: &[char], mut offset: usize) -> Option<usize> {
+    loop {
+        if let Some(keyword) = keyword_before(code, offset, "use") {
+            return Some(keyword);
+        }
+        let c = code[..offset].last()?;
+        if !(is_ident_char(*c) || c.is_whitespace() || ":{},".contains(*c)) {
+            return None;
+        }
+        offset -= 1;
+    }
+}
+
+/// The offset of `keyword` if it is the word just before `offset`.
+fn keyword_before(code: &[char], offset: usize, keyword: &str) -> Option<usize> {
+    let end = code[..offset].iter().rposition(|c| !c.is_whitespace())? + 1;
+    let start = end.checked_sub(keyword.len())?;
+    let is_keyword = code[start..end].iter().copied().eq(keyword.chars())
+        && (start == 0 || !is_ident_char(code[start - 1]))
+        && end < offset;
+    if is_keyword {
+        Some(start)
+    } else {
+        None
+    }
+}
+
+/// `true` if the item whose keyword starts at `offset` is declared `pub`
+/// (but not, e.g., `pub(crate)`).
+fn is_pub(code: &[char], offset: usize) -> bool {
+    keyword_before(code, offset, "pub").is_some()
+}
+
+/// Computes the edit which imports `path`: adding the name to a `use`
+/// declaration of the same module if there is one, or adding a new `use`
+/// declaration among the existing ones otherwise. Returns `None` if the path
+/// is already imported.
+fn use_edit(chars: &[char], lexed: &Lexed, path: &str) -> Option<(OffsetRange, String)> {
+    let (module, name) = match path.rfind("::") {
+        Some(i) => (&path[..i], &path[i + 2..]),
+        None => ("", path),
+    };
+    let uses = top_level_uses(chars, lexed);
+    let imported_path = |text: &str| text["use ".len()..].trim_end_matches(';').to_owned();
+
+    for (range, text) in &uses {
+        let imported = imported_path(text);
+        if imported == path {
+            return None;
+        }
+        if module.is_empty() || !imported.starts_with(module) {
+            continue;
+        }
+        let rest = &imported[module.len()..];
+        if !rest.starts_with("::") {
+            continue;
+        }
+        let rest = &rest[2..];
+        let names = if rest.starts_with('{') && rest.ends_with('}') {
+            let inner = &rest[1..rest.len() - 1];
+            if inner.contains('{') {
+                continue;
+            }
+            inner.to_owned()
+        } else if rest.chars().all(|c| c.is_alphanumeric() || c == '_') && rest != "self" {
+            rest.to_owned()
+        } else {
+            continue;
+        };
+        if names.split(',').any(|n| n.trim() == name) {
+            return None;
+        }
+        let names = format!("{}, {}", names.trim().trim_end_matches(','), name);
+        return Some((*range, format!("use {}::{{{}}};", module, sort_deglob_str(&names))));
+    }
+
+    let new_use = format!("use {};\n", path);
+    let line_start = |offset| lexed.offset(lexed.position(offset).0, 0);
+    let mut plain_uses = uses.iter().filter(|(_, text)| !text.contains(" as "));
+    if let Some(((start, _), _)) = plain_uses.find(|(_, text)| imported_path(text).as_str() > path)
+    {
+        return Some(((line_start(*start), line_start(*start)), new_use));
+    }
+    if let Some(((_, end), _)) = uses.last() {
+        let next_line = lexed.offset(lexed.position(*end).0 + 1, 0);
+        if next_line == *end {
+            // The last line of the file, without a newline.
+            return Some(((*end, *end), format!("\n{}", new_use.trim_end())));
+        }
+        return Some(((next_line, next_line), new_use));
+    }
+
+    // Without any `use` declarations, add one after the inner attributes and
+    // doc comments, and any `extern crate` declarations.
+    let mut row = 0;
+    while row < lexed.line_count() {
+        let start = lexed.offset(row, 0);
+        let line: String = chars[start..start + lexed.line(row).chars().count()].iter().collect();
+        let line = line.trim();
+        let is_header = line.is_empty()
+            || line.starts_with("//!")
+            || line.starts_with("#![")
+            || line.starts_with("extern crate ");
+        if !is_header {
+            break;
+        }
+        row += 1;
+    }
+    let offset = lexed.offset(row, 0);
+    Some(((offset, offset), format!("{}\n", new_use)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn import(text: &str, path: &str) -> Option<String> {
+        let chars: Vec<char> = text.chars().collect();
+        let ((start, end), new_text) = use_edit(&chars, &Lexed::new(text), path)?;
+        let mut text = text.to_owned();
+        text.replace_range(start..end, &new_text);
+        Some(text)
+    }
+
+    #[test]
+    fn test_unresolved_name() {
+        let message = "cannot find type `HashSet` in this scope\n\nnot found in this scope";
+        assert_eq!(unresolved_name(message), Some("HashSet".to_owned()));
+        let message = "failed to resolve: use of undeclared type or module `fs`";
+        assert_eq!(unresolved_name(message), Some("fs".to_owned()));
+        assert_eq!(unresolved_name("mismatched types"), None);
+    }
+
+    #[test]
+    fn test_visibility() {
+        let text = "pub mod a;\nmod b;\npub(crate) mod c;\npub use x::{y::{self, z}, w};\nuse v;";
+        let code: Vec<char> = text.chars().collect();
+        let at = |s: &str| text.find(s).unwrap();
+        assert!(is_pub(&code, at("mod a")));
+        assert!(!is_pub(&code, at("mod b")));
+        assert!(!is_pub(&code, at("mod c")));
+        assert_eq!(keyword_before(&code, at("a;"), "mod"), Some(at("mod a")));
+        assert_eq!(keyword_before(&code, at("a;"), "pub"), None);
+
+        assert_eq!(use_keyword(&code, at("w}")), Some(at("use x")));
+        assert_eq!(use_keyword(&code, at("z}")), Some(at("use x")));
+        assert!(is_pub(&code, at("use x")));
+        assert_eq!(use_keyword(&code, at("v;")), Some(at("use v")));
+        assert!(!is_pub(&code, at("use v")));
+        assert_eq!(use_keyword(&code, at("a;")), None);
+    }
+
+    #[test]
+    fn test_use_edit() {
+        let text = "use std::fs;\nuse std::io::{Write, Read};\nuse url::Url;\n\nfn main() {}\n";
+        assert_eq!(
+            import(text, "std::io::BufRead").unwrap(),
+            "use std::fs;\nuse std::io::{BufRead, Read, Write};\nuse url::Url;\n\nfn main() {}\n"
+        );
+        assert_eq!(
+            import(text, "std::path::Path").unwrap(),
+            "use std::fs;\nuse std::io::{Write, Read};\nuse std::path::Path;\nuse url::Url;\n\nfn main() {}\n"
+        );
+        assert_eq!(
+            import(text, "std::fs::File").unwrap(),
+            "use std::fs;\nuse std::fs::File;\nuse std::io::{Write, Read};\nuse url::Url;\n\nfn main() {}\n"
+        );
+        assert_eq!(import(text, "url::Url"), None);
+        assert_eq!(
+            import(text, "url::ParseError").unwrap(),
+            "use std::fs;\nuse std::io::{Write, Read};\nuse url::{ParseError, Url};\n\nfn main() {}\n"
+        );
+
+        let text = "//! Docs.\n#![deny(warnings)]\n\nfn main() {\n    use std::fs;\n}\n";
+        assert_eq!(
+            import(text, "std::fs::File").unwrap(),
+            "//! Docs.\n#![deny(warnings)]\n\nuse std::fs::File;\n\nfn main() {\n    use std::fs;\n}\n"
+        );
+    }
+}
@@ -0,0 +1,158 @@
+//! Call hierarchy: the functions which call a function, and the functions it
+//! calls.
+//!
+//! Save-analysis only records the names of functions, not their bodies, so
+//! the extent of a function is found by a lexical scan of its source file.
+
+use log::trace;
+use rls_analysis::{Def, DefKind, Id};
+use url::Url;
+
+use crate::actions::lexical::SourceFiles;
+use crate::actions::InitActionContext;
+use crate::lsp_data::*;
+use crate::server::ResponseError;
+use crate::Span;
+
+/// Returns the call hierarchy item for the function at the given position.
+pub fn prepare(
+    ctx: &InitActionContext,
+    params: &TextDocumentPositionParams,
+) -> Result<Vec<CallHierarchyItem>, ResponseError> {
+    let file_path = parse_file_path!(&params.text_document.uri, "call_hierarchy")?;
+    let span = ctx.convert_pos_to_span(file_path, params.position);
+
+    let def = match ctx.analysis.id(&span).and_then(|id| ctx.analysis.get_def(id)) {
+        Ok(ref def) if is_fn(def.kind) => def.clone(),
+        _ => return Ok(vec![]),
+    };
+    Ok(call_hierarchy_item(&mut SourceFiles::new(&ctx.vfs), &def).into_iter().collect())
+}
+
+/// Returns the functions which call the function of `params.item`, grouped
+/// by the calling function.
+pub fn incoming_calls(
+    ctx: &InitActionContext,
+    params: &CallHierarchyCallsParams,
+) -> Result<Vec<CallHierarchyIncomingCall>, ResponseError> {
+    let (id, def) = match item_def(ctx, &params.item) {
+        Some(def) => def,
+        None => return Ok(vec![]),
+    };
+    let mut sources = SourceFiles::new(&ctx.vfs);
+
+    let mut calls: Vec<(Id, Def, Vec<Range>)> = vec![];
+    let refs = ctx.analysis.find_all_refs_by_id(id).unwrap_or_else(|_| vec![]);
+    for span in refs.into_iter().filter(|span| *span != def.span) {
+        let (caller_id, caller) = match enclosing_fn(ctx, &mut sources, &span) {
+            Some(caller) => caller,
+            None => continue,
+        };
+        let range = ls_util::rls_to_range(span.range);
+        match calls.iter_mut().find(|(id, ..)| *id == caller_id) {
+            Some((_, _, ranges)) => ranges.push(range),
+            None => calls.push((caller_id, caller, vec![range])),
+        }
+    }
+    trace!("incoming_calls: {} callers of {}", calls.len(), def.name);
+
+    Ok(calls
+        .into_iter()
+        .filter_map(|(_, caller, from_ranges)| {
+            call_hierarchy_item(&mut sources, &caller)
+                .map(|from| CallHierarchyIncomingCall { from, from_ranges })
+        })
+        .collect())
+}
+
+/// Returns the functions called from the body of the function of
+/// `params.item`, grouped by the called function.
+pub fn outgoing_calls(
+    ctx: &InitActionContext,
+    params: &CallHierarchyCallsParams,
+) -> Result<Vec<CallHierarchyOutgoingCall>, ResponseError> {
+    let (_, def) = match item_def(ctx, &params.item) {
+        Some(def) => def,
+        None => return Ok(vec![]),
+    };
+    let mut sources = SourceFiles::new(&ctx.vfs);
+    let body = match sources.item_span(&def.span) {
+        Some(body) => body,
+        None => return Ok(vec![]),
+    };
+
+    let mut calls: Vec<(Id, Def, Vec<Range>)> = vec![];
+    for (span, callee_id) in ctx.analysis.refs_within(&body).unwrap_or_else(|_| vec![]) {
+        let callee = match ctx.analysis.get_def(callee_id) {
+            // Skip the definitions of nested functions, we only want references.
+            Ok(callee) if is_fn(callee.kind) && callee.span != span => callee,
+            _ => continue,
+        };
+        let range = ls_util::rls_to_range(span.range);
+        match calls.iter_mut().find(|(id, ..)| *id == callee_id) {
+            Some((_, _, ranges)) => ranges.push(range),
+            None => calls.push((callee_id, callee, vec![range])),
+        }
+    }
+    trace!("outgoing_calls: {} callees of {}", calls.len(), def.name);
+
+    Ok(calls
+        .into_iter()
+        .filter_map(|(_, callee, from_ranges)| {
+            call_hierarchy_item(&mut sources, &callee)
+                .map(|to| CallHierarchyOutgoingCall { to, from_ranges })
+        })
+        .collect())
+}
+
+fn is_fn(kind: DefKind) -> bool {
+    match kind {
+        DefKind::Function | DefKind::Method => true,
+        _ => false,
+    }
+}
+
+/// Finds the definition of the function a call hierarchy item refers to.
+fn item_def(ctx: &InitActionContext, item: &CallHierarchyItem) -> Option<(Id, Def)> {
+    let location = Location { uri: item.uri.clone(), range: item.selection_range };
+    let span = ls_util::location_to_rls(&location).ok()?;
+    let id = ctx.analysis.id(&span).ok()?;
+    let def = ctx.analysis.get_def(id).ok()?;
+    if is_fn(def.kind) {
+        Some((id, def))
+    } else {
+        None
+    }
+}
+
+/// Finds the innermost function whose body contains `span`.
+fn enclosing_fn(
+    ctx: &InitActionContext,
+    sources: &mut SourceFiles,
+    span: &Span,
+) -> Option<(Id, Def)> {
+    let offset = sources.offset(span)?;
+    let candidates = ctx.analysis.defs_preceding(span).ok()?;
+    candidates.into_iter().filter(|(_, def)| is_fn(def.kind)).find(|(_, def)| {
+        match sources.item_extent(&def.span) {
+            Some((start, end)) => start <= offset && offset < end,
+            None => false,
+        }
+    })
+}
+
+fn call_hierarchy_item(sources: &mut SourceFiles, def: &Def) -> Option<CallHierarchyItem> {
+    let selection_range = ls_util::rls_to_range(def.span.range);
+    let range = sources
+        .item_span(&def.span)
+        .map(|span| ls_util::rls_to_range(span.range))
+        .unwrap_or(selection_range);
+    Some(CallHierarchyItem {
+        name: def.name.clone(),
+        kind: source_kind_from_def_kind(def.kind),
+        detail: if def.value.is_empty() { None } else { Some(def.value.clone()) },
+        uri: Url::from_file_path(&def.span.file).ok()?,
+        range,
+        selection_range,
+    })
+}
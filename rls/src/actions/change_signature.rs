@@ -0,0 +1,489 @@
+//! Changing the parameters of a function, rewriting its definition and all
+//! of its call sites.
+
+use std::cmp::Reverse;
+use std::collections::hash_map::{Entry, HashMap};
+use std::path::{Path, PathBuf};
+
+use log::trace;
+use rls_analysis::{Def, DefKind};
+use rls_vfs::FileContents;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::actions::lexical::{
+    call_arguments, skip_whitespace, span_to_offsets, Lexed, OffsetRange,
+};
+use crate::actions::InitActionContext;
+use crate::lsp_data::*;
+
+/// A parameter of the new signature.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Parameter {
+    /// The parameter at `index` in the current signature, not counting a
+    /// `self` parameter.
+    Existing { index: usize },
+    /// A new parameter such as `count: usize`, and the argument to pass for
+    /// it at the existing call sites.
+    New { declaration: String, argument: String },
+}
+
+/// The edits changing a signature, and the call sites which could not be
+/// rewritten with the reason why.
+#[derive(Debug)]
+pub struct SignatureChange {
+    pub edit: WorkspaceEdit,
+    pub skipped: Vec<(Location, String)>,
+}
+
+/// A replacement of the text in a range of a file.
+type Edit = (OffsetRange, String);
+
+/// The parameter list of a function definition.
+#[derive(Debug, PartialEq)]
+struct ParameterList {
+    /// The inside of the parentheses.
+    range: OffsetRange,
+    /// The `self` parameter of a method, if any.
+    receiver: Option<String>,
+    params: Vec<String>,
+}
+
+/// Changes the parameters of the function named at `location` to `new`,
+/// at its definition and at every call site.
+pub fn change_signature(
+    ctx: &InitActionContext,
+    location: &Location,
+    new: &[Parameter],
+) -> Result<SignatureChange, String> {
+    let span = ls_util::location_to_rls(location).map_err(|_| "invalid location".to_owned())?;
+    let id = ctx.analysis.id(&span).map_err(|_| "no information for symbol".to_owned())?;
+    let def = ctx.analysis.get_def(id).map_err(|_| "no definition for symbol".to_owned())?;
+    if def.kind != DefKind::Function && def.kind != DefKind::Method {
+        return Err(format!("`{}` is not a function", def.name));
+    }
+    if is_trait_method(ctx, &def) {
+        return Err(format!("`{}` is a trait method", def.name));
+    }
+    if def.distro_crate || !def.span.file.starts_with(&ctx.current_project) {
+        return Err(format!("`{}` is defined outside the workspace", def.name));
+    }
+
+    let mut files = Files::new(ctx);
+    let (chars, lexed) = files.load(&def.span.file)?;
+    let name = span_to_offsets(lexed, &def.span);
+    let list = parameter_list(chars, lexed, name.1)
+        .ok_or_else(|| format!("cannot find the parameters of `{}`", def.name))?;
+    validate(new, list.params.len())?;
+
+    let mut edits: HashMap<PathBuf, Vec<Edit>> = HashMap::new();
+    edits.entry(def.span.file.clone()).or_default().push(definition_edit(&list, new));
+
+    let refs =
+        ctx.analysis.find_all_refs_by_id(id).map_err(|_| "error finding references".to_owned())?;
+    let mut skipped = vec![];
+    let mut calls: HashMap<PathBuf, Vec<(OffsetRange, Location)>> = HashMap::new();
+    for span in refs.iter().filter(|span| **span != def.span) {
+        let (chars, lexed) = files.load(&span.file)?;
+        let name = span_to_offsets(lexed, span);
+        if chars[name.0..name.1].iter().collect::<String>() != def.name {
+            skipped.push((ls_util::rls_to_location(span), "inside a macro".to_owned()));
+        } else if !is_import(lexed, name.0) {
+            calls
+                .entry(span.file.clone())
+                .or_default()
+                .push((name, ls_util::rls_to_location(span)));
+        }
+    }
+    for (file, calls) in calls {
+        let (chars, lexed) = files.load(&file)?;
+        let (file_edits, failed) =
+            call_edits(chars, lexed, calls, list.receiver.is_some(), list.params.len(), new);
+        edits.entry(file).or_default().extend(file_edits);
+        skipped.extend(failed);
+    }
+    trace!("change_signature: {} -> {:?}, skipped {:?}", def.name, edits, skipped);
+
+    let mut changes = HashMap::new();
+    for (file, edits) in edits {
+        let (_, lexed) = files.load(&file)?;
+        let position = |offset| {
+            let (row, col) = lexed.position(offset);
+            Position::new(row as u64, col as u64)
+        };
+        let edits = edits
+            .into_iter()
+            .map(|((start, end), new_text)| TextEdit {
+                range: Range::new(position(start), position(end)),
+                new_text,
+            })
+            .collect();
+        changes.insert(Url::from_file_path(&file).unwrap(), edits);
+    }
+
+    Ok(SignatureChange {
+        edit: WorkspaceEdit { changes: Some(changes), document_changes: None },
+        skipped,
+    })
+}
+
+/// `true` if the method is declared by a trait, or implements one. Changing
+/// its signature would have to change the trait and all of its impls alike.
+fn is_trait_method(ctx: &InitActionContext, def: &Def) -> bool {
+    // The name of a method in a trait impl refers to the trait's method, but
+    // its qualname is `<Type as Trait>::method` if it is analyzed on its own.
+    def.kind == DefKind::Method
+        && (def.qualname.contains(" as ")
+            || def
+                .parent
+                .and_then(|parent| ctx.analysis.get_def(parent).ok())
+                .map_or(false, |parent| parent.kind == DefKind::Trait))
+}
+
+/// Source files with their text, loaded on demand.
+struct Files<'a> {
+    ctx: &'a InitActionContext,
+    files: HashMap<PathBuf, (Vec<char>, Lexed)>,
+}
+
+impl<'a> Files<'a> {
+    fn new(ctx: &'a InitActionContext) -> Files<'a> {
+        Files { ctx, files: HashMap::new() }
+    }
+
+    fn load(&mut self, path: &Path) -> Result<(&[char], &Lexed), String> {
+        if let Entry::Vacant(entry) = self.files.entry(path.to_owned()) {
+            let text = match self.ctx.vfs.load_file(path) {
+                Ok(FileContents::Text(text)) => text,
+                _ => return Err(format!("cannot read {}", path.display())),
+            };
+            entry.insert((text.chars().collect(), Lexed::new(&text)));
+        }
+        let (chars, lexed) = &self.files[path];
+        Ok((chars, lexed))
+    }
+}
+
+/// Checks that `new` only refers to existing parameters, each at most once.
+fn validate(new: &[Parameter], arity: usize) -> Result<(), String> {
+    let mut used = vec![false; arity];
+    for param in new {
+        match *param {
+            Parameter::Existing { index } if index >= arity => {
+                return Err(format!("there is no parameter {}", index));
+            }
+            Parameter::Existing { index } if used[index] => {
+                return Err(format!("parameter {} is used more than once", index));
+            }
+            Parameter::Existing { index } => used[index] = true,
+            Parameter::New { ref declaration, .. } if declaration.trim().is_empty() => {
+                return Err("new parameters must be declared".to_owned());
+            }
+            Parameter::New { .. } => {}
+        }
+    }
+    Ok(())
+}
+
+/// Finds the parameter list of the function whose name ends at `offset`.
+fn parameter_list(chars: &[char], lexed: &Lexed, offset: usize) -> Option<ParameterList> {
+    let code = &lexed.code;
+    let mut i = skip_whitespace(code, offset);
+
+    // Skip generic parameters, e.g. `fn foo<F: Fn(u8)>(..)`.
+    if code.get(i) == Some(&'<') {
+        let mut depth = 0;
+        while i < code.len() {
+            match code[i] {
+                '<' => depth += 1,
+                '>' if code[i - 1] != '-' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+        i = skip_whitespace(code, i + 1);
+    }
+    if code.get(i) != Some(&'(') {
+        return None;
+    }
+
+    let open = i;
+    let mut params = vec![];
+    let mut depth = 0;
+    let mut start = open + 1;
+    for (j, &c) in code.iter().enumerate().skip(open + 1) {
+        match c {
+            '(' | '[' | '{' | '<' => depth += 1,
+            '>' if code[j - 1] == '-' => {}
+            ')' | ']' | '}' | '>' if depth > 0 => depth -= 1,
+            ',' | ')' if depth == 0 => {
+                let param = text(chars, (start, j));
+                if !param.is_empty() {
+                    params.push(param);
+                }
+                if c == ')' {
+                    let receiver = match params.first() {
+                        Some(first) if is_receiver(first) => Some(params.remove(0)),
+                        _ => None,
+                    };
+                    return Some(ParameterList { range: (open + 1, j), receiver, params });
+                }
+                start = j + 1;
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// `true` if `param` is a `self` parameter, e.g. `&'a mut self` or
+/// `self: Box<Self>`.
+fn is_receiver(param: &str) -> bool {
+    let pat = param.split(':').next().unwrap_or("").trim();
+    let pat = pat.trim_start_matches('&').trim_start();
+    let pat = if pat.starts_with('\'') {
+        pat.trim_start_matches(|c: char| c == '\'' || c.is_alphanumeric() || c == '_')
+    } else {
+        pat
+    };
+    let pat = pat.trim_start();
+    let pat = if pat.starts_with("mut ") { pat[4..].trim_start() } else { pat };
+    pat == "self"
+}
+
+fn definition_edit(list: &ParameterList, new: &[Parameter]) -> Edit {
+    let params = list.receiver.iter().cloned().chain(new.iter().map(|param| match param {
+        Parameter::Existing { index } => list.params[*index].clone(),
+        Parameter::New { declaration, .. } => declaration.trim().to_owned(),
+    }));
+    (list.range, params.collect::<Vec<_>>().join(", "))
+}
+
+/// Rewrites the arguments of the calls in a file whose callee names are at
+/// the given offsets, returning the edits and the calls which could not be
+/// rewritten with the reason why. The edits of a file must not overlap, so a
+/// call nested in the arguments of another one is rewritten within the edit
+/// of the outer call.
+fn call_edits<T>(
+    chars: &[char],
+    lexed: &Lexed,
+    mut calls: Vec<(OffsetRange, T)>,
+    receiver: bool,
+    arity: usize,
+    new: &[Parameter],
+) -> (Vec<Edit>, Vec<(T, String)>) {
+    // The arguments of a call follow its name, so going from the last call
+    // to the first rewrites the nested calls before the outer ones.
+    calls.sort_by_key(|(name, _)| Reverse(name.0));
+    let mut edits: Vec<Edit> = vec![];
+    let mut failed = vec![];
+    for (name, call) in calls {
+        match call_edit(chars, lexed, name, receiver, arity, new, &edits) {
+            Ok(edit) => {
+                let (start, end) = edit.0;
+                edits.retain(|((s, e), _)| *s < start || *e > end);
+                edits.push(edit);
+            }
+            Err(reason) => failed.push((call, reason)),
+        }
+    }
+    (edits, failed)
+}
+
+/// Rewrites the arguments of the call whose callee name is at `name`, for a
+/// function taking `arity` parameters besides `self` (if it has a
+/// `receiver`). Method calls such as `x.foo(a)` pass `self` before the name,
+/// while calls such as `Foo::foo(x, a)` pass it as the first argument. The
+/// `nested` edits within the arguments are applied to them.
+fn call_edit(
+    chars: &[char],
+    lexed: &Lexed,
+    name: OffsetRange,
+    receiver: bool,
+    arity: usize,
+    new: &[Parameter],
+    nested: &[Edit],
+) -> Result<Edit, String> {
+    let ranges = call_arguments(lexed, name.1).ok_or_else(|| "not a call".to_owned())?;
+    let range = (ranges[0].0, ranges[ranges.len() - 1].1);
+    let mut args: Vec<String> =
+        ranges.iter().map(|&range| rewritten_text(chars, range, nested)).collect();
+    // `foo()` and trailing commas.
+    if args.last().map_or(false, String::is_empty) {
+        args.pop();
+    }
+
+    let method_call = lexed.code[..name.0].iter().rev().find(|c| !c.is_whitespace()) == Some(&'.');
+    let receiver_arg = if receiver && !method_call {
+        if args.is_empty() {
+            return Err("missing `self` argument".to_owned());
+        }
+        Some(args.remove(0))
+    } else {
+        None
+    };
+    if args.len() != arity {
+        return Err(format!("expected {} arguments, found {}", arity, args.len()));
+    }
+
+    let args = receiver_arg.into_iter().chain(new.iter().map(|param| match param {
+        Parameter::Existing { index } => args[*index].clone(),
+        Parameter::New { argument, .. } => argument.trim().to_owned(),
+    }));
+    Ok((range, args.collect::<Vec<_>>().join(", ")))
+}
+
+/// `true` if the name at `offset` is part of a `use` declaration.
+fn is_import(lexed: &Lexed, offset: usize) -> bool {
+    let code = &lexed.code;
+    let mut start = lexed.statement_start(offset);
+    // Step out of groups such as `use a::{b, c}`.
+    loop {
+        let before = code[..start].iter().rposition(|c| !c.is_whitespace());
+        match before {
+            Some(brace) if code[brace] == '{' && code[..brace].ends_with(&[':', ':']) => {
+                start = lexed.statement_start(brace);
+            }
+            _ => break,
+        }
+    }
+    let words: String = code[start..offset].iter().collect();
+    words.split_whitespace().any(|word| word == "use")
+}
+
+fn text(chars: &[char], (start, end): OffsetRange) -> String {
+    chars[start..end].iter().collect::<String>().trim().to_owned()
+}
+
+/// Like `text`, with the `edits` within the range applied.
+fn rewritten_text(chars: &[char], (start, end): OffsetRange, edits: &[Edit]) -> String {
+    let mut edits: Vec<_> = edits.iter().filter(|((s, e), _)| start <= *s && *e <= end).collect();
+    edits.sort_by_key(|((s, _), _)| *s);
+    let mut result = String::new();
+    let mut i = start;
+    for ((s, e), new_text) in edits {
+        result.extend(&chars[i..*s]);
+        result.push_str(new_text);
+        i = *e;
+    }
+    result.extend(&chars[i..end]);
+    result.trim().to_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lex(text: &str) -> (Vec<char>, Lexed) {
+        (text.chars().collect(), Lexed::new(text))
+    }
+
+    fn name_end(text: &str, name: &str) -> usize {
+        let byte = text.find(name).unwrap() + name.len();
+        text[..byte].chars().count()
+    }
+
+    #[test]
+    fn test_parameter_list() {
+        let text = "fn foo<F: Fn(u8) -> u8>(&'a mut self, f: F, m: HashMap<u8, (u8, u8)>,) {}";
+        let (chars, lexed) = lex(text);
+        let list = parameter_list(&chars, &lexed, name_end(text, "foo")).unwrap();
+        assert_eq!(list.receiver, Some("&'a mut self".to_owned()));
+        assert_eq!(list.params, ["f: F", "m: HashMap<u8, (u8, u8)>"]);
+
+        let text = "fn bar() -> u8 { 0 }";
+        let (chars, lexed) = lex(text);
+        let list = parameter_list(&chars, &lexed, name_end(text, "bar")).unwrap();
+        assert_eq!(list, ParameterList { range: (7, 7), receiver: None, params: vec![] });
+    }
+
+    #[test]
+    fn test_is_receiver() {
+        assert!(is_receiver("self"));
+        assert!(is_receiver("mut self"));
+        assert!(is_receiver("&self"));
+        assert!(is_receiver("&'a mut self"));
+        assert!(is_receiver("self: Box<Self>"));
+        assert!(!is_receiver("selfish: u8"));
+        assert!(!is_receiver("x: &Self"));
+    }
+
+    #[test]
+    fn test_validate() {
+        let new = |argument: &str| Parameter::New {
+            declaration: "x: u8".to_owned(),
+            argument: argument.to_owned(),
+        };
+        assert!(validate(&[Parameter::Existing { index: 1 }, new("0")], 2).is_ok());
+        assert!(validate(&[Parameter::Existing { index: 2 }], 2).is_err());
+        assert!(validate(&[Parameter::Existing { index: 0 }, Parameter::Existing { index: 0 }], 2)
+            .is_err());
+    }
+
+    #[test]
+    fn test_edits() {
+        let new = [
+            Parameter::Existing { index: 1 },
+            Parameter::New { declaration: "c: &str".to_owned(), argument: "\"c\"".to_owned() },
+        ];
+
+        let text = "fn foo(&self, a: u8, b: (u8, u8)) {}";
+        let (chars, lexed) = lex(text);
+        let list = parameter_list(&chars, &lexed, name_end(text, "foo")).unwrap();
+        let (range, params) = definition_edit(&list, &new);
+        assert_eq!(range, (7, 32));
+        assert_eq!(params, "&self, b: (u8, u8), c: &str");
+
+        let edit = |text: &str| {
+            let (chars, lexed) = lex(text);
+            let end = name_end(text, "foo");
+            call_edit(&chars, &lexed, (end - 3, end), true, 2, &new, &[])
+                .map(|((start, end), args)| format!("{}{}{}", &text[..start], args, &text[end..]))
+        };
+        assert_eq!(edit("x.foo(1, (2, 3))"), Ok("x.foo((2, 3), \"c\")".to_owned()));
+        assert_eq!(
+            edit("x\n    .foo::<u8>(f(1), \"a,b\",)"),
+            Ok("x\n    .foo::<u8>(\"a,b\", \"c\")".to_owned())
+        );
+        assert_eq!(edit("S::foo(&x, 1, 2)"), Ok("S::foo(&x, 2, \"c\")".to_owned()));
+        assert_eq!(edit("S::foo(&x, 1)"), Err("expected 2 arguments, found 1".to_owned()));
+        assert_eq!(edit("S::foo()"), Err("missing `self` argument".to_owned()));
+        assert_eq!(edit("let f = S::foo;"), Err("not a call".to_owned()));
+    }
+
+    #[test]
+    fn test_nested_calls() {
+        let new = [Parameter::Existing { index: 1 }, Parameter::Existing { index: 0 }];
+        let text = "foo(foo(1, 2), foo(3));\nfoo(foo(foo(4, 5), 6), 7);";
+        let (chars, lexed) = lex(text);
+        let calls: Vec<_> = text
+            .match_indices("foo")
+            .enumerate()
+            .map(|(i, (start, _))| ((start, start + 3), i))
+            .collect();
+        let (mut edits, failed) = call_edits(&chars, &lexed, calls, false, 2, &new);
+        assert_eq!(failed, [(2, "expected 2 arguments, found 1".to_owned())]);
+
+        // Only the outer calls are edited, which also rewrite the nested ones.
+        assert_eq!(edits.len(), 2);
+        edits.sort_by_key(|((start, _), _)| Reverse(*start));
+        let mut result = text.to_owned();
+        for ((start, end), args) in edits {
+            result.replace_range(start..end, &args);
+        }
+        assert_eq!(result, "foo(foo(3), foo(2, 1));\nfoo(7, foo(6, foo(5, 4)));");
+    }
+
+    #[test]
+    fn test_is_import() {
+        let text = "use a::{b, foo};\nfn main() { foo(); }";
+        let lexed = Lexed::new(text);
+        assert!(is_import(&lexed, text.find("foo").unwrap()));
+        assert!(!is_import(&lexed, text.rfind("foo").unwrap()));
+    }
+}
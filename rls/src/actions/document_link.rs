@@ -0,0 +1,301 @@
+//! Document links: paths in `Cargo.toml` manifests, `#[path]` attributes and
+//! `include_str!`-like macros, and URLs in doc comments.
+//!
+//! Links are found by a lexical scan of the document, so that they are
+//! available before the first build has finished.
+
+use std::path::{Path, PathBuf};
+
+use log::trace;
+use rls_vfs::FileContents;
+use url::Url;
+
+use crate::actions::lexical::{is_ident_char, skip_whitespace, Lexed};
+use crate::actions::InitActionContext;
+use crate::lsp_data::*;
+use crate::server::ResponseError;
+
+/// The target of a link, as written in the document.
+#[derive(Debug, PartialEq)]
+enum Target {
+    Url(String),
+    /// A file, relative to the directory of the document.
+    File(String),
+    /// A package directory, relative to the directory of the manifest.
+    Package(String),
+}
+
+/// Computes the links of the given document.
+pub fn document_links(
+    ctx: &InitActionContext,
+    params: &DocumentLinkParams,
+) -> Result<Vec<DocumentLink>, ResponseError> {
+    let file_path = parse_file_path!(&params.text_document.uri, "document_link")?;
+
+    let text = match ctx.vfs.load_file(&file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return Ok(vec![]),
+    };
+    let dir = match file_path.parent() {
+        Some(dir) => dir,
+        None => return Ok(vec![]),
+    };
+
+    let links =
+        if file_path.ends_with("Cargo.toml") { manifest_links(&text) } else { source_links(&text) };
+    trace!("document_link: {} links in {:?}", links.len(), file_path);
+
+    Ok(links
+        .into_iter()
+        .filter_map(|(range, target)| {
+            let target = match target {
+                Target::Url(url) => Url::parse(&url).ok()?,
+                Target::File(path) => Url::from_file_path(dir.join(path)).ok()?,
+                Target::Package(path) => {
+                    Url::from_file_path(package_manifest(ctx, &dir.join(path))?).ok()?
+                }
+            };
+            Some(DocumentLink { range, target })
+        })
+        .collect())
+}
+
+/// Finds the manifest of the package in `dir`. Packages in the project model
+/// are preferred, but any manifest on disk will do (e.g., for a dependency
+/// which has not been added to the lock file yet).
+fn package_manifest(ctx: &InitActionContext, dir: &Path) -> Option<PathBuf> {
+    let manifest = dir.canonicalize().ok()?.join("Cargo.toml");
+    let in_project = match ctx.project_model() {
+        Ok(project) => project.package_for_manifest(&manifest).is_some(),
+        Err(_) => false,
+    };
+    if in_project || manifest.exists() {
+        Some(manifest)
+    } else {
+        None
+    }
+}
+
+fn char_range(row: usize, start: usize, end: usize) -> Range {
+    Range::new(Position::new(row as u64, start as u64), Position::new(row as u64, end as u64))
+}
+
+/// Finds the `path` keys of a `Cargo.toml` manifest. These refer to packages
+/// in dependency tables, and to source files in target tables.
+fn manifest_links(text: &str) -> Vec<(Range, Target)> {
+    let mut links = vec![];
+    let mut in_target_table = false;
+    for (row, line) in text.lines().enumerate() {
+        let chars: Vec<char> = line.chars().collect();
+        let trimmed = line.trim_start();
+        if trimmed.starts_with('[') {
+            let table = trimmed.trim_start_matches('[').split(']').next().unwrap_or("");
+            in_target_table = ["lib", "bin", "test", "bench", "example"].contains(&table.trim());
+            continue;
+        }
+        if trimmed.starts_with('#') {
+            continue;
+        }
+
+        let mut i = 0;
+        while i + 4 <= chars.len() {
+            let is_key = chars[i..i + 4] == ['p', 'a', 't', 'h']
+                && (i == 0 || !(is_ident_char(chars[i - 1]) || chars[i - 1] == '-'))
+                && chars.get(i + 4).map(|&c| !(is_ident_char(c) || c == '-')).unwrap_or(true);
+            if is_key {
+                let mut j = skip_whitespace(&chars, i + 4);
+                if chars.get(j) == Some(&'=') {
+                    j = skip_whitespace(&chars, j + 1);
+                    if let Some((start, end)) = string_contents(&chars, j) {
+                        let value: String = chars[start..end].iter().collect();
+                        let target = if in_target_table {
+                            Target::File(value)
+                        } else {
+                            Target::Package(value)
+                        };
+                        links.push((char_range(row, start, end), target));
+                        i = end;
+                        continue;
+                    }
+                }
+            }
+            i += 1;
+        }
+    }
+    links
+}
+
+/// Finds URLs in doc comments, and the paths of `#[path]` attributes and of
+/// `include!`, `include_str!` and `include_bytes!` invocations.
+fn source_links(text: &str) -> Vec<(Range, Target)> {
+    let chars: Vec<char> = text.chars().collect();
+    let lexed = Lexed::new(text);
+    let code = &lexed.code;
+    let to_range = |start: usize, end: usize| {
+        let (start_row, start_col) = lexed.position(start);
+        let (end_row, end_col) = lexed.position(end);
+        Range::new(
+            Position::new(start_row as u64, start_col as u64),
+            Position::new(end_row as u64, end_col as u64),
+        )
+    };
+    let mut links = vec![];
+
+    for &(row, _) in lexed.line_comments.iter().filter(|(_, is_doc)| *is_doc) {
+        let start = lexed.offset(row, 0);
+        let end = start + lexed.line(row).chars().count();
+        for (start, end) in
+            urls(&chars[start..end]).into_iter().map(|(s, e)| (start + s, start + e))
+        {
+            links.push((to_range(start, end), Target::Url(chars[start..end].iter().collect())));
+        }
+    }
+
+    // Whitespace is skipped in the original text, as string literals are
+    // blanked out in the lexed code.
+    let mut i = 0;
+    while i < code.len() {
+        if !is_ident_char(code[i]) || (i > 0 && is_ident_char(code[i - 1])) {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < code.len() && is_ident_char(code[i]) {
+            i += 1;
+        }
+        let word: String = code[start..i].iter().collect();
+
+        // The offset at which the string literal should start.
+        let literal = match &*word {
+            "path" => {
+                let before: String = code[..start].iter().collect();
+                let is_attr = before.trim_end().ends_with('[')
+                    && before.trim_end().trim_end_matches('[').trim_end().ends_with('#');
+                let eq = skip_whitespace(&chars, i);
+                if is_attr && code.get(eq) == Some(&'=') {
+                    Some(skip_whitespace(&chars, eq + 1))
+                } else {
+                    None
+                }
+            }
+            "include" | "include_str" | "include_bytes" => {
+                let bang = skip_whitespace(&chars, i);
+                let open = skip_whitespace(&chars, bang + 1);
+                if code.get(bang) == Some(&'!') && code.get(open) == Some(&'(') {
+                    Some(skip_whitespace(&chars, open + 1))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        };
+        if let Some((start, end)) = literal.and_then(|j| string_contents(&chars, j)) {
+            let path = chars[start..end].iter().collect();
+            links.push((to_range(start, end), Target::File(path)));
+            i = end;
+        }
+    }
+    links
+}
+
+/// If a (non-raw) string literal starts at `start`, returns the range of its
+/// contents.
+fn string_contents(chars: &[char], start: usize) -> Option<(usize, usize)> {
+    if chars.get(start) != Some(&'"') {
+        return None;
+    }
+    let mut end = start + 1;
+    while end < chars.len() && chars[end] != '"' {
+        end += if chars[end] == '\\' { 2 } else { 1 };
+    }
+    if end < chars.len() && end > start + 1 {
+        Some((start + 1, end))
+    } else {
+        None
+    }
+}
+
+/// Finds `http` and `https` URLs in some text, e.g., a doc comment.
+fn urls(text: &[char]) -> Vec<(usize, usize)> {
+    let mut result = vec![];
+    let mut i = 0;
+    while i < text.len() {
+        let rest = &text[i..];
+        if !(rest.starts_with(&['h', 't', 't', 'p', ':', '/', '/'])
+            || rest.starts_with(&['h', 't', 't', 'p', 's', ':', '/', '/']))
+        {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < text.len() && !text[i].is_whitespace() && !"<>()[]\"'`".contains(text[i]) {
+            i += 1;
+        }
+        // Don't include punctuation ending a sentence.
+        let mut end = i;
+        while end > start && ".,;:!?".contains(text[end - 1]) {
+            end -= 1;
+        }
+        result.push((start, end));
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ranges(links: &[(Range, Target)]) -> Vec<(u64, u64, u64)> {
+        links.iter().map(|(r, _)| (r.start.line, r.start.character, r.end.character)).collect()
+    }
+
+    #[test]
+    fn test_source_links() {
+        let text = r#"/// See <https://example.com/a>, or https://example.com.
+#[path = "foo/bar.rs"]
+mod bar;
+const DATA: &str = include_str!( "data.txt" );
+// https://example.com/not-a-doc-comment
+fn path(p: &str) -> &str { let s = "include_str!(\"x\")"; s }
+"#;
+        let links = source_links(text);
+        assert_eq!(ranges(&links), vec![(0, 9, 30), (0, 36, 55), (1, 10, 20), (3, 34, 42)]);
+        assert_eq!(
+            links.into_iter().map(|(_, t)| t).collect::<Vec<_>>(),
+            vec![
+                Target::Url("https://example.com/a".to_owned()),
+                Target::Url("https://example.com".to_owned()),
+                Target::File("foo/bar.rs".to_owned()),
+                Target::File("data.txt".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_manifest_links() {
+        let text = r#"[package]
+name = "foo"
+
+[lib]
+path = "src/foo.rs"
+
+[dependencies]
+bar = { version = "0.1", path = "../bar" }
+# baz = { path = "../baz" }
+classpath = "nope"
+
+[dependencies.qux]
+path = "qux"
+"#;
+        let links = manifest_links(text);
+        assert_eq!(ranges(&links), vec![(4, 8, 18), (7, 33, 39), (12, 8, 11)]);
+        assert_eq!(
+            links.into_iter().map(|(_, t)| t).collect::<Vec<_>>(),
+            vec![
+                Target::File("src/foo.rs".to_owned()),
+                Target::Package("../bar".to_owned()),
+                Target::Package("qux".to_owned()),
+            ]
+        );
+    }
+}
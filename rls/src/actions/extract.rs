@@ -0,0 +1,533 @@
+//! Extract function and extract variable refactorings.
+//!
+//! Whether the selection is a whole expression or whole statements is decided
+//! by a lexical scan of the source text. The locals it uses, and their types,
+//! are taken from the save-analysis data.
+
+use log::trace;
+use rls_analysis::{DefKind, Id};
+use rls_span::{Column, Row};
+use rls_vfs::FileContents;
+
+use crate::actions::lexical::{
+    contains_word, is_balanced, is_copy, is_ident_char, is_mut, is_write, span_to_offsets,
+    workspace_edit, Lexed, OffsetRange,
+};
+use crate::actions::selection::enclosing_segment;
+use crate::actions::InitActionContext;
+use crate::lsp_data::*;
+use crate::Span;
+
+/// The name of an extracted function, for the user to rename.
+const FUNCTION_NAME: &str = "new_function";
+/// The name of an extracted variable, for the user to rename.
+const VARIABLE_NAME: &str = "new_variable";
+
+/// A local variable of the function containing the selection.
+#[derive(Debug)]
+struct Local {
+    name: String,
+    /// The type of the variable, empty if it is not known.
+    ty: String,
+    /// The offset of the name in the definition.
+    def: usize,
+    is_mut: bool,
+    /// The names in all other uses of the variable.
+    uses: Vec<OffsetRange>,
+}
+
+/// A selection in the body of a function.
+struct Selection {
+    chars: Vec<char>,
+    lexed: Lexed,
+    /// The selection, trimmed of whitespace.
+    range: OffsetRange,
+    /// The item after which an extracted function is inserted: the function
+    /// containing the selection, or the impl containing the method.
+    item: OffsetRange,
+    /// The locals of the function, in the order of their definitions.
+    locals: Vec<Local>,
+}
+
+/// Computes the edit which extracts the selected statements into a new
+/// function, or `None` if they can't be extracted.
+pub fn extract_function(ctx: &InitActionContext, location: &Location) -> Option<WorkspaceEdit> {
+    let selection = Selection::load(ctx, location)?;
+    let edits = function_edits(&selection)?;
+    trace!("extract_function: {:?} -> {:?}", location, edits);
+    Some(workspace_edit(&selection.lexed, location, edits))
+}
+
+/// Computes the edit which extracts the selected expression into a new local
+/// variable, or `None` if it can't be extracted.
+pub fn extract_variable(ctx: &InitActionContext, location: &Location) -> Option<WorkspaceEdit> {
+    let selection = Selection::load(ctx, location)?;
+    let edit = variable_edit(&selection)?;
+    trace!("extract_variable: {:?} -> {:?}", location, edit);
+    Some(workspace_edit(&selection.lexed, location, vec![edit]))
+}
+
+impl Selection {
+    fn load(ctx: &InitActionContext, location: &Location) -> Option<Selection> {
+        let span = ls_util::location_to_rls(location).ok()?;
+        let text = match ctx.vfs.load_file(&span.file) {
+            Ok(FileContents::Text(text)) => text,
+            _ => return None,
+        };
+        let chars: Vec<char> = text.chars().collect();
+        let lexed = Lexed::new(&text);
+
+        // Trim using the original text, so that literals (which are blanked
+        // out in the lexed code) are not mistaken for whitespace.
+        let (mut start, mut end) = span_to_offsets(&lexed, &span);
+        while start < end && chars[start].is_whitespace() {
+            start += 1;
+        }
+        while end > start && chars[end - 1].is_whitespace() {
+            end -= 1;
+        }
+        if start == end {
+            return None;
+        }
+
+        // The innermost function whose body contains the selection.
+        let (def, function) = ctx
+            .analysis
+            .defs_preceding(&span)
+            .ok()?
+            .into_iter()
+            .filter(|(_, def)| def.kind == DefKind::Function || def.kind == DefKind::Method)
+            .filter_map(|(_, def)| {
+                let name = span_to_offsets(&lexed, &def.span).0;
+                let extent = lexed.item_extent(name)?;
+                let body = (name..extent.1).find(|&i| lexed.code[i] == '{')?;
+                if body < start && end < extent.1 {
+                    Some((def, extent))
+                } else {
+                    None
+                }
+            })
+            .next()?;
+        let item = if def.kind == DefKind::Method {
+            let (open, _) = enclosing_block(&lexed, function.0)?;
+            lexed.item_extent(open)?
+        } else {
+            function
+        };
+
+        // `idents` checks the columns of the span on every line, so ask for
+        // all the columns of the lines of the function.
+        let (first_row, _) = lexed.position(function.0);
+        let (last_row, _) = lexed.position(function.1);
+        let lines = Span::new(
+            Row::new_zero_indexed(first_row as u32),
+            Row::new_zero_indexed(last_row as u32),
+            Column::new_zero_indexed(0),
+            Column::new_zero_indexed(u32::max_value()),
+            span.file.clone(),
+        );
+        let mut locals: Vec<(Id, Local)> = vec![];
+        for ident in ctx.analysis.idents(&lines).unwrap_or_else(|_| vec![]) {
+            let offsets = span_to_offsets(&lexed, &ident.span);
+            if offsets.0 < function.0 || function.1 < offsets.1 {
+                continue;
+            }
+            let index = match locals.iter().position(|(id, _)| *id == ident.id) {
+                Some(index) => index,
+                None => {
+                    let def = match ctx.analysis.get_def(ident.id) {
+                        Ok(def) if def.kind == DefKind::Local && def.span.file == span.file => def,
+                        _ => continue,
+                    };
+                    let def_offset = span_to_offsets(&lexed, &def.span).0;
+                    let local = Local {
+                        name: def.name,
+                        ty: def.value.trim().to_owned(),
+                        def: def_offset,
+                        is_mut: is_mut(&lexed.code, def_offset),
+                        uses: vec![],
+                    };
+                    locals.push((ident.id, local));
+                    locals.len() - 1
+                }
+            };
+            let local = &mut locals[index].1;
+            if offsets.0 != local.def && !local.uses.contains(&offsets) {
+                local.uses.push(offsets);
+            }
+        }
+        let mut locals: Vec<Local> = locals.into_iter().map(|(_, local)| local).collect();
+        locals.sort_by_key(|local| local.def);
+
+        Some(Selection { chars, lexed, range: (start, end), item, locals })
+    }
+
+    fn text(&self, (start, end): OffsetRange) -> String {
+        self.chars[start..end].iter().collect()
+    }
+
+    /// The indentation of the line containing `offset`.
+    fn indentation(&self, offset: usize) -> String {
+        let (row, _) = self.lexed.position(offset);
+        let line_start = self.lexed.offset(row, 0);
+        self.chars[line_start..].iter().take_while(|&&c| c == ' ' || c == '\t').collect()
+    }
+}
+
+/// Finds the innermost braces enclosing `offset`.
+fn enclosing_block(lexed: &Lexed, offset: usize) -> Option<OffsetRange> {
+    let mut stack = vec![];
+    for (i, &c) in lexed.code.iter().enumerate() {
+        match c {
+            '(' | '[' | '{' => stack.push(i),
+            ')' | ']' | '}' => match stack.pop() {
+                // Inner blocks are closed first.
+                Some(open) if lexed.code[open] == '{' && open < offset && offset < i => {
+                    return Some((open, i));
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Finds the statement containing `offset`, in the innermost block of
+/// statements. Returns the offset of the opening brace of the block and the
+/// extent of the statement, or `None` if the innermost braces are not a block
+/// of statements (e.g., a `match` or a struct literal).
+fn enclosing_statement(lexed: &Lexed, offset: usize) -> Option<(usize, OffsetRange)> {
+    let code = &lexed.code;
+    let (open, close) = enclosing_block(lexed, offset)?;
+
+    let header: String = code[lexed.statement_start(open)..open].iter().collect();
+    if contains_word(&header, "match") {
+        return None;
+    }
+    // A struct literal starts with a field, `Foo { a: 1 }` or `Foo { a, b }`.
+    let body: String = code[open + 1..close].iter().collect();
+    let body = body.trim_start();
+    let first_word = body.find(|c| !is_ident_char(c)).unwrap_or(body.len());
+    let after = body[first_word..].trim_start();
+    if first_word > 0
+        && ((after.starts_with(':') && !after.starts_with("::")) || after.starts_with(','))
+    {
+        return None;
+    }
+
+    let statement = enclosing_segment(lexed, open + 1, close, offset)?;
+    Some((open, statement))
+}
+
+/// `true` if the type can be written in a signature.
+fn is_nameable(ty: &str) -> bool {
+    !ty.is_empty() && !ty.contains("closure@") && !ty.contains("[type error]")
+}
+
+/// Replaces the selected expression with a new variable, defined just before
+/// the statement containing it. This is a single edit, from the start of the
+/// statement to the end of the selection.
+fn variable_edit(selection: &Selection) -> Option<(OffsetRange, String)> {
+    let (start, end) = selection.range;
+    let code = &selection.lexed.code;
+    let selected: String = code[start..end].iter().collect();
+    if !is_balanced(&code[start..end]) || selected.contains(';') || contains_word(&selected, "let")
+    {
+        return None;
+    }
+    // Commas between arguments or fields.
+    let mut depth = 0;
+    for &c in &code[start..end] {
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            ',' if depth == 0 => return None,
+            _ => {}
+        }
+    }
+
+    let (_, statement) = enclosing_statement(&selection.lexed, start)?;
+    if end > statement.1 {
+        return None;
+    }
+    // The locals used by the expression must still be in scope before the
+    // statement, i.e., not bound by a closure or pattern in it.
+    let is_bound_in_statement = |local: &Local| {
+        statement.0 <= local.def
+            && local.def < start
+            && local.uses.iter().any(|&(s, _)| start <= s && s < end)
+    };
+    if selection.locals.iter().any(is_bound_in_statement) {
+        return None;
+    }
+
+    let new_text = format!(
+        "let {} = {};\n{}{}{}",
+        VARIABLE_NAME,
+        selection.text((start, end)),
+        selection.indentation(statement.0),
+        selection.text((statement.0, start)),
+        VARIABLE_NAME,
+    );
+    Some(((statement.0, end), new_text))
+}
+
+/// Replaces the selected statements with a call to a new function, inserted
+/// after the item containing them. Locals defined before the selection become
+/// parameters, those defined in it and used after it are returned.
+fn function_edits(selection: &Selection) -> Option<Vec<(OffsetRange, String)>> {
+    let (start, end) = selection.range;
+    let code = &selection.lexed.code;
+    let (first_block, first) = enclosing_statement(&selection.lexed, start)?;
+    let (last_block, last) = enclosing_statement(&selection.lexed, end - 1)?;
+    if first_block != last_block || first.0 != start || last.1 != end {
+        return None;
+    }
+    if code[end - 1] != ';' && code[end - 1] != '}' {
+        return None;
+    }
+    // Control flow out of the selection, and `self`, can't be moved to a
+    // function.
+    let selected: String = code[start..end].iter().collect();
+    let control_flow = ["return", "break", "continue", "self", "Self"];
+    if control_flow.iter().any(|w| contains_word(&selected, w)) || selected.contains('?') {
+        return None;
+    }
+
+    let inside = |offset: usize| start <= offset && offset < end;
+    let mut params = vec![];
+    let mut args = vec![];
+    let mut returned = vec![];
+    // The uses of locals passed by mutable reference, which need a deref.
+    let mut derefs = vec![];
+    for local in &selection.locals {
+        let used_inside = local.uses.iter().any(|&(s, _)| inside(s));
+        let used_after = local.uses.iter().any(|&(s, _)| s >= end);
+        if local.def < start && used_inside {
+            if !is_nameable(&local.ty) {
+                return None;
+            }
+            let written = local.uses.iter().any(|&u| inside(u.0) && is_write(code, u));
+            let (param, arg) = if written {
+                derefs.extend(local.uses.iter().filter(|&&(s, _)| inside(s)).cloned());
+                (format!("{}: &mut {}", local.name, local.ty), format!("&mut {}", local.name))
+            } else if used_after && !is_copy(&local.ty) {
+                // Methods might still mutate a `mut` variable.
+                let mutability = if local.is_mut { "mut " } else { "" };
+                (
+                    format!("{}: &{}{}", local.name, mutability, local.ty),
+                    format!("&{}{}", mutability, local.name),
+                )
+            } else {
+                let mutability = if local.is_mut && !is_copy(&local.ty) { "mut " } else { "" };
+                (format!("{}{}: {}", mutability, local.name, local.ty), local.name.clone())
+            };
+            params.push(param);
+            args.push(arg);
+        } else if inside(local.def) && used_after {
+            if !is_nameable(&local.ty) {
+                return None;
+            }
+            returned.push(local);
+        }
+    }
+
+    // The body, with the mutable references dereferenced (unless auto-deref
+    // will do it) and re-indented.
+    let mut body: Vec<char> = selection.chars[start..end].to_vec();
+    derefs.sort();
+    for &(s, e) in derefs.iter().rev() {
+        let next = code[e..].iter().find(|c| !c.is_whitespace());
+        if next != Some(&'.') && next != Some(&'[') {
+            body.insert(s - start, '*');
+        }
+    }
+    let body: String = body.into_iter().collect();
+    let base_indent = selection.indentation(start).chars().count();
+    let indent = selection.indentation(selection.item.0);
+    let mut function = format!("\n\n{}fn {}({})", indent, FUNCTION_NAME, params.join(", "));
+    let names: Vec<&str> = returned.iter().map(|local| &*local.name).collect();
+    let types: Vec<&str> = returned.iter().map(|local| &*local.ty).collect();
+    match returned.len() {
+        0 => {}
+        1 => function.push_str(&format!(" -> {}", types[0])),
+        _ => function.push_str(&format!(" -> ({})", types.join(", "))),
+    }
+    function.push_str(" {\n");
+    for (i, line) in body.split('\n').enumerate() {
+        let line = if i == 0 {
+            line
+        } else {
+            let indent_len: usize = line
+                .chars()
+                .take(base_indent)
+                .take_while(|c| c.is_whitespace())
+                .map(char::len_utf8)
+                .sum();
+            &line[indent_len..]
+        };
+        if !line.trim().is_empty() {
+            function.push_str(&format!("{}    {}", indent, line));
+        }
+        function.push('\n');
+    }
+    match returned.len() {
+        0 => {}
+        1 => function.push_str(&format!("{}    {}\n", indent, names[0])),
+        _ => function.push_str(&format!("{}    ({})\n", indent, names.join(", "))),
+    }
+    function.push_str(&format!("{}}}", indent));
+
+    let call = format!("{}({})", FUNCTION_NAME, args.join(", "));
+    let patterns: Vec<String> = returned
+        .iter()
+        .map(|local| format!("{}{}", if local.is_mut { "mut " } else { "" }, local.name))
+        .collect();
+    let call = match returned.len() {
+        0 => format!("{};", call),
+        1 => format!("let {} = {};", patterns[0], call),
+        _ => format!("let ({}) = {};", patterns.join(", "), call),
+    };
+    Some(vec![((start, end), call), ((selection.item.1, selection.item.1), function)])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Builds a selection of `selected` in `text`, where an extracted function
+    /// is inserted after `item`. Each local is given by its name, type and
+    /// whether it is `mut`; the first occurrence of its name is the
+    /// definition, all others are uses.
+    fn select(text: &str, selected: &str, item: &str, locals: &[(&str, &str, bool)]) -> Selection {
+        let offset = |byte: usize| text[..byte].chars().count();
+        let start = offset(text.find(selected).unwrap());
+        let end = start + selected.chars().count();
+        let item_start = offset(text.find(item).unwrap());
+        let lexed = Lexed::new(text);
+        let item = lexed.item_extent(item_start).unwrap();
+        let locals = locals
+            .iter()
+            .map(|&(name, ty, is_mut)| {
+                let mut occurrences = text
+                    .match_indices(name)
+                    .filter(|(i, _)| {
+                        !text[..*i].ends_with(is_ident_char)
+                            && !text[i + name.len()..].starts_with(is_ident_char)
+                    })
+                    .map(|(i, _)| (offset(i), offset(i) + name.chars().count()));
+                let def = occurrences.next().unwrap().0;
+                Local {
+                    name: name.to_owned(),
+                    ty: ty.to_owned(),
+                    def,
+                    is_mut,
+                    uses: occurrences.collect(),
+                }
+            })
+            .collect();
+        Selection { chars: text.chars().collect(), lexed, range: (start, end), item, locals }
+    }
+
+    fn apply(text: &str, mut edits: Vec<(OffsetRange, String)>) -> String {
+        let mut chars: Vec<char> = text.chars().collect();
+        edits.sort_by_key(|&(range, _)| range);
+        for ((start, end), new_text) in edits.into_iter().rev() {
+            chars.splice(start..end, new_text.chars());
+        }
+        chars.into_iter().collect()
+    }
+
+    #[test]
+    fn test_extract_function() {
+        let text = "fn main() {
+    let mut a = 1;
+    let b = String::new();
+    a += b.len();
+    let c = a * 2;
+    println!(\"{} {} {}\", a, b, c);
+}
+";
+        let locals = [("a", "usize", true), ("b", "String", false), ("c", "usize", false)];
+        let selected = "a += b.len();\n    let c = a * 2;";
+        let selection = select(text, selected, "fn main", &locals);
+        assert_eq!(
+            apply(text, function_edits(&selection).unwrap()),
+            "fn main() {
+    let mut a = 1;
+    let b = String::new();
+    let c = new_function(&mut a, &b);
+    println!(\"{} {} {}\", a, b, c);
+}
+
+fn new_function(a: &mut usize, b: &String) -> usize {
+    *a += b.len();
+    let c = *a * 2;
+    c
+}
+"
+        );
+
+        // Only whole statements, without control flow, can be extracted.
+        let selection = select(text, "a += b.len()", "fn main", &locals);
+        assert!(function_edits(&selection).is_none());
+        let text = "fn f(x: u8) -> u8 {\n    if x > 1 {\n        return 0;\n    }\n    x\n}\n";
+        let selection = select(text, "if x > 1 {\n        return 0;\n    }", "fn f", &[]);
+        assert!(function_edits(&selection).is_none());
+    }
+
+    #[test]
+    fn test_extract_method_body() {
+        let text = "impl Foo {
+    fn foo(&self) {
+        let mut v = vec![];
+        v.push(1);
+        for x in v.iter() {
+            println!(\"{}\", x);
+        }
+    }
+}
+";
+        let locals = [("v", "std::vec::Vec<i32>", true), ("x", "&i32", false)];
+        let selected = "for x in v.iter() {\n            println!(\"{}\", x);\n        }";
+        let selection = select(text, selected, "impl Foo", &locals);
+        assert_eq!(
+            apply(text, function_edits(&selection).unwrap()),
+            "impl Foo {
+    fn foo(&self) {
+        let mut v = vec![];
+        v.push(1);
+        new_function(v);
+    }
+}
+
+fn new_function(mut v: std::vec::Vec<i32>) {
+    for x in v.iter() {
+        println!(\"{}\", x);
+    }
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_extract_variable() {
+        let text = "fn main() {\n    let x = 1;\n    foo(x + 2, \"a; b\");\n}\n";
+        let locals = [("x", "i32", false)];
+        let selection = select(text, "x + 2", "fn main", &locals);
+        assert_eq!(
+            apply(text, vec![variable_edit(&selection).unwrap()]),
+            "fn main() {\n    let x = 1;\n    let new_variable = x + 2;\n    foo(new_variable, \"a; b\");\n}\n"
+        );
+
+        assert!(variable_edit(&select(text, "x + 2, \"a; b\"", "fn main", &locals)).is_none());
+        assert!(variable_edit(&select(text, "let x = 1", "fn main", &locals)).is_none());
+
+        let text = "fn main() {\n    match a {\n        _ => 1 + 2,\n    }\n}\n";
+        assert!(variable_edit(&select(text, "1 + 2", "fn main", &[])).is_none());
+        let text = "fn main() {\n    let f = |y| y + 1;\n}\n";
+        let locals = [("f", "", false), ("y", "i32", false)];
+        assert!(variable_edit(&select(text, "y + 1", "fn main", &locals)).is_none());
+    }
+}
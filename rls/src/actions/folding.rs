@@ -0,0 +1,221 @@
+//! Folding ranges for Rust source files.
+//!
+//! Items are located using the save-analysis data, everything else (imports,
+//! comments and `#[cfg]` blocks) is found by a lexical scan of the source
+//! text, so that folding works even before the first build has finished.
+
+use log::trace;
+use rls_analysis::DefKind;
+use rls_vfs::FileContents;
+
+use crate::actions::lexical::Lexed;
+use crate::actions::InitActionContext;
+use crate::lsp_data::*;
+use crate::server::ResponseError;
+
+/// Computes the folding ranges for the given document.
+pub fn folding_ranges(
+    ctx: &InitActionContext,
+    params: &FoldingRangeParams,
+) -> Result<Vec<FoldingRange>, ResponseError> {
+    let file_path = parse_file_path!(&params.text_document.uri, "folding_range")?;
+
+    let text = match ctx.vfs.load_file(&file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return Ok(vec![]),
+    };
+
+    let items: Vec<_> = ctx
+        .analysis
+        .symbols(&file_path)
+        .unwrap_or_else(|_| vec![])
+        .into_iter()
+        .filter(|s| is_foldable_item(s.kind))
+        .map(|s| (s.span.range.row_start.0 as usize, s.span.range.col_start.0 as usize))
+        .collect();
+    trace!("folding_range: {} items from analysis", items.len());
+
+    Ok(compute_folding_ranges(&text, &items))
+}
+
+fn is_foldable_item(kind: DefKind) -> bool {
+    match kind {
+        DefKind::Function
+        | DefKind::Method
+        | DefKind::Mod
+        | DefKind::Struct
+        | DefKind::Enum
+        | DefKind::Union
+        | DefKind::Trait
+        | DefKind::Macro => true,
+        _ => false,
+    }
+}
+
+/// Computes folding ranges for `text`, where `items` are the (zero-indexed)
+/// row and column of the names of items defined in the text.
+fn compute_folding_ranges(text: &str, items: &[(usize, usize)]) -> Vec<FoldingRange> {
+    let lexed = Lexed::new(text);
+    let mut ranges = vec![];
+
+    let mut push = |start: usize, end: usize, kind: Option<FoldingRangeKind>| {
+        if end > start {
+            ranges.push(FoldingRange {
+                start_line: start as u64,
+                start_character: None,
+                end_line: end as u64,
+                end_character: None,
+                kind,
+            });
+        }
+    };
+
+    let block_end =
+        |row, col| lexed.block_end(lexed.offset(row, col)).map(|end| lexed.position(end).0);
+
+    // Items, as found by the analysis.
+    for &(row, col) in items {
+        if let Some(end) = block_end(row, col) {
+            push(row, end, None);
+        }
+    }
+
+    for row in 0..lexed.line_count() {
+        let line = lexed.line(row);
+        let line = line.trim_start();
+
+        // Impls are not recorded as definitions, so find them lexically.
+        let item = line.trim_start_matches("unsafe ").trim_start();
+        if item.starts_with("impl ") || item.starts_with("impl<") {
+            if let Some(end) = block_end(row, 0) {
+                push(row, end, None);
+            }
+        }
+
+        // `#[cfg]` attributes, folding the attribute together with its item.
+        if line.starts_with("#[cfg(") {
+            if let Some(end) = block_end(row, 0) {
+                push(row, end, Some(FoldingRangeKind::Region));
+            }
+        }
+    }
+
+    // Blocks of consecutive imports, possibly separated by blank lines.
+    let mut imports: Option<(usize, usize)> = None;
+    let mut row = 0;
+    while row < lexed.line_count() {
+        let line = lexed.line(row);
+        if is_import(&line) {
+            let end = lexed.statement_end(lexed.offset(row, 0));
+            let end = end.map(|end| lexed.position(end).0).unwrap_or(row);
+            imports = Some((imports.map(|(start, _)| start).unwrap_or(row), end));
+            row = end + 1;
+            continue;
+        }
+        if !line.trim().is_empty() {
+            if let Some((start, end)) = imports.take() {
+                push(start, end, Some(FoldingRangeKind::Imports));
+            }
+        }
+        row += 1;
+    }
+    if let Some((start, end)) = imports {
+        push(start, end, Some(FoldingRangeKind::Imports));
+    }
+
+    // Runs of line comments, keeping doc comments and regular comments apart.
+    let mut run: Option<(usize, usize, bool)> = None;
+    for &(row, is_doc) in &lexed.line_comments {
+        run = match run {
+            Some((start, end, doc)) if end + 1 == row && doc == is_doc => Some((start, row, doc)),
+            Some((start, end, _)) => {
+                push(start, end, Some(FoldingRangeKind::Comment));
+                Some((row, row, is_doc))
+            }
+            None => Some((row, row, is_doc)),
+        };
+    }
+    if let Some((start, end, _)) = run {
+        push(start, end, Some(FoldingRangeKind::Comment));
+    }
+
+    for &(start, end) in &lexed.block_comments {
+        push(start, end, Some(FoldingRangeKind::Comment));
+    }
+
+    ranges.sort_by_key(|r| (r.start_line, r.end_line));
+    ranges.dedup_by_key(|r| (r.start_line, r.end_line));
+    ranges
+}
+
+fn is_import(line: &str) -> bool {
+    let mut line = line.trim_start();
+    if line.starts_with("pub(") {
+        line = match line.find(')') {
+            Some(i) => &line[i + 1..],
+            None => return false,
+        };
+    } else if line.starts_with("pub ") {
+        line = &line[4..];
+    }
+    let line = line.trim_start();
+    line.starts_with("use ") || line.starts_with("extern crate ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lines(ranges: &[FoldingRange]) -> Vec<(u64, u64, Option<FoldingRangeKind>)> {
+        ranges.iter().map(|r| (r.start_line, r.end_line, r.kind.clone())).collect()
+    }
+
+    #[test]
+    fn test_lexical_folding_ranges() {
+        let text = r#"use std::fmt;
+
+use foo::{
+    bar,
+    baz,
+};
+
+/// Some docs
+/// more docs
+// A regular comment
+// which goes on.
+struct Foo;
+
+/*
+ * A block comment.
+ */
+impl fmt::Display for Foo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{{ }")
+    }
+}
+
+#[cfg(test)]
+mod test {
+}
+"#;
+        let ranges = compute_folding_ranges(text, &[]);
+        assert_eq!(
+            lines(&ranges),
+            vec![
+                (0, 5, Some(FoldingRangeKind::Imports)),
+                (7, 8, Some(FoldingRangeKind::Comment)),
+                (9, 10, Some(FoldingRangeKind::Comment)),
+                (13, 15, Some(FoldingRangeKind::Comment)),
+                (16, 20, None),
+                (22, 24, Some(FoldingRangeKind::Region)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_item_folding_ranges() {
+        let text = "fn foo(x: [u8; 3]) {\n    let c = '}';\n}\n\nfn bar();\n";
+        let ranges = compute_folding_ranges(text, &[(0, 3), (4, 3)]);
+        assert_eq!(lines(&ranges), vec![(0, 2, None)]);
+    }
+}
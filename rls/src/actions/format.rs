@@ -14,6 +14,8 @@ use lsp_types::{Position, Range, TextEdit};
 use rand::{distributions, thread_rng, Rng};
 use rustfmt_nightly::{Config, Input, ModifiedLines, NewlineStyle, Session};
 
+use crate::actions::lexical::{is_balanced, Lexed};
+
 /// Specifies which `rustfmt` to use.
 #[derive(Clone)]
 pub enum Rustfmt {
@@ -128,6 +130,106 @@ impl Rustfmt {
     }
 }
 
+/// The range to format when `ch` has just been typed before `position`: the
+/// item or statement ended by a `;`, or the item, statement or expression
+/// whose block is closed by a `}`. Returns `None` if there is nothing to
+/// format, or if the brackets in the text don't match up (in which case the
+/// text won't parse anyway).
+pub fn on_type_range(text: &str, position: Position, ch: &str) -> Option<Range> {
+    let lexed = Lexed::new(text);
+    if !is_balanced(&lexed.code) {
+        return None;
+    }
+    let offset =
+        lexed.offset(position.line as usize, position.character as usize).checked_sub(1)?;
+
+    let start = match (ch, lexed.code[offset]) {
+        (";", ';') => lexed.statement_start(offset),
+        ("}", '}') => {
+            let mut depth = 0;
+            let open = (0..offset).rev().find(|&i| match lexed.code[i] {
+                '}' => {
+                    depth += 1;
+                    false
+                }
+                '{' if depth == 0 => true,
+                '{' => {
+                    depth -= 1;
+                    false
+                }
+                _ => false,
+            })?;
+            lexed.statement_start(open)
+        }
+        _ => return None,
+    };
+
+    let (start_row, start_col) = lexed.position(start);
+    let (end_row, end_col) = lexed.position(offset + 1);
+    Some(Range::new(
+        Position::new(start_row as u64, start_col as u64),
+        Position::new(end_row as u64, end_col as u64),
+    ))
+}
+
+/// Shrinks the edits computed by `Rustfmt::calc_text_edits`, which replace
+/// whole lines, to the characters which actually change. Edits which don't
+/// change anything are dropped.
+pub fn minimal_edits(text: &str, edits: Vec<TextEdit>) -> Vec<TextEdit> {
+    // The lines of the text, including their line endings.
+    let mut lines = vec![];
+    let mut line_start = 0;
+    for (i, c) in text.char_indices() {
+        if c == '\n' {
+            lines.push(&text[line_start..=i]);
+            line_start = i + 1;
+        }
+    }
+    if line_start < text.len() {
+        lines.push(&text[line_start..]);
+    }
+
+    edits
+        .into_iter()
+        .filter_map(|edit| {
+            let start_line = edit.range.start.line as usize;
+            let end_line = (edit.range.end.line as usize).min(lines.len());
+            let old: Vec<char> =
+                lines[start_line.min(end_line)..end_line].concat().chars().collect();
+            let new: Vec<char> = edit.new_text.chars().collect();
+
+            let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+            let suffix = old[prefix..]
+                .iter()
+                .rev()
+                .zip(new[prefix..].iter().rev())
+                .take_while(|(a, b)| a == b)
+                .count();
+            if prefix + suffix == old.len() && prefix + suffix == new.len() {
+                return None;
+            }
+
+            // Converts an offset into `old` to a position in the text.
+            let position = |offset: usize| {
+                let (mut line, mut character) = (edit.range.start.line, 0);
+                for &c in &old[..offset] {
+                    if c == '\n' {
+                        line += 1;
+                        character = 0;
+                    } else {
+                        character += 1;
+                    }
+                }
+                Position::new(line, character)
+            };
+            Some(TextEdit {
+                range: Range::new(position(prefix), position(old.len() - suffix)),
+                new_text: new[prefix..new.len() - suffix].iter().collect(),
+            })
+        })
+        .collect()
+}
+
 fn format_external(
     path: &PathBuf,
     cwd: &PathBuf,
@@ -264,6 +366,47 @@ mod tests {
         );
     }
 
+    #[test]
+    fn on_type_ranges() {
+        let text = "fn foo() {\n    bar( 1 ) ;\n    let s = \"}\";\n}\n";
+        let range = |line, character, ch| {
+            on_type_range(text, Position::new(line, character), ch)
+                .map(|r| (r.start.line, r.start.character, r.end.line, r.end.character))
+        };
+        assert_eq!(range(1, 14, ";"), Some((1, 4, 1, 14)));
+        assert_eq!(range(3, 1, "}"), Some((0, 0, 3, 1)));
+        assert_eq!(range(2, 14, "}"), None);
+        assert_eq!(range(1, 13, ";"), None);
+        assert_eq!(on_type_range("fn foo() {\n    bar(;\n}\n", Position::new(1, 9), ";"), None);
+    }
+
+    #[test]
+    fn minimize_text_edits() {
+        let text = "fn main() {\n    let x=1;\n    foo();\n}\n";
+        let edit = |start, end, new_text: &str| TextEdit {
+            range: Range { start: Position::new(start, 0), end: Position::new(end, 0) },
+            new_text: new_text.to_owned(),
+        };
+        let edits = vec![
+            edit(1, 2, "    let x = 1;\n"),
+            edit(2, 3, "    foo();\n"),
+            edit(1, 3, "    let x=1;\n"),
+        ];
+        assert_eq!(
+            minimal_edits(text, edits),
+            vec![
+                TextEdit {
+                    range: Range { start: Position::new(1, 9), end: Position::new(1, 10) },
+                    new_text: " = ".to_owned(),
+                },
+                TextEdit {
+                    range: Range { start: Position::new(2, 0), end: Position::new(3, 0) },
+                    new_text: "".to_owned(),
+                },
+            ]
+        );
+    }
+
     #[test]
     fn no_empty_file_lines() {
         let config_with_lines = {
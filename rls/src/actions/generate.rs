@@ -0,0 +1,433 @@
+//! Generating boilerplate for a struct: a constructor, getters and setters,
+//! and skeletons of `Default` and `Display` impls.
+//!
+//! The fields, and their types, are the children of the struct's definition
+//! in the save-analysis data. The generated code is formatted with Rustfmt.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use log::{debug, trace};
+use rls_analysis::DefKind;
+use rls_span::Column;
+use rls_vfs::FileContents;
+use rustfmt_nightly::Edition as RustfmtEdition;
+
+use crate::actions::lexical::{
+    contains_word, is_copy, skip_whitespace, top_level_uses, Lexed, OffsetRange,
+};
+use crate::actions::InitActionContext;
+use crate::build::Edition;
+use crate::lsp_data::*;
+
+/// The kinds of boilerplate which can be generated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Boilerplate {
+    New,
+    Getters,
+    Setters,
+    Default,
+    Display,
+}
+
+impl Boilerplate {
+    pub const ALL: [Boilerplate; 5] = [
+        Boilerplate::New,
+        Boilerplate::Getters,
+        Boilerplate::Setters,
+        Boilerplate::Default,
+        Boilerplate::Display,
+    ];
+
+    /// The name of the boilerplate, as passed to the `rls.generate` command.
+    pub fn name(self) -> &'static str {
+        match self {
+            Boilerplate::New => "new",
+            Boilerplate::Getters => "getters",
+            Boilerplate::Setters => "setters",
+            Boilerplate::Default => "default",
+            Boilerplate::Display => "display",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Boilerplate> {
+        Boilerplate::ALL.iter().cloned().find(|b| b.name() == name)
+    }
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Boilerplate::New => "Generate `new`",
+            Boilerplate::Getters => "Generate getters",
+            Boilerplate::Setters => "Generate setters",
+            Boilerplate::Default => "Generate `Default` impl",
+            Boilerplate::Display => "Generate `Display` impl",
+        }
+    }
+}
+
+/// A struct with named fields.
+#[derive(Debug)]
+struct Struct {
+    name: String,
+    /// The visibility of the struct, used for the generated methods.
+    vis: String,
+    /// The generic parameters, without defaults.
+    params: Vec<String>,
+    where_clause: Option<String>,
+    /// The names and types of the fields.
+    fields: Vec<(String, String)>,
+    /// The end of the struct definition.
+    end: usize,
+    indent: String,
+}
+
+impl Struct {
+    /// The start of an impl for the struct, e.g., `impl<T> Foo<T>` or
+    /// `impl<T> Default for Foo<T>`.
+    fn impl_header(&self, trait_name: Option<&str>) -> String {
+        let mut header = "impl".to_owned();
+        if !self.params.is_empty() {
+            header.push_str(&format!("<{}>", self.params.join(", ")));
+        }
+        if let Some(trait_name) = trait_name {
+            header.push_str(&format!(" {} for", trait_name));
+        }
+        header.push_str(&format!(" {}", self.name));
+        if !self.params.is_empty() {
+            let args: Vec<&str> =
+                self.params.iter().map(|p| p.split(':').next().unwrap_or(p).trim()).collect();
+            header.push_str(&format!("<{}>", args.join(", ")));
+        }
+        if let Some(ref where_clause) = self.where_clause {
+            header.push_str(&format!(" {}", where_clause));
+        }
+        header
+    }
+
+    fn vis_prefix(&self) -> String {
+        if self.vis.is_empty() {
+            String::new()
+        } else {
+            format!("{} ", self.vis)
+        }
+    }
+}
+
+/// Returns the kinds of boilerplate which can be generated for the struct at
+/// `location`.
+pub fn available(ctx: &InitActionContext, location: &Location) -> Vec<Boilerplate> {
+    let (_, text, strukt) = match load(ctx, location) {
+        Some(loaded) => loaded,
+        None => return vec![],
+    };
+    let implements =
+        |trait_name: &str| contains_word(&text, &format!("{} for {}", trait_name, strukt.name));
+    Boilerplate::ALL
+        .iter()
+        .cloned()
+        .filter(|b| match b {
+            Boilerplate::Getters | Boilerplate::Setters => !strukt.fields.is_empty(),
+            Boilerplate::Default => !implements("Default"),
+            Boilerplate::Display => !implements("Display"),
+            Boilerplate::New => true,
+        })
+        .collect()
+}
+
+/// Computes the edit which adds the boilerplate after the struct at
+/// `location`.
+pub fn generate(
+    ctx: &InitActionContext,
+    location: &Location,
+    boilerplate: Boilerplate,
+) -> Option<WorkspaceEdit> {
+    let (file_path, text, strukt) = load(ctx, location)?;
+    let lexed = Lexed::new(&text);
+    let chars: Vec<char> = text.chars().collect();
+    let fmt_path = if imports_fmt(&chars, &lexed) { "fmt" } else { "std::fmt" };
+
+    let config = ctx.fmt_config();
+    let mut config = config.get_rustfmt_config().clone();
+    let unit = if config.hard_tabs() { "\t".to_owned() } else { " ".repeat(config.tab_spaces()) };
+    let code = render(&strukt, boilerplate, fmt_path, &unit);
+
+    if !config.was_set().edition() {
+        if let Some(edition) = ctx.file_edition(file_path) {
+            config.set().edition(match edition {
+                Edition::Edition2015 => RustfmtEdition::Edition2015,
+                Edition::Edition2018 => RustfmtEdition::Edition2018,
+            });
+        }
+    }
+    let code = match ctx.formatter().format(code.clone(), config) {
+        Ok(formatted) => formatted,
+        Err(e) => {
+            debug!("generate: could not format the generated code: {:?}", e);
+            code
+        }
+    };
+    trace!("generate {:?}: {}", boilerplate, code);
+
+    let new_text = format!("\n\n{}", indent(code.trim_end(), &strukt.indent));
+    let (row, col) = lexed.position(strukt.end);
+    let position = Position::new(row as u64, col as u64);
+    let edit = TextEdit { range: Range::new(position, position), new_text };
+    let changes: HashMap<_, _> = vec![(location.uri.clone(), vec![edit])].into_iter().collect();
+    Some(WorkspaceEdit { changes: Some(changes), document_changes: None })
+}
+
+/// Loads the file, and the struct whose definition contains the start of
+/// `location`.
+fn load(ctx: &InitActionContext, location: &Location) -> Option<(PathBuf, String, Struct)> {
+    let span = ls_util::location_to_rls(location).ok()?;
+    let text = match ctx.vfs.load_file(&span.file) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return None,
+    };
+    let lexed = Lexed::new(&text);
+    let start = location.range.start;
+    let offset = lexed.offset(start.line as usize, start.character as usize);
+
+    // Look for the struct's name up to the end of the line, so that the
+    // action is available on the `struct` keyword too.
+    let mut line_end = span.clone();
+    let line_len = lexed.line(start.line as usize).chars().count() as u32;
+    line_end.range.row_end = line_end.range.row_start;
+    line_end.range.col_start = Column::new_zero_indexed(line_len);
+    line_end.range.col_end = Column::new_zero_indexed(line_len);
+    let (id, def, name, extent) = ctx
+        .analysis
+        .defs_preceding(&line_end)
+        .ok()?
+        .into_iter()
+        .filter(|(_, def)| def.kind == DefKind::Struct)
+        .find_map(|(id, def)| {
+            let range = def.span.range;
+            let name = (
+                lexed.offset(range.row_start.0 as usize, range.col_start.0 as usize),
+                lexed.offset(range.row_end.0 as usize, range.col_end.0 as usize),
+            );
+            let extent = lexed.item_extent(name.0)?;
+            if extent.0 <= offset && offset < extent.1 {
+                Some((id, def, name, extent))
+            } else {
+                None
+            }
+        })?;
+
+    let mut fields = ctx.analysis.for_each_child_def(id, |_, def| def.clone()).ok()?;
+    fields.retain(|def| def.kind == DefKind::Field);
+    fields.sort_by_key(|def| (def.span.range.row_start, def.span.range.col_start));
+    let fields: Vec<(String, String)> =
+        fields.into_iter().map(|def| (def.name, def.value)).collect();
+    // Tuple structs are not supported.
+    if fields.iter().any(|(name, _)| name.starts_with(|c: char| c.is_numeric())) {
+        return None;
+    }
+
+    let strukt = parse_struct(&lexed, def.name, name, extent, fields)?;
+    Some((span.file, text, strukt))
+}
+
+/// Reads the visibility, generic parameters and where clause of the struct
+/// whose name is at `name`.
+fn parse_struct(
+    lexed: &Lexed,
+    name_text: String,
+    name: OffsetRange,
+    extent: OffsetRange,
+    fields: Vec<(String, String)>,
+) -> Option<Struct> {
+    let code = &lexed.code;
+    let text = |start: usize, end: usize| -> String {
+        code[start..end].iter().collect::<String>().split_whitespace().collect::<Vec<_>>().join(" ")
+    };
+
+    // The visibility is between any attributes and the `struct` keyword.
+    let header = text(extent.0, name.0);
+    let header = header.rsplit(']').next().unwrap_or("").trim();
+    let vis = header.trim_end_matches("struct").trim().to_owned();
+
+    let body = (name.1..extent.1).find(|&i| code[i] == '{' || code[i] == ';' || code[i] == '(')?;
+    if code[body] == '(' {
+        return None;
+    }
+    let mut params = vec![];
+    let mut where_clause = None;
+    let mut i = skip_whitespace(code, name.1);
+    if code[i] == '<' {
+        let mut depth = 0;
+        let mut param_start = i + 1;
+        for (j, &c) in code.iter().enumerate().take(body).skip(i) {
+            match c {
+                '<' | '(' | '[' => depth += 1,
+                '>' | ')' | ']' => depth -= 1,
+                ',' if depth == 1 => {
+                    params.push(text(param_start, j));
+                    param_start = j + 1;
+                }
+                _ => {}
+            }
+            if depth == 0 {
+                params.push(text(param_start, j));
+                i = j + 1;
+                break;
+            }
+        }
+    }
+    // Defaults are not allowed in impls.
+    let params: Vec<String> = params
+        .into_iter()
+        .map(|p| p.split('=').next().unwrap_or("").trim().to_owned())
+        .filter(|p| !p.is_empty())
+        .collect();
+    let rest = text(i, body);
+    if rest.starts_with("where") {
+        where_clause = Some(rest.trim_end_matches(',').to_owned());
+    }
+
+    let row = lexed.position(extent.0).0;
+    let indent = lexed.line(row).chars().take_while(|c| c.is_whitespace()).collect();
+    Some(Struct { name: name_text, vis, params, where_clause, fields, end: extent.1, indent })
+}
+
+/// `true` if `std::fmt` is imported by the file.
+fn imports_fmt(chars: &[char], lexed: &Lexed) -> bool {
+    top_level_uses(chars, lexed)
+        .iter()
+        .any(|(_, text)| text == "use std::fmt;" || text.starts_with("use std::fmt::{self"))
+}
+
+/// Generates the code for the boilerplate, with `unit` as one level of
+/// indentation.
+fn render(strukt: &Struct, boilerplate: Boilerplate, fmt_path: &str, unit: &str) -> String {
+    let vis = strukt.vis_prefix();
+    let fields = &strukt.fields;
+    let methods: Vec<String> = match boilerplate {
+        Boilerplate::New => {
+            let params: Vec<String> =
+                fields.iter().map(|(name, ty)| format!("{}: {}", name, ty)).collect();
+            let names: Vec<&str> = fields.iter().map(|(name, _)| &**name).collect();
+            let init = if names.is_empty() {
+                "Self {}".to_owned()
+            } else {
+                format!("Self {{ {} }}", names.join(", "))
+            };
+            vec![format!("{}fn new({}) -> Self {{\n{}{}\n}}", vis, params.join(", "), unit, init)]
+        }
+        Boilerplate::Getters => fields
+            .iter()
+            .map(|(name, ty)| {
+                if is_copy(ty) {
+                    format!("{}fn {}(&self) -> {} {{\n{}self.{}\n}}", vis, name, ty, unit, name)
+                } else {
+                    format!("{}fn {}(&self) -> &{} {{\n{}&self.{}\n}}", vis, name, ty, unit, name)
+                }
+            })
+            .collect(),
+        Boilerplate::Setters => fields
+            .iter()
+            .map(|(name, ty)| {
+                format!(
+                    "{}fn set_{}(&mut self, {}: {}) {{\n{}self.{} = {};\n}}",
+                    vis, name, name, ty, unit, name, name
+                )
+            })
+            .collect(),
+        Boilerplate::Default => {
+            let init: Vec<String> =
+                fields.iter().map(|(name, _)| format!("{}: Default::default()", name)).collect();
+            let init = if init.is_empty() {
+                "Self {}".to_owned()
+            } else {
+                format!("Self {{ {} }}", init.join(", "))
+            };
+            vec![format!("fn default() -> Self {{\n{}{}\n}}", unit, init)]
+        }
+        Boilerplate::Display => vec![format!(
+            "fn fmt(&self, f: &mut {}::Formatter) -> {}::Result {{\n{}unimplemented!()\n}}",
+            fmt_path, fmt_path, unit
+        )],
+    };
+
+    let header = match boilerplate {
+        Boilerplate::Default => strukt.impl_header(Some("Default")),
+        Boilerplate::Display => strukt.impl_header(Some(&format!("{}::Display", fmt_path))),
+        _ => strukt.impl_header(None),
+    };
+    let methods: Vec<String> = methods.iter().map(|method| indent(method, unit)).collect();
+    format!("{} {{\n{}\n}}\n", header, methods.join("\n\n"))
+}
+
+/// Indents the non-empty lines of `code` by `indent`.
+fn indent(code: &str, indent: &str) -> String {
+    code.lines()
+        .map(|line| if line.is_empty() { line.to_owned() } else { format!("{}{}", indent, line) })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(text: &str) -> Struct {
+        let lexed = Lexed::new(text);
+        let start = text.find("Foo").unwrap();
+        let extent = lexed.item_extent(start).unwrap();
+        let fields = vec![("a".to_owned(), "Vec<T>".to_owned()), ("b".to_owned(), "u8".to_owned())];
+        parse_struct(&lexed, "Foo".to_owned(), (start, start + 3), extent, fields).unwrap()
+    }
+
+    #[test]
+    fn test_parse_struct() {
+        let strukt = parse("/// Docs.\n#[derive(Debug)]\npub(crate) struct Foo<'a, T: Clone = u8> where T: 'a {\n    a: Vec<T>,\n}\n");
+        assert_eq!(strukt.vis, "pub(crate)");
+        assert_eq!(strukt.params, vec!["'a", "T: Clone"]);
+        assert_eq!(strukt.where_clause, Some("where T: 'a".to_owned()));
+        assert_eq!(
+            strukt.impl_header(Some("Default")),
+            "impl<'a, T: Clone> Default for Foo<'a, T> where T: 'a"
+        );
+
+        let strukt = parse("mod m {\n    struct Foo {}\n}\n");
+        assert_eq!((strukt.vis.as_str(), strukt.indent.as_str()), ("", "    "));
+        assert_eq!(strukt.impl_header(None), "impl Foo");
+    }
+
+    #[test]
+    fn test_render() {
+        let strukt = parse("pub struct Foo<T> {\n    a: Vec<T>,\n    b: u8,\n}\n");
+        assert_eq!(
+            render(&strukt, Boilerplate::New, "fmt", "    "),
+            "impl<T> Foo<T> {
+    pub fn new(a: Vec<T>, b: u8) -> Self {
+        Self { a, b }
+    }
+}
+"
+        );
+        assert_eq!(
+            render(&strukt, Boilerplate::Getters, "fmt", "    "),
+            "impl<T> Foo<T> {
+    pub fn a(&self) -> &Vec<T> {
+        &self.a
+    }
+
+    pub fn b(&self) -> u8 {
+        self.b
+    }
+}
+"
+        );
+        assert_eq!(
+            render(&strukt, Boilerplate::Display, "std::fmt", "    "),
+            "impl<T> std::fmt::Display for Foo<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        unimplemented!()
+    }
+}
+"
+        );
+    }
+}
@@ -628,6 +628,7 @@ fn racer_match_to_def(ctx: &InitActionContext, m: &racer::Match) -> Option<Def>
             distro_crate: false,
             parent: None,
             docs,
+            sig: None,
         };
         trace!(
             "racer_match_to_def: Def {{ kind: {:?}, span: {:?}, name: {:?}, \
@@ -0,0 +1,193 @@
+//! Inlay hints: the inferred types of local variables without a type
+//! annotation, and the names of parameters at call sites.
+//!
+//! Locals and callees are taken from the save-analysis data, annotations and
+//! call arguments are found by a lexical scan of the source text.
+
+use log::trace;
+use rls_analysis::DefKind;
+use rls_vfs::FileContents;
+
+use crate::actions::lexical::{call_arguments, is_ident_char, span_to_offsets, Lexed, OffsetRange};
+use crate::actions::signature_help::{is_self_param, parameter_ranges};
+use crate::actions::InitActionContext;
+use crate::lsp_data::*;
+use crate::server::ResponseError;
+use crate::Span;
+
+/// Computes the inlay hints for the given range of a document.
+pub fn inlay_hints(
+    ctx: &InitActionContext,
+    params: &InlayHintsParams,
+) -> Result<Vec<InlayHint>, ResponseError> {
+    if !ctx.config.lock().unwrap().inlay_hints {
+        return Ok(vec![]);
+    }
+    let file_path = parse_file_path!(&params.text_document.uri, "inlay_hints")?;
+
+    let text = match ctx.vfs.load_file(&file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return Ok(vec![]),
+    };
+    let lexed = Lexed::new(&text);
+
+    let range = Span::from_range(ls_util::range_to_rls(params.range), file_path);
+    let mut hints = vec![];
+    for (span, id) in ctx.analysis.refs_within(&range).unwrap_or_else(|_| vec![]) {
+        let def = match ctx.analysis.get_def(id) {
+            Ok(def) => def,
+            Err(_) => continue,
+        };
+        let name = span_to_offsets(&lexed, &span);
+        let is_def = def.span == span;
+
+        match def.kind {
+            DefKind::Local if is_def && !def.value.trim().is_empty() => {
+                if let Some(offset) = type_hint_offset(&lexed, name) {
+                    hints.push((offset, format!(": {}", def.value.trim()), InlayHintKind::Type));
+                }
+            }
+            DefKind::Function | DefKind::Method if !is_def => {
+                if let Some(ref sig) = def.sig {
+                    for (offset, label) in parameter_hints(&text, &lexed, name, &sig.text) {
+                        hints.push((offset, label, InlayHintKind::Parameter));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    trace!("inlay_hints: {} hints in {:?}", hints.len(), params.range);
+
+    Ok(hints
+        .into_iter()
+        .map(|(offset, label, kind)| {
+            let (row, col) = lexed.position(offset);
+            InlayHint { position: Position::new(row as u64, col as u64), label, kind }
+        })
+        .collect())
+}
+
+/// Returns the offset at which to show the type of a local variable, i.e.,
+/// the end of its name, or `None` if the variable has a type annotation.
+fn type_hint_offset(lexed: &Lexed, name: OffsetRange) -> Option<usize> {
+    let code = &lexed.code;
+    let next = code[name.1..].iter().position(|c| !c.is_whitespace()).map(|i| name.1 + i);
+    match next {
+        Some(i) if code[i] == ':' && code.get(i + 1) != Some(&':') => None,
+        _ => Some(name.1),
+    }
+}
+
+/// Computes the parameter name hints for a call, given the callee name and
+/// its signature. Returns the offsets of the arguments with their labels.
+fn parameter_hints(
+    text: &str,
+    lexed: &Lexed,
+    name: OffsetRange,
+    sig: &str,
+) -> Vec<(usize, String)> {
+    let args = match call_arguments(lexed, name.1) {
+        Some(args) => args,
+        None => return vec![],
+    };
+
+    let mut params: Vec<&str> =
+        parameter_ranges(sig).into_iter().map(|(s, e)| &sig[s..e]).collect();
+    let is_method_call = lexed.code[..name.0]
+        .iter()
+        .rev()
+        .find(|c| !c.is_whitespace())
+        .map(|&c| c == '.')
+        .unwrap_or(false);
+    if is_method_call && params.first().map(|p| is_self_param(p)).unwrap_or(false) {
+        params.remove(0);
+    }
+
+    // Trim the arguments using the original text, so that literals (which
+    // are blanked out in the lexed code) are not mistaken for whitespace.
+    let chars: Vec<char> = text.chars().collect();
+    let args = args.into_iter().map(|(mut start, mut end)| {
+        while start < end && chars[start].is_whitespace() {
+            start += 1;
+        }
+        while end > start && chars[end - 1].is_whitespace() {
+            end -= 1;
+        }
+        (start, end)
+    });
+    args.filter(|&(start, end)| start < end)
+        .zip(params)
+        .filter_map(|((start, end), param)| {
+            let name = param_name(param)?;
+            let arg: String = chars[start..end].iter().collect();
+            if arg_matches_name(&arg, name) {
+                None
+            } else {
+                Some((start, format!("{}:", name)))
+            }
+        })
+        .collect()
+}
+
+/// The name bound by a parameter, or `None` if the parameter is a pattern or
+/// its name starts with an underscore.
+fn param_name(param: &str) -> Option<&str> {
+    let pat = param.splitn(2, ':').next()?.trim();
+    let pat = if pat.starts_with("mut ") { pat[4..].trim_start() } else { pat };
+    if pat.is_empty() || pat.starts_with('_') || !pat.chars().all(is_ident_char) {
+        return None;
+    }
+    Some(pat)
+}
+
+/// `true` if the argument is (a reference to) a variable or field with the
+/// same name as the parameter, in which case the hint would be noise.
+fn arg_matches_name(arg: &str, name: &str) -> bool {
+    let arg = arg.trim_start_matches(|c| c == '&' || c == '*').trim_start();
+    let arg = if arg.starts_with("mut ") { &arg[4..] } else { arg };
+    let last = arg.rsplit(|c| c == '.' || c == ':').next().unwrap_or(arg);
+    last == name
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn hints(text: &str, callee: &str, sig: &str) -> Vec<String> {
+        let lexed = Lexed::new(text);
+        let start = text.find(callee).unwrap();
+        parameter_hints(text, &lexed, (start, start + callee.len()), sig)
+            .into_iter()
+            .map(|(offset, label)| format!("{}@{}", label, offset))
+            .collect()
+    }
+
+    #[test]
+    fn test_parameter_hints() {
+        let sig = "fn foo(count: usize, (a, b): (u8, u8), name: &str, f: impl Fn(u8, u8))";
+        assert_eq!(
+            hints("foo(1 + 2, (3, 4), \"a, b\", |x, y| x)", "foo", sig),
+            vec!["count:@4", "name:@19", "f:@27"]
+        );
+        assert_eq!(hints("foo(count, x, &self.name)", "foo", sig), Vec::<String>::new());
+        assert_eq!(hints("let foo = 1;", "foo", sig), Vec::<String>::new());
+
+        let sig = "pub fn bar<T>(&mut self, value: T)";
+        assert_eq!(hints("x.bar::<u8>(42)", "bar", sig), vec!["value:@12"]);
+        assert_eq!(hints("Foo::bar(x, 42)", "bar", sig), vec!["value:@12"]);
+    }
+
+    #[test]
+    fn test_type_hint_offset() {
+        let text = "let x = 1; let y: u8 = 2; match a { Foo::B(z) => {} }";
+        let lexed = Lexed::new(text);
+        let offsets = |name: &str| {
+            let start = text.find(name).unwrap();
+            type_hint_offset(&lexed, (start, start + name.len()))
+        };
+        assert_eq!(offsets("x"), Some(5));
+        assert_eq!(offsets("y"), None);
+        assert_eq!(offsets("z"), Some(44));
+    }
+}
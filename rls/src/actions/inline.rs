@@ -0,0 +1,554 @@
+//! Inline local variable and inline function refactorings.
+//!
+//! The variable or function and its uses are taken from the save-analysis
+//! data. Whether inlining keeps the meaning of the code is checked by a
+//! lexical scan: an initializer or argument with side effects (a call) must
+//! be evaluated exactly once, and in the same order as before.
+
+use log::trace;
+use rls_analysis::DefKind;
+use rls_span::{Column, Row};
+use rls_vfs::FileContents;
+
+use crate::actions::lexical::{
+    call_arguments, contains_word, is_ident_char, is_write, span_to_offsets, workspace_edit, Lexed,
+    OffsetRange,
+};
+use crate::actions::signature_help::parameter_ranges;
+use crate::actions::InitActionContext;
+use crate::lsp_data::*;
+use crate::Span;
+
+/// A function which can be inlined, within the text of its file.
+struct Function {
+    name: String,
+    /// The parameters, with the uses of each in the body.
+    params: Vec<(String, Vec<OffsetRange>)>,
+    /// The body, without the braces.
+    body: OffsetRange,
+}
+
+/// Computes the edit which inlines the local variable at `location` into all
+/// of its uses and removes its definition. Returns `None` if there is no
+/// local variable at `location`, or an error explaining why it can't be
+/// inlined.
+pub fn inline_local(
+    ctx: &InitActionContext,
+    location: &Location,
+) -> Option<Result<WorkspaceEdit, String>> {
+    let span = ls_util::location_to_rls(location).ok()?;
+    let id = ctx.analysis.id(&span).ok()?;
+    let def = ctx.analysis.get_def(id).ok()?;
+    if def.kind != DefKind::Local {
+        return None;
+    }
+    // Function arguments don't have a `$` in their qualname.
+    if !def.qualname.contains('$') {
+        return Some(Err(format!("`{}` is a function argument", def.name)));
+    }
+
+    let (chars, lexed) = load(ctx, &def.span)?;
+    let name = span_to_offsets(&lexed, &def.span);
+    let refs = ctx.analysis.find_all_refs_by_id(id).ok()?;
+    let uses: Vec<_> = refs
+        .iter()
+        .filter(|span| **span != def.span && span.file == def.span.file)
+        .map(|span| span_to_offsets(&lexed, span))
+        .collect();
+
+    let result = local_edits(&chars, &lexed, name, &uses);
+    trace!("inline_local: {} -> {:?}", def.name, result);
+    Some(result.map(|edits| workspace_edit(&lexed, location, edits)))
+}
+
+/// Computes the edit which replaces the call at `location` with the body of
+/// the called function. Returns `None` if there is no call to a function at
+/// `location`, or an error explaining why it can't be inlined.
+pub fn inline_function(
+    ctx: &InitActionContext,
+    location: &Location,
+) -> Option<Result<WorkspaceEdit, String>> {
+    let span = ls_util::location_to_rls(location).ok()?;
+    let id = ctx.analysis.id(&span).ok()?;
+    let def = ctx.analysis.get_def(id).ok()?;
+    if def.kind != DefKind::Function || def.span == span {
+        return None;
+    }
+    let (chars, lexed) = load(ctx, &span)?;
+    let call = span_to_offsets(&lexed, &span);
+    call_arguments(&lexed, call.1)?;
+
+    if def.span.file != span.file {
+        return Some(Err(format!("`{}` is defined in another file", def.name)));
+    }
+    let function = match load_function(ctx, &lexed, &def.span) {
+        Ok(function) => function,
+        Err(message) => return Some(Err(message)),
+    };
+    let result = call_edit(&chars, &lexed, call, &function);
+    trace!("inline_function: {} -> {:?}", def.name, result);
+    Some(result.map(|edit| workspace_edit(&lexed, location, vec![edit])))
+}
+
+fn load(ctx: &InitActionContext, span: &Span) -> Option<(Vec<char>, Lexed)> {
+    match ctx.vfs.load_file(&span.file) {
+        Ok(FileContents::Text(text)) => Some((text.chars().collect(), Lexed::new(&text))),
+        _ => None,
+    }
+}
+
+/// Finds the parameters and body of the function named at `name`.
+fn load_function(ctx: &InitActionContext, lexed: &Lexed, name: &Span) -> Result<Function, String> {
+    let code = &lexed.code;
+    let name_offsets = span_to_offsets(lexed, name);
+    let fn_name: String = code[name_offsets.0..name_offsets.1].iter().collect();
+    let (start, end) =
+        lexed.item_extent(name_offsets.0).ok_or_else(|| format!("`{}` has no body", fn_name))?;
+    let header: String = code[start..name_offsets.0].iter().collect();
+    if header.split_whitespace().any(|word| word.starts_with("pub")) {
+        return Err(format!("`{}` is public, only private functions can be inlined", fn_name));
+    }
+    let open = (name_offsets.1..end)
+        .find(|&i| code[i] == '{')
+        .ok_or_else(|| format!("`{}` has no body", fn_name))?;
+
+    // The parameters, from the signature.
+    let sig: String = code[start..open].iter().collect();
+    let mut params = vec![];
+    for (s, e) in parameter_ranges(&sig) {
+        let param = &sig[s..e];
+        let param_name = param.splitn(2, ':').next().unwrap_or("").trim();
+        if param_name.is_empty() || !param_name.chars().all(is_ident_char) {
+            return Err(format!("`{}` has patterns or `mut` in its parameters", fn_name));
+        }
+        params.push((param_name.to_owned(), vec![]));
+    }
+
+    // The uses of the parameters in the body.
+    let (first_row, first_col) = lexed.position(open);
+    let (last_row, last_col) = lexed.position(end);
+    let body_span = Span::new(
+        Row::new_zero_indexed(first_row as u32),
+        Row::new_zero_indexed(last_row as u32),
+        Column::new_zero_indexed(first_col as u32),
+        Column::new_zero_indexed(last_col as u32),
+        name.file.clone(),
+    );
+    for (span, id) in ctx.analysis.refs_within(&body_span).unwrap_or_else(|_| vec![]) {
+        let def = match ctx.analysis.get_def(id) {
+            Ok(def) if def.kind == DefKind::Local && def.span != span => def,
+            _ => continue,
+        };
+        // Only the parameters are defined before the body.
+        if span_to_offsets(lexed, &def.span).0 > open {
+            continue;
+        }
+        if let Some((_, uses)) = params.iter_mut().find(|(name, _)| *name == def.name) {
+            uses.push(span_to_offsets(lexed, &span));
+        }
+    }
+
+    Ok(Function { name: fn_name, params, body: (open + 1, end - 1) })
+}
+
+/// Shrinks `start..end` so that it doesn't start or end with whitespace. This
+/// uses the original text, as literals are blanked out in the lexed code.
+fn trim(chars: &[char], mut start: usize, mut end: usize) -> OffsetRange {
+    while start < end && chars[start].is_whitespace() {
+        start += 1;
+    }
+    while end > start && chars[end - 1].is_whitespace() {
+        end -= 1;
+    }
+    (start, end)
+}
+
+/// `true` if evaluating the expression can't have side effects, i.e., it
+/// contains no function, method or macro calls.
+fn is_pure(code: &[char]) -> bool {
+    let mut prev = ' ';
+    for (i, &c) in code.iter().enumerate() {
+        let is_call = c == '(' && (is_ident_char(prev) || ")]>".contains(prev));
+        let is_macro = c == '!'
+            && is_ident_char(prev)
+            && code[i + 1..]
+                .iter()
+                .find(|c| !c.is_whitespace())
+                .map_or(false, |c| "([{".contains(*c));
+        if is_call || is_macro {
+            return false;
+        }
+        if !c.is_whitespace() {
+            prev = c;
+        }
+    }
+    true
+}
+
+/// `true` if the expression doesn't need parentheses when it is substituted
+/// into another expression: a path, literal, field access or call.
+fn is_atomic(chars: &[char], code: &[char]) -> bool {
+    let mut depth = 0;
+    let mut i = 0;
+    while i < code.len() {
+        match code[i] {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            // A literal, which is blanked out in the lexed code.
+            ' ' if !chars[i].is_whitespace() => {
+                while i + 1 < code.len() && code[i + 1] == ' ' {
+                    i += 1;
+                }
+            }
+            c if depth > 0 || is_ident_char(c) || c == '.' || c == ':' => {}
+            _ => return false,
+        }
+        i += 1;
+    }
+    true
+}
+
+/// `true` if the expression at `range` is a whole argument, initializer,
+/// statement or field, so that it can be replaced without parentheses.
+fn is_standalone(code: &[char], range: OffsetRange) -> bool {
+    let before = code[..range.0].iter().rev().position(|c| !c.is_whitespace());
+    let before = before.map(|i| range.0 - 1 - i);
+    let prev = before.map(|i| code[i]).unwrap_or(';');
+    let next = code[range.1..].iter().find(|c| !c.is_whitespace()).cloned().unwrap_or(';');
+    let is_assignment =
+        prev == '=' && before.map_or(false, |i| i == 0 || !"=!<>+-*/%&|^".contains(code[i - 1]));
+    (is_assignment || "(,{;[".contains(prev)) && ");,}]".contains(next)
+}
+
+/// `true` if the variable at `range` is a shorthand field initializer, e.g.,
+/// `Foo { x, y: 1 }`.
+fn is_shorthand_field(code: &[char], range: OffsetRange) -> bool {
+    let prev = code[..range.0].iter().rev().find(|c| !c.is_whitespace());
+    let next = code[range.1..].iter().find(|c| !c.is_whitespace());
+    let mut depth = 0;
+    let opening = code[..range.0].iter().rev().find(|&&c| {
+        match c {
+            ')' | ']' | '}' => depth += 1,
+            '(' | '[' | '{' if depth > 0 => depth -= 1,
+            '(' | '[' | '{' => return true,
+            _ => {}
+        }
+        false
+    });
+    opening == Some(&'{')
+        && (prev == Some(&',') || next == Some(&','))
+        && (prev == Some(&',') || prev == Some(&'{'))
+        && (next == Some(&',') || next == Some(&'}'))
+}
+
+/// Computes the edits which inline the variable defined at `name`, i.e.,
+/// remove its `let` statement and replace its uses with its initializer.
+fn local_edits(
+    chars: &[char],
+    lexed: &Lexed,
+    name: OffsetRange,
+    uses: &[OffsetRange],
+) -> Result<Vec<(OffsetRange, String)>, String> {
+    let code = &lexed.code;
+    let var: String = chars[name.0..name.1].iter().collect();
+    let start = lexed.statement_start(name.0);
+    let keyword: String = code[start..name.0].iter().collect();
+    match keyword.split_whitespace().collect::<Vec<_>>()[..] {
+        ["let"] => {}
+        ["let", "mut"] => return Err(format!("`{}` is mutable", var)),
+        _ => return Err(format!("`{}` is not bound by a simple `let` statement", var)),
+    }
+    let end = lexed.statement_end(name.0).ok_or_else(|| format!("`{}` is not initialized", var))?;
+
+    // Skip the type annotation, if any, up to the `=`.
+    let mut depth = 0;
+    let eq = (name.1..end).find(|&i| match code[i] {
+        '(' | '[' | '<' => {
+            depth += 1;
+            false
+        }
+        ')' | ']' => {
+            depth -= 1;
+            false
+        }
+        '>' if code[i - 1] != '-' => {
+            depth -= 1;
+            false
+        }
+        '=' => depth == 0,
+        _ => false,
+    });
+    let eq = eq.ok_or_else(|| format!("`{}` is not initialized", var))?;
+    let init = trim(chars, eq + 1, end);
+    if init.0 == init.1 {
+        return Err(format!("`{}` is not initialized", var));
+    }
+    let init_text: String = chars[init.0..init.1].iter().collect();
+    let init_code = &code[init.0..init.1];
+
+    let last_use = uses.iter().map(|&(_, e)| e).max().unwrap_or(end);
+    if !is_pure(init_code) {
+        if uses.len() != 1 {
+            return Err(format!(
+                "`{}` has side effects, inlining `{}` would evaluate it {} times",
+                init_text,
+                var,
+                uses.len()
+            ));
+        }
+        let between = &code[end + 1..uses[0].0];
+        if between.iter().any(|&c| c == ';' || c == '{' || c == '}' || c == '|') {
+            return Err(format!(
+                "`{}` has side effects, inlining `{}` would change when they happen",
+                init_text, var
+            ));
+        }
+    }
+    // The variables read by the initializer must not change before the uses.
+    let mut i = init.0;
+    while i < init.1 {
+        if !is_ident_char(code[i]) || (i > init.0 && is_ident_char(code[i - 1])) {
+            i += 1;
+            continue;
+        }
+        let word_start = i;
+        while i < init.1 && is_ident_char(code[i]) {
+            i += 1;
+        }
+        let word = &code[word_start..i];
+        let mut j = end;
+        while j + word.len() <= last_use {
+            let is_word = &code[j..j + word.len()] == word
+                && !is_ident_char(code[j - 1])
+                && code.get(j + word.len()).map_or(true, |&c| !is_ident_char(c));
+            if is_word && is_write(code, (j, j + word.len())) {
+                let word: String = word.iter().collect();
+                return Err(format!("`{}` is modified before a use of `{}`", word, var));
+            }
+            j += 1;
+        }
+    }
+
+    // Remove the statement, with its line if there is nothing else on it.
+    let (start_row, _) = lexed.position(start);
+    let line_start = lexed.offset(start_row, 0);
+    let after = (end + 1..code.len())
+        .find(|&i| !code[i].is_whitespace() || code[i] == '\n')
+        .unwrap_or(code.len());
+    let removed = if code[line_start..start].iter().all(|c| c.is_whitespace())
+        && (after == code.len() || code[after] == '\n')
+    {
+        (line_start, (after + 1).min(code.len()))
+    } else {
+        (start, after)
+    };
+
+    let needs_parens = !is_atomic(&chars[init.0..init.1], init_code);
+    let mut edits = vec![(removed, String::new())];
+    for &range in uses {
+        let new_text = if is_shorthand_field(code, range) {
+            format!("{}: {}", var, init_text)
+        } else if needs_parens && !is_standalone(code, range) {
+            format!("({})", init_text)
+        } else {
+            init_text.clone()
+        };
+        edits.push((range, new_text));
+    }
+    Ok(edits)
+}
+
+/// Computes the edit which replaces the call whose callee name is at `name`
+/// with the body of `function`.
+fn call_edit(
+    chars: &[char],
+    lexed: &Lexed,
+    name: OffsetRange,
+    function: &Function,
+) -> Result<(OffsetRange, String), String> {
+    let code = &lexed.code;
+    let fn_name = &function.name;
+    let args =
+        call_arguments(lexed, name.1).ok_or_else(|| format!("`{}` is not called", fn_name))?;
+    let args: Vec<OffsetRange> =
+        args.into_iter().map(|(s, e)| trim(chars, s, e)).filter(|&(s, e)| s < e).collect();
+    if args.len() != function.params.len() {
+        return Err(format!("The arguments don't match the parameters of `{}`", fn_name));
+    }
+
+    let body = trim(chars, function.body.0, function.body.1);
+    let body_code: String = code[body.0..body.1].iter().collect();
+    if contains_word(&body_code, "return") || body_code.contains('?') {
+        return Err(format!("`{}` returns early", fn_name));
+    }
+    if body_code.contains(';') || contains_word(&body_code, "let") || body.0 == body.1 {
+        return Err(format!("The body of `{}` is not a single expression", fn_name));
+    }
+    if contains_word(&body_code, "self") || contains_word(&body_code, "Self") {
+        return Err(format!("`{}` refers to `self`", fn_name));
+    }
+
+    // Arguments with side effects must be evaluated exactly once, and in the
+    // same order.
+    let mut order = vec![];
+    for ((param, uses), &(s, e)) in function.params.iter().zip(&args) {
+        if is_pure(&code[s..e]) {
+            continue;
+        }
+        let arg: String = chars[s..e].iter().collect();
+        if uses.len() != 1 {
+            return Err(format!(
+                "`{}` has side effects, inlining `{}` would evaluate it {} times",
+                arg,
+                fn_name,
+                uses.len()
+            ));
+        }
+        order.push((uses[0].0, param));
+    }
+    if order.windows(2).any(|w| w[0].0 > w[1].0) {
+        return Err(format!(
+            "Inlining `{}` would change the order in which its arguments are evaluated",
+            fn_name
+        ));
+    }
+
+    // Substitute the arguments into the body.
+    let mut substitutions: Vec<(OffsetRange, String)> = vec![];
+    for ((_, uses), &(s, e)) in function.params.iter().zip(&args) {
+        let arg: String = chars[s..e].iter().collect();
+        for &range in uses {
+            let new_text = if !is_atomic(&chars[s..e], &code[s..e]) && !is_standalone(code, range) {
+                format!("({})", arg)
+            } else {
+                arg.clone()
+            };
+            substitutions.push((range, new_text));
+        }
+    }
+    substitutions.sort_by_key(|&(range, _)| range);
+    let mut new_text = String::new();
+    let mut last = body.0;
+    for ((s, e), text) in substitutions {
+        new_text.extend(&chars[last..s]);
+        new_text.push_str(&text);
+        last = e;
+    }
+    new_text.extend(&chars[last..body.1]);
+
+    // The whole call, including any path before the name.
+    let mut start = name.0;
+    while start >= 2 && code[start - 2..start] == [':', ':'] {
+        let segment = code[..start - 2].iter().rev().take_while(|&&c| is_ident_char(c)).count();
+        if segment == 0 {
+            break;
+        }
+        start -= 2 + segment;
+    }
+    let close = call_arguments(lexed, name.1).and_then(|args| args.last().map(|&(_, e)| e));
+    let call = (start, close.map_or(name.1, |e| e + 1));
+
+    let is_body_atomic = is_atomic(&chars[body.0..body.1], &code[body.0..body.1]);
+    if !is_body_atomic && !is_standalone(code, call) {
+        new_text = format!("({})", new_text);
+    }
+    Ok((call, new_text))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Finds the whole-word occurrences of `word` in `text`.
+    fn occurrences(text: &str, word: &str) -> Vec<OffsetRange> {
+        text.match_indices(word)
+            .filter(|(i, _)| {
+                !text[..*i].ends_with(is_ident_char)
+                    && !text[i + word.len()..].starts_with(is_ident_char)
+            })
+            .map(|(i, _)| (i, i + word.len()))
+            .collect()
+    }
+
+    fn apply(text: &str, mut edits: Vec<(OffsetRange, String)>) -> String {
+        let mut text = text.to_owned();
+        edits.sort_by_key(|&(range, _)| range);
+        for ((start, end), new_text) in edits.into_iter().rev() {
+            text.replace_range(start..end, &new_text);
+        }
+        text
+    }
+
+    fn inline_local(text: &str, var: &str) -> Result<String, String> {
+        let chars: Vec<char> = text.chars().collect();
+        let lexed = Lexed::new(text);
+        let occurrences = occurrences(text, var);
+        local_edits(&chars, &lexed, occurrences[0], &occurrences[1..])
+            .map(|edits| apply(text, edits))
+    }
+
+    #[test]
+    fn test_inline_local() {
+        let text =
+            "fn f(a: u8, b: u8) {\n    let x = a + b;\n    foo(x, x * 2, Foo { y, x });\n}\n";
+        assert_eq!(
+            inline_local(text, "x").unwrap(),
+            "fn f(a: u8, b: u8) {\n    foo(a + b, (a + b) * 2, Foo { y, x: a + b });\n}\n"
+        );
+
+        let text = "let s: &str = \"a b\"; let t = s.len(); bar(t);";
+        assert_eq!(inline_local(text, "s").unwrap(), "let t = \"a b\".len(); bar(t);");
+        assert_eq!(inline_local(text, "t").unwrap(), "let s: &str = \"a b\"; bar(s.len());");
+    }
+
+    #[test]
+    fn test_inline_local_refused() {
+        assert!(inline_local("let mut x = 1; x += 1; foo(x);", "x").is_err());
+        assert!(inline_local("let x = bar(); foo(x); foo(x);", "x").is_err());
+        assert!(inline_local("let x = bar(); baz(); foo(x);", "x").is_err());
+        assert!(inline_local("let x = a; a = 2; foo(x);", "x").is_err());
+        assert!(inline_local("let (x, y) = (1, 2); foo(x);", "x").is_err());
+    }
+
+    fn inline_call(text: &str, callee: &str, params: &[&str]) -> Result<String, String> {
+        let chars: Vec<char> = text.chars().collect();
+        let lexed = Lexed::new(text);
+        let def = occurrences(text, callee)[0];
+        let open = text[def.1..].find('{').unwrap() + def.1;
+        let close = lexed.block_end(def.0).unwrap();
+        let params = params
+            .iter()
+            .map(|&param| {
+                let uses = occurrences(text, param).into_iter();
+                let uses = uses.filter(|&(s, _)| open < s && s < close).collect();
+                (param.to_owned(), uses)
+            })
+            .collect();
+        let function = Function { name: callee.to_owned(), params, body: (open + 1, close) };
+        let call = *occurrences(text, callee).last().unwrap();
+        call_edit(&chars, &lexed, call, &function).map(|edit| apply(text, vec![edit]))
+    }
+
+    #[test]
+    fn test_inline_function() {
+        let text =
+            "fn double(x: u32) -> u32 { x * 2 }\nfn f() { let y = self::double(a + 1) + 1; }";
+        assert_eq!(
+            inline_call(text, "double", &["x"]).unwrap(),
+            "fn double(x: u32) -> u32 { x * 2 }\nfn f() { let y = ((a + 1) * 2) + 1; }"
+        );
+
+        let text = "fn get(v: &[u8], i: usize) -> u8 { v[i] }\nfn f() { foo(get(&v, next())); }";
+        assert_eq!(
+            inline_call(text, "get", &["v", "i"]).unwrap(),
+            "fn get(v: &[u8], i: usize) -> u8 { v[i] }\nfn f() { foo((&v)[next()]); }"
+        );
+
+        let text = "fn twice(x: u32) -> u32 { x + x }\nfn f() { twice(next()); }";
+        assert!(inline_call(text, "twice", &["x"]).is_err());
+        let text = "fn sub(a: u32, b: u32) -> u32 { b - a }\nfn f() { sub(g(), h()); }";
+        assert!(inline_call(text, "sub", &["a", "b"]).is_err());
+        let text = "fn g(a: u32) -> u32 { let b = a; b }\nfn f() { g(1); }";
+        assert!(inline_call(text, "g", &["a"]).is_err());
+    }
+}
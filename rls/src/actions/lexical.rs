@@ -0,0 +1,656 @@
+//! A lightweight lexical scan of Rust source text.
+//!
+//! This does not attempt to tokenize Rust properly, it only knows enough to
+//! tell code apart from comments and literals, so that brackets and
+//! punctuation can be matched up without a parser (or a finished build).
+//!
+//! The helpers at the end of this module are shared by the actions which edit
+//! the lexed text.
+
+use std::collections::HashMap;
+use std::iter;
+use std::path::{Path, PathBuf};
+
+use rls_span::{Column, Row};
+use rls_vfs::{FileContents, Vfs};
+
+use crate::lsp_data::{Location, Position, Range, TextEdit, WorkspaceEdit};
+use crate::Span;
+
+/// The result of a lexical scan over some source text.
+pub struct Lexed {
+    /// The characters of the source text, with comments and the contents of
+    /// string and char literals replaced by spaces (newlines are kept).
+    pub code: Vec<char>,
+    /// Line comments which take up a whole line, with `true` if the comment
+    /// is a doc comment.
+    pub line_comments: Vec<(usize, bool)>,
+    /// The first and last lines of block comments.
+    pub block_comments: Vec<(usize, usize)>,
+    /// The offsets at which each line starts.
+    line_starts: Vec<usize>,
+}
+
+/// A range of char offsets into the text, the end is exclusive.
+pub type OffsetRange = (usize, usize);
+
+impl Lexed {
+    pub fn new(text: &str) -> Lexed {
+        let chars: Vec<char> = text.chars().collect();
+        let mut code = Vec::with_capacity(chars.len());
+        let mut line_comments = vec![];
+        let mut block_comments = vec![];
+
+        let mut row = 0;
+        // Whether the current line contains only whitespace so far.
+        let mut blank = true;
+        let mut i = 0;
+
+        // Blanks out `chars[start..end]`, keeping any newlines.
+        let blank_out = |code: &mut Vec<char>, row: &mut usize, start: usize, end: usize| {
+            for &c in &chars[start..end] {
+                if c == '\n' {
+                    *row += 1;
+                    code.push('\n');
+                } else {
+                    code.push(' ');
+                }
+            }
+        };
+
+        while i < chars.len() {
+            let c = chars[i];
+            let next = chars.get(i + 1).cloned();
+
+            if c == '/' && next == Some('/') {
+                let start = i;
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+                if blank {
+                    let comment: String = chars[start..i].iter().collect();
+                    let is_doc = (comment.starts_with("///") && !comment.starts_with("////"))
+                        || comment.starts_with("//!");
+                    line_comments.push((row, is_doc));
+                }
+                code.extend(iter::repeat(' ').take(i - start));
+                continue;
+            }
+
+            if c == '/' && next == Some('*') {
+                let (start, start_row) = (i, row);
+                let mut depth = 0;
+                while i < chars.len() {
+                    if chars[i] == '/' && chars.get(i + 1) == Some(&'*') {
+                        depth += 1;
+                        i += 2;
+                    } else if chars[i] == '*' && chars.get(i + 1) == Some(&'/') {
+                        depth -= 1;
+                        i += 2;
+                        if depth == 0 {
+                            break;
+                        }
+                    } else {
+                        i += 1;
+                    }
+                }
+                let end = i.min(chars.len());
+                blank_out(&mut code, &mut row, start, end);
+                if row > start_row {
+                    block_comments.push((start_row, row));
+                    blank = false;
+                }
+                continue;
+            }
+
+            if c == '"' {
+                let start = i;
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    i += if chars[i] == '\\' { 2 } else { 1 };
+                }
+                let end = (i + 1).min(chars.len());
+                blank_out(&mut code, &mut row, start, end);
+                i = end;
+                blank = false;
+                continue;
+            }
+
+            if let Some(hashes) = raw_string_start(&chars, i) {
+                let start = i;
+                let terminator: Vec<char> =
+                    iter::once('"').chain(iter::repeat('#').take(hashes)).collect();
+                i += hashes + 2;
+                while i < chars.len() && !chars[i..].starts_with(&terminator) {
+                    i += 1;
+                }
+                let end = (i + terminator.len()).min(chars.len());
+                blank_out(&mut code, &mut row, start, end);
+                i = end;
+                blank = false;
+                continue;
+            }
+
+            if c == '\'' {
+                // Distinguish char literals from lifetimes.
+                let end = if next == Some('\\') {
+                    chars
+                        .get(i + 3..)
+                        .and_then(|rest| rest.iter().position(|&c| c == '\''))
+                        .map(|p| i + 4 + p)
+                } else if chars.get(i + 2) == Some(&'\'') {
+                    Some(i + 3)
+                } else {
+                    None
+                };
+                if let Some(end) = end {
+                    blank_out(&mut code, &mut row, i, end);
+                    i = end;
+                    blank = false;
+                    continue;
+                }
+            }
+
+            if c == '\n' {
+                row += 1;
+                blank = true;
+            } else if !c.is_whitespace() {
+                blank = false;
+            }
+            code.push(c);
+            i += 1;
+        }
+
+        let line_starts = iter::once(0)
+            .chain(code.iter().enumerate().filter(|&(_, &c)| c == '\n').map(|(i, _)| i + 1))
+            .collect();
+
+        Lexed { code, line_comments, block_comments, line_starts }
+    }
+
+    /// The number of lines in the text.
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// The code on line `row`, without the trailing newline.
+    pub fn line(&self, row: usize) -> String {
+        let start = self.line_starts[row];
+        let end = self.line_starts.get(row + 1).map(|&end| end - 1).unwrap_or(self.code.len());
+        self.code[start..end].iter().collect()
+    }
+
+    /// Converts a (zero-indexed) row and char column to an offset into
+    /// `code`. Columns past the end of a line are clamped to its end.
+    pub fn offset(&self, row: usize, col: usize) -> usize {
+        match self.line_starts.get(row) {
+            Some(&start) => {
+                let end = self.line_starts.get(row + 1).map(|&end| end - 1);
+                let end = end.unwrap_or(self.code.len());
+                (start + col).min(end)
+            }
+            None => self.code.len(),
+        }
+    }
+
+    /// Converts an offset into `code` to a (zero-indexed) row and column.
+    pub fn position(&self, offset: usize) -> (usize, usize) {
+        let row = match self.line_starts.binary_search(&offset) {
+            Ok(row) => row,
+            Err(row) => row - 1,
+        };
+        (row, offset - self.line_starts[row])
+    }
+
+    /// Finds the closing brace of the block of the item or expression
+    /// starting at `offset`. Returns `None` if there is no block, i.e., a
+    /// semicolon or the end of the enclosing block is found first.
+    pub fn block_end(&self, offset: usize) -> Option<usize> {
+        let mut depth = 0;
+        let mut in_block = false;
+        for (i, &c) in self.code.iter().enumerate().skip(offset) {
+            match c {
+                '(' | '[' => depth += 1,
+                '{' => {
+                    in_block = true;
+                    depth += 1;
+                }
+                ')' | ']' | '}' => {
+                    depth -= 1;
+                    if depth < 0 {
+                        return None;
+                    }
+                    if c == '}' && in_block && depth == 0 {
+                        return Some(i);
+                    }
+                }
+                ';' if depth == 0 && !in_block => return None,
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Scans backwards from `offset` for the start of the statement or item
+    /// containing it.
+    pub fn statement_start(&self, offset: usize) -> usize {
+        let mut depth = 0;
+        let mut start = 0;
+        for i in (0..offset).rev() {
+            match self.code[i] {
+                ';' | '}' | '{' | '(' | '[' if depth == 0 => {
+                    start = i + 1;
+                    break;
+                }
+                ')' | ']' | '}' => depth += 1,
+                '(' | '[' | '{' => depth -= 1,
+                _ => {}
+            }
+        }
+        self.trim(start, offset).0
+    }
+
+    /// The extent of an item, given the offset of its name; from the start of
+    /// its header (including attributes) to the end of its body. The end is
+    /// exclusive.
+    pub fn item_extent(&self, name: usize) -> Option<(usize, usize)> {
+        let start = self.statement_start(name);
+        let end = self.block_end(name).or_else(|| self.statement_end(name))?;
+        Some((start, end + 1))
+    }
+
+    /// Shrinks `start..end` so that it doesn't start or end with whitespace.
+    pub fn trim(&self, mut start: usize, mut end: usize) -> (usize, usize) {
+        while start < end && self.code[start].is_whitespace() {
+            start += 1;
+        }
+        while end > start && self.code[end - 1].is_whitespace() {
+            end -= 1;
+        }
+        (start, end)
+    }
+
+    /// Finds the semicolon ending the statement starting at `offset`.
+    pub fn statement_end(&self, offset: usize) -> Option<usize> {
+        let mut depth = 0;
+        for (i, &c) in self.code.iter().enumerate().skip(offset) {
+            match c {
+                '(' | '[' | '{' => depth += 1,
+                ')' | ']' | '}' => depth -= 1,
+                ';' if depth == 0 => return Some(i),
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+/// Source files, loaded from the VFS and scanned on demand.
+pub struct SourceFiles<'a> {
+    vfs: &'a Vfs,
+    files: HashMap<PathBuf, Option<Lexed>>,
+}
+
+impl<'a> SourceFiles<'a> {
+    pub fn new(vfs: &'a Vfs) -> SourceFiles<'a> {
+        SourceFiles { vfs, files: HashMap::new() }
+    }
+
+    /// Returns the scanned file, or `None` if it could not be loaded.
+    pub fn lexed(&mut self, path: &Path) -> Option<&Lexed> {
+        let vfs = self.vfs;
+        self.files
+            .entry(path.to_owned())
+            .or_insert_with(|| match vfs.load_file(path) {
+                Ok(FileContents::Text(text)) => Some(Lexed::new(&text)),
+                _ => None,
+            })
+            .as_ref()
+    }
+
+    /// Returns the offset in its file of the start of `span`.
+    pub fn offset(&mut self, span: &Span) -> Option<usize> {
+        let range = span.range;
+        let lexed = self.lexed(&span.file)?;
+        Some(lexed.offset(range.row_start.0 as usize, range.col_start.0 as usize))
+    }
+
+    /// The extent of an item as offsets into its file, given the span of its
+    /// name. See `Lexed::item_extent`.
+    pub fn item_extent(&mut self, name: &Span) -> Option<(usize, usize)> {
+        let offset = self.offset(name)?;
+        self.lexed(&name.file)?.item_extent(offset)
+    }
+
+    /// The extent of an item as a span, given the span of its name.
+    pub fn item_span(&mut self, name: &Span) -> Option<Span> {
+        let (start, end) = self.item_extent(name)?;
+        let lexed = self.lexed(&name.file)?;
+        let (start, end) = (lexed.position(start), lexed.position(end));
+        Some(Span::new(
+            Row::new_zero_indexed(start.0 as u32),
+            Row::new_zero_indexed(end.0 as u32),
+            Column::new_zero_indexed(start.1 as u32),
+            Column::new_zero_indexed(end.1 as u32),
+            name.file.clone(),
+        ))
+    }
+}
+
+/// `true` if the brackets in some (lexed) code match up.
+pub fn is_balanced(code: &[char]) -> bool {
+    let mut stack = vec![];
+    for &c in code {
+        match c {
+            '(' => stack.push(')'),
+            '[' => stack.push(']'),
+            '{' => stack.push('}'),
+            ')' | ']' | '}' if stack.pop() != Some(c) => return false,
+            _ => {}
+        }
+    }
+    stack.is_empty()
+}
+
+/// Builds a workspace edit which makes the given edits to the (lexed) text
+/// of the document at `location`.
+pub fn workspace_edit(
+    lexed: &Lexed,
+    location: &Location,
+    edits: Vec<(OffsetRange, String)>,
+) -> WorkspaceEdit {
+    let position = |offset| {
+        let (row, col) = lexed.position(offset);
+        Position::new(row as u64, col as u64)
+    };
+    let edits = edits
+        .into_iter()
+        .map(|((start, end), new_text)| TextEdit {
+            range: Range::new(position(start), position(end)),
+            new_text,
+        })
+        .collect();
+    let changes: HashMap<_, _> = vec![(location.uri.clone(), edits)].into_iter().collect();
+    WorkspaceEdit { changes: Some(changes), document_changes: None }
+}
+
+/// The offsets of `span` in the (lexed) text of its file.
+pub fn span_to_offsets(lexed: &Lexed, span: &Span) -> OffsetRange {
+    let range = span.range;
+    (
+        lexed.offset(range.row_start.0 as usize, range.col_start.0 as usize),
+        lexed.offset(range.row_end.0 as usize, range.col_end.0 as usize),
+    )
+}
+
+pub fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Returns the first offset at or after `i` that is not whitespace, or
+/// `code.len()` if there is none.
+pub fn skip_whitespace(code: &[char], mut i: usize) -> usize {
+    while i < code.len() && code[i].is_whitespace() {
+        i += 1;
+    }
+    i
+}
+
+/// `true` if the variable whose name starts at `offset` is declared `mut`,
+/// e.g., `let mut x` or `(mut a, b)`. A `&mut x` pattern binds an immutable
+/// `x`, so it is not.
+pub fn is_mut(code: &[char], offset: usize) -> bool {
+    let last_non_space = |end: usize| code[..end].iter().rposition(|c| !c.is_whitespace());
+    let end = match last_non_space(offset) {
+        Some(i) => i + 1,
+        None => return false,
+    };
+    let is_keyword = end >= 3
+        && code[end - 3..end] == ['m', 'u', 't']
+        && (end == 3 || !is_ident_char(code[end - 4]));
+    is_keyword && last_non_space(end - 3).map(|i| code[i] != '&').unwrap_or(true)
+}
+
+/// `true` if `word` occurs in `code` as a whole word.
+pub fn contains_word(code: &str, word: &str) -> bool {
+    code.match_indices(word).any(|(i, _)| {
+        !code[..i].ends_with(is_ident_char) && !code[i + word.len()..].starts_with(is_ident_char)
+    })
+}
+
+/// `true` if the use of a variable at `name` assigns to it or borrows it
+/// mutably, including through a field.
+pub fn is_write(code: &[char], name: OffsetRange) -> bool {
+    let before: String = code[name.0.saturating_sub(16)..name.0].iter().collect();
+    if before.trim_end().ends_with("&mut") {
+        return true;
+    }
+
+    let after: String = code[name.1..].iter().take(256).collect();
+    let mut after = after.trim_start();
+    // Skip field accesses, but not method calls.
+    while after.starts_with('.') {
+        let field = after[1..].trim_start();
+        let len = field.find(|c| !is_ident_char(c)).unwrap_or(field.len());
+        if len == 0 || field[len..].trim_start().starts_with(|c| c == '(' || c == ':') {
+            return false;
+        }
+        after = field[len..].trim_start();
+    }
+    let is_assignment =
+        after.starts_with('=') && !after.starts_with("==") && !after.starts_with("=>");
+    is_assignment
+        || ["+=", "-=", "*=", "/=", "%=", "^=", "&=", "|=", "<<=", ">>="]
+            .iter()
+            .any(|op| after.starts_with(op))
+}
+
+/// `true` if values of the type are (probably) `Copy`, and can be passed by
+/// value without moving them.
+pub fn is_copy(ty: &str) -> bool {
+    const PRIMITIVES: &[&str] = &[
+        "bool", "char", "f32", "f64", "i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16",
+        "u32", "u64", "u128", "usize", "()",
+    ];
+    (ty.starts_with('&') && !ty.starts_with("&mut")) || PRIMITIVES.contains(&ty)
+}
+
+/// Finds the arguments of a call whose callee name ends at `offset`, without
+/// trimming them. Returns `None` if the name is not followed by an argument
+/// list.
+pub fn call_arguments(lexed: &Lexed, offset: usize) -> Option<Vec<OffsetRange>> {
+    let code = &lexed.code;
+
+    // Skip generic arguments, e.g. `foo::<u8>(..)`.
+    let mut i = skip_whitespace(code, offset);
+    if code[i..].starts_with(&[':', ':', '<']) {
+        let mut depth = 0;
+        while i < code.len() {
+            match code[i] {
+                '<' => depth += 1,
+                '>' if code[i - 1] != '-' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+        i = skip_whitespace(code, i + 1);
+    }
+    if code.get(i) != Some(&'(') {
+        return None;
+    }
+
+    let mut args = vec![];
+    let mut depth = 0;
+    let mut start = i + 1;
+    // Whether we are in the parameters of a closure argument, whose commas
+    // don't separate arguments.
+    let mut closure_params = false;
+    for (j, &c) in code.iter().enumerate().skip(i + 1) {
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' if depth > 0 => depth -= 1,
+            '|' if depth == 0 => {
+                let before: String = code[start..j].iter().collect();
+                let before = before.trim();
+                if closure_params {
+                    closure_params = false;
+                } else if before.is_empty() || before == "move" {
+                    closure_params = true;
+                }
+            }
+            ',' if depth == 0 && !closure_params => {
+                args.push((start, j));
+                start = j + 1;
+            }
+            ')' => {
+                args.push((start, j));
+                return Some(args);
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The top-level `use` declarations of a file, with their normalized text.
+/// Declarations with attributes are skipped.
+pub fn top_level_uses(chars: &[char], lexed: &Lexed) -> Vec<(OffsetRange, String)> {
+    let code = &lexed.code;
+    let mut uses = vec![];
+    let mut depth = 0;
+    let mut i = 0;
+    while i < code.len() {
+        match code[i] {
+            '{' | '(' | '[' => depth += 1,
+            '}' | ')' | ']' => depth -= 1,
+            'u' if depth == 0
+                && code[i..].starts_with(&['u', 's', 'e', ' '])
+                && (i == 0 || code[i - 1].is_whitespace()) =>
+            {
+                let row = lexed.position(i).0;
+                let line_start = lexed.offset(row, 0);
+                let has_attribute = (0..row)
+                    .rev()
+                    .map(|row| lexed.line(row))
+                    .find(|line| !line.trim().is_empty())
+                    .map(|line| line.trim_end().ends_with(']'))
+                    .unwrap_or(false);
+                if code[line_start..i].iter().all(|c| c.is_whitespace()) && !has_attribute {
+                    if let Some(end) = lexed.statement_end(i) {
+                        let text: String = chars[i..=end].iter().collect();
+                        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                        uses.push(((i, end + 1), text));
+                        i = end;
+                    }
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    uses
+}
+
+// Ideally we'd use Rustfmt for this, but reparsing is a bit of a pain.
+pub fn sort_deglob_str(s: &str) -> String {
+    let mut substrings = s.split(',').map(str::trim).collect::<Vec<_>>();
+    substrings.sort_by(|a, b| {
+        use std::cmp::Ordering;
+
+        // Algorithm taken from rustfmt (`rustfmt/src/imports.rs`).
+
+        let is_upper_snake_case =
+            |s: &str| s.chars().all(|c| c.is_uppercase() || c == '_' || c.is_numeric());
+
+        // snake_case < CamelCase < UPPER_SNAKE_CASE
+        if a.starts_with(char::is_uppercase) && b.starts_with(char::is_lowercase) {
+            return Ordering::Greater;
+        }
+        if a.starts_with(char::is_lowercase) && b.starts_with(char::is_uppercase) {
+            return Ordering::Less;
+        }
+        if is_upper_snake_case(a) && !is_upper_snake_case(b) {
+            return Ordering::Greater;
+        }
+        if !is_upper_snake_case(a) && is_upper_snake_case(b) {
+            return Ordering::Less;
+        }
+        a.cmp(b)
+    });
+    substrings.join(", ")
+}
+
+/// If a raw string literal (`r"..."`, `r#"..."#`, `br"..."`) starts at `i`,
+/// returns the number of `#`s delimiting it.
+fn raw_string_start(chars: &[char], i: usize) -> Option<usize> {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+    if chars[i] != 'r' {
+        return None;
+    }
+    let prefix_ok = match i {
+        0 => true,
+        1 => !is_ident(chars[0]) || chars[0] == 'b',
+        _ => !is_ident(chars[i - 1]) || (chars[i - 1] == 'b' && !is_ident(chars[i - 2])),
+    };
+    if !prefix_ok {
+        return None;
+    }
+    let hashes = chars[i + 1..].iter().take_while(|&&c| c == '#').count();
+    if chars.get(i + 1 + hashes) == Some(&'"') {
+        Some(hashes)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lexed_code() {
+        let text = "let s = \"{\"; // }\nlet c = '('; let r = r#\"]\"#;\nfn f<'a>() {}";
+        let lexed = Lexed::new(text);
+        assert_eq!(lexed.line_count(), 3);
+        assert_eq!(lexed.line(0), "let s =    ;     ");
+        assert_eq!(lexed.line(1), "let c =    ; let r =       ;");
+        assert_eq!(lexed.line(2), "fn f<'a>() {}");
+        assert_eq!(lexed.position(lexed.offset(2, 4)), (2, 4));
+        assert_eq!(lexed.block_end(lexed.offset(2, 0)), Some(lexed.offset(2, 12)));
+        assert_eq!(lexed.block_end(0), None);
+    }
+
+    #[test]
+    fn test_is_mut() {
+        let is_mut_at = |text: &str, name: &str| {
+            let code: Vec<char> = text.chars().collect();
+            is_mut(&code, text.find(name).unwrap())
+        };
+        assert!(is_mut_at("let mut x = 1;", "x"));
+        assert!(is_mut_at("let (a, mut  b) = c;", "b"));
+        assert!(is_mut_at("static mut FOO: u8 = 0;", "FOO"));
+        assert!(!is_mut_at("let (a, mut  b) = c;", "a"));
+        assert!(!is_mut_at("let &mut x = y;", "x"));
+        assert!(!is_mut_at("let &  mut x = y;", "x"));
+        assert!(!is_mut_at("let smut x = y;", "x"));
+        assert!(!is_mut_at("x", "x"));
+    }
+
+    #[test]
+    fn test_sort_deglob_str() {
+        assert_eq!(sort_deglob_str(""), "");
+        assert_eq!(sort_deglob_str("foo"), "foo");
+        assert_eq!(sort_deglob_str("a, b"), "a, b");
+        assert_eq!(sort_deglob_str("b, a"), "a, b");
+        assert_eq!(sort_deglob_str("foo, bar, baz"), "bar, baz, foo");
+        assert_eq!(
+            sort_deglob_str("Curve, curve, ARC, bow, Bow, arc, Arc"),
+            "arc, bow, curve, Arc, Bow, Curve, ARC",
+        );
+    }
+}
@@ -0,0 +1,546 @@
+//! Filling in the missing arms of a `match` on an enum.
+//!
+//! The enum is found from the type of the scrutinee, and its variants from
+//! the children of the enum's definition. An arm is added for each variant
+//! which is not matched by an existing (unguarded) arm.
+
+use std::iter;
+use std::path::Path;
+
+use log::trace;
+use rls_analysis::{Def, DefKind, Id};
+use rls_vfs::FileContents;
+
+use crate::actions::lexical::{is_ident_char, skip_whitespace, Lexed, OffsetRange};
+use crate::actions::InitActionContext;
+use crate::lsp_data::*;
+use crate::Span;
+
+/// The body of the arms which are added.
+const ARM_BODY: &str = "unimplemented!()";
+
+/// An arm of a `match` expression.
+#[derive(Debug, PartialEq)]
+struct Arm {
+    /// The pattern, without any guard.
+    pattern: OffsetRange,
+    has_guard: bool,
+    /// The end of the arm, after the comma if there is one.
+    end: usize,
+    /// `true` if a comma must be added before any following arm.
+    needs_comma: bool,
+}
+
+/// A `match` expression.
+#[derive(Debug, PartialEq)]
+struct Match {
+    keyword: usize,
+    /// The braces around the arms.
+    open: usize,
+    close: usize,
+}
+
+/// Computes the text which adds arms for the enum variants not matched by the
+/// innermost `match` expression at `position`, and the range it replaces.
+pub fn missing_arms(
+    ctx: &InitActionContext,
+    file_path: &Path,
+    position: Position,
+) -> Option<(Range, String)> {
+    let text = match ctx.vfs.load_file(file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return None,
+    };
+    let chars: Vec<char> = text.chars().collect();
+    let lexed = Lexed::new(&text);
+    let to_position = |offset| {
+        let (row, col) = lexed.position(offset);
+        Position::new(row as u64, col as u64)
+    };
+    let to_span = |(start, end)| {
+        let range = Range::new(to_position(start), to_position(end));
+        Span::from_range(ls_util::range_to_rls(range), file_path.to_owned())
+    };
+
+    let offset = lexed.offset(position.line as usize, position.character as usize);
+    let expr = enclosing_match(&lexed, offset)?;
+    let arms = arms(&lexed.code, expr.open, expr.close)?;
+    let unguarded: Vec<&Arm> = arms.iter().filter(|arm| !arm.has_guard).collect();
+    if unguarded.iter().any(|arm| is_catch_all(&chars[arm.pattern.0..arm.pattern.1])) {
+        return None;
+    }
+
+    let patterns: Vec<String> =
+        unguarded.iter().map(|arm| chars[arm.pattern.0..arm.pattern.1].iter().collect()).collect();
+    let refs: Vec<Vec<Id>> = unguarded
+        .iter()
+        .map(|arm| {
+            let refs = ctx.analysis.refs_within(&to_span(arm.pattern)).unwrap_or_default();
+            refs.into_iter().map(|(_, id)| id).collect()
+        })
+        .collect();
+    let matched: Vec<Id> = refs.iter().flatten().cloned().collect();
+    // The arms the analysis knows nothing about are judged from their text.
+    let named: Vec<&str> = patterns
+        .iter()
+        .zip(&refs)
+        .filter(|(_, refs)| refs.is_empty())
+        .flat_map(|(pattern, _)| pattern_heads(pattern))
+        .collect();
+    let scrutinee = last_ident(&lexed.code, expr.keyword + "match".len(), expr.open);
+    let enum_id = enum_id(ctx, scrutinee.map(to_span), &matched)?;
+    let enum_def = ctx.analysis.get_def(enum_id).ok()?;
+    let mut variants =
+        ctx.analysis.for_each_child_def(enum_id, |id, def| (id, def.clone())).ok()?;
+    variants.retain(|(_, def)| is_variant(def));
+    variants.sort_by_key(|(_, def)| (def.span.range.row_start, def.span.range.col_start));
+
+    let names: Vec<&str> = variants.iter().map(|(_, def)| &*def.name).collect();
+    let prefix = variant_prefix(&patterns, &names).unwrap_or_else(|| {
+        let is_prelude = enum_def.qualname.ends_with("option::Option")
+            || enum_def.qualname.ends_with("result::Result");
+        if is_prelude {
+            String::new()
+        } else {
+            format!("{}::", enum_def.name)
+        }
+    });
+    let missing: Vec<String> = variants
+        .iter()
+        .filter(|(id, def)| !matched.contains(id) && !named.contains(&&*def.name))
+        .map(|(_, def)| format!("{}{}", prefix, variant_pattern(&def.name, &def.value)))
+        .collect();
+    trace!("missing_arms: {:?}", missing);
+    if missing.is_empty() {
+        return None;
+    }
+
+    let config = ctx.fmt_config();
+    let config = config.get_rustfmt_config();
+    let unit = if config.hard_tabs() { "\t".to_owned() } else { " ".repeat(config.tab_spaces()) };
+    let ((start, end), new_text) = arms_edit(&lexed, &expr, &arms, &missing, &unit);
+    Some((Range::new(to_position(start), to_position(end)), new_text))
+}
+
+/// Finds the enum matched on: the enum named by the type of the scrutinee,
+/// preferring the one whose variants are matched by the existing arms if the
+/// name is ambiguous.
+fn enum_id(ctx: &InitActionContext, scrutinee: Option<Span>, matched: &[Id]) -> Option<Id> {
+    let from_arms = matched
+        .iter()
+        .filter_map(|&id| ctx.analysis.get_def(id).ok())
+        .find(is_variant)
+        .and_then(|def| def.parent);
+
+    let ty = scrutinee.and_then(|span| ctx.analysis.show_type(&span).ok());
+    let name = match ty.as_ref().and_then(|ty| enum_name(ty)) {
+        Some(name) => name,
+        None => return from_arms,
+    };
+    let candidates: Vec<Id> = ctx
+        .analysis
+        .search_for_id(name)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|&id| {
+            ctx.analysis.get_def(id).map(|def| def.kind == DefKind::Enum).unwrap_or(false)
+        })
+        .collect();
+    match from_arms {
+        Some(id) if candidates.contains(&id) => Some(id),
+        _ if candidates.len() == 1 => Some(candidates[0]),
+        _ => None,
+    }
+}
+
+fn is_variant(def: &Def) -> bool {
+    def.kind == DefKind::TupleVariant || def.kind == DefKind::StructVariant
+}
+
+/// The name of the type `ty`, without references, path or generic arguments.
+fn enum_name(ty: &str) -> Option<&str> {
+    let mut ty = ty.trim();
+    while ty.starts_with('&') {
+        ty = ty[1..].trim_start();
+        if ty.starts_with('\'') {
+            ty = ty.trim_start_matches(|c: char| c == '\'' || is_ident_char(c)).trim_start();
+        }
+        if ty.starts_with("mut ") {
+            ty = ty["mut ".len()..].trim_start();
+        }
+    }
+    let path = ty.split('<').next()?.trim_end();
+    let name = path.rsplit("::").next()?;
+    if !name.is_empty() && name.chars().all(is_ident_char) {
+        Some(name)
+    } else {
+        None
+    }
+}
+
+/// The last identifier of the scrutinee in `code[start..end]`, e.g., `kind`
+/// in `self.kind`.
+fn last_ident(code: &[char], start: usize, end: usize) -> Option<OffsetRange> {
+    let mut end = end;
+    while end > start && code[end - 1].is_whitespace() {
+        end -= 1;
+    }
+    let mut ident_start = end;
+    while ident_start > start && is_ident_char(code[ident_start - 1]) {
+        ident_start -= 1;
+    }
+    if ident_start < end && !code[ident_start].is_numeric() {
+        Some((ident_start, end))
+    } else {
+        None
+    }
+}
+
+/// Finds the closing bracket matching the opening one at `open`.
+fn closing_bracket(code: &[char], open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, &c) in code.iter().enumerate().skip(open) {
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Finds the innermost `match` expression whose arms contain `offset`, or
+/// whose header does.
+fn enclosing_match(lexed: &Lexed, offset: usize) -> Option<Match> {
+    let code = &lexed.code;
+    let is_keyword = |i: usize| {
+        code[i..].starts_with(&['m', 'a', 't', 'c', 'h'])
+            && (i == 0 || !is_ident_char(code[i - 1]))
+            && code.get(i + 5).map(|&c| !is_ident_char(c)).unwrap_or(false)
+    };
+    (0..=offset.min(code.len().saturating_sub(1))).rev().filter(|&i| is_keyword(i)).find_map(
+        |keyword| {
+            let mut depth = 0;
+            let open = (keyword + 5..code.len()).find(|&i| match code[i] {
+                '(' | '[' => {
+                    depth += 1;
+                    false
+                }
+                ')' | ']' => {
+                    depth -= 1;
+                    false
+                }
+                '{' => depth == 0,
+                _ => false,
+            })?;
+            let close = closing_bracket(code, open)?;
+            if offset <= close {
+                Some(Match { keyword, open, close })
+            } else {
+                None
+            }
+        },
+    )
+}
+
+/// Splits the body of a `match` expression into arms. Returns `None` if the
+/// body can't be parsed.
+fn arms(code: &[char], open: usize, close: usize) -> Option<Vec<Arm>> {
+    let trim_end = |start: usize, mut end: usize| {
+        while end > start && code[end - 1].is_whitespace() {
+            end -= 1;
+        }
+        end
+    };
+
+    let mut arms = vec![];
+    let mut i = skip_whitespace(code, open + 1);
+    while i < close {
+        // The pattern and guard, up to the `=>`.
+        let start = i;
+        let mut depth = 0;
+        let mut guard = None;
+        while i < close && !(depth == 0 && code[i] == '=' && code[i + 1] == '>') {
+            match code[i] {
+                '(' | '[' | '{' => depth += 1,
+                ')' | ']' | '}' => depth -= 1,
+                'i' if depth == 0
+                    && guard.is_none()
+                    && code[i..].starts_with(&['i', 'f'])
+                    && !is_ident_char(code[i - 1])
+                    && !is_ident_char(code[i + 2]) =>
+                {
+                    guard = Some(i)
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+        if i >= close {
+            return None;
+        }
+        let pattern = (start, trim_end(start, guard.unwrap_or(i)));
+
+        // The body, up to the comma or the end of the block.
+        i = skip_whitespace(code, i + 2);
+        let (end, needs_comma) = if code[i] == '{' {
+            let block_end = closing_bracket(code, i)? + 1;
+            let after = skip_whitespace(code, block_end);
+            if after < close && code[after] == ',' {
+                (after + 1, false)
+            } else {
+                (block_end, false)
+            }
+        } else {
+            let mut depth = 0;
+            while i < close && !(depth == 0 && code[i] == ',') {
+                match code[i] {
+                    '(' | '[' | '{' => depth += 1,
+                    ')' | ']' | '}' => depth -= 1,
+                    _ => {}
+                }
+                i += 1;
+            }
+            if i < close {
+                (i + 1, false)
+            } else {
+                (trim_end(start, close), true)
+            }
+        };
+        arms.push(Arm { pattern, has_guard: guard.is_some(), end, needs_comma });
+        i = skip_whitespace(code, end);
+    }
+    Some(arms)
+}
+
+/// `true` if the pattern matches anything: a wildcard or a binding.
+fn is_catch_all(pattern: &[char]) -> bool {
+    let pattern: String = pattern.iter().collect();
+    let binding = pattern.trim_start_matches("ref ").trim_start_matches("mut ");
+    binding.starts_with(|c: char| c.is_lowercase() || c == '_')
+        && binding.chars().all(is_ident_char)
+}
+
+/// The last segment of the path each alternative of `pattern` starts with,
+/// i.e., the variant it matches, e.g., `C` for `Kind::C(Other::A)`.
+fn pattern_heads(pattern: &str) -> Vec<&str> {
+    let mut heads = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in pattern.char_indices().chain(iter::once((pattern.len(), '|'))) {
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            '|' if depth == 0 => {
+                let alternative =
+                    pattern[start..i].trim_start_matches(|c: char| c == '&' || c.is_whitespace());
+                let len = alternative
+                    .find(|c: char| !is_ident_char(c) && c != ':')
+                    .unwrap_or(alternative.len());
+                let head = alternative[..len].rsplit("::").next().unwrap_or("");
+                if !head.is_empty() {
+                    heads.push(head);
+                }
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    heads
+}
+
+/// The path prefix used for the variants by the existing patterns, e.g.,
+/// `Kind::` or `self::Kind::`.
+fn variant_prefix(patterns: &[String], names: &[&str]) -> Option<String> {
+    for pattern in patterns {
+        for name in names {
+            for (i, _) in pattern.match_indices(name) {
+                let is_word = !pattern[i + name.len()..].starts_with(is_ident_char);
+                if !is_word || !pattern[..i].ends_with("::") {
+                    continue;
+                }
+                let start = pattern[..i]
+                    .rfind(|c: char| !is_ident_char(c) && c != ':')
+                    .map(|start| start + 1)
+                    .unwrap_or(0);
+                return Some(pattern[start..i].to_owned());
+            }
+        }
+    }
+    None
+}
+
+/// The pattern matching the variant `name`, with `_` for its fields. `value`
+/// is the variant's value in the save-analysis data, e.g.,
+/// `Enum::Variant(u8, String)` or `Enum::Variant { a, b }`.
+fn variant_pattern(name: &str, value: &str) -> String {
+    let path = format!("::{}", name);
+    let fields = match value.find(&path) {
+        Some(i) => value[i + path.len()..].trim(),
+        None => "",
+    };
+    if fields.starts_with('(') && fields.ends_with(')') {
+        let fields = &fields[1..fields.len() - 1];
+        let mut count = if fields.trim().is_empty() { 0 } else { 1 };
+        let mut depth = 0;
+        for c in fields.chars() {
+            match c {
+                '(' | '[' | '{' | '<' => depth += 1,
+                ')' | ']' | '}' | '>' => depth -= 1,
+                ',' if depth == 0 => count += 1,
+                _ => {}
+            }
+        }
+        if fields.trim_end().ends_with(',') {
+            count -= 1;
+        }
+        format!("{}({})", name, vec!["_"; count].join(", "))
+    } else if fields.starts_with('{') && fields.ends_with('}') {
+        let fields: Vec<String> = fields[1..fields.len() - 1]
+            .split(',')
+            .map(str::trim)
+            .filter(|field| !field.is_empty())
+            .map(|field| format!("{}: _", field))
+            .collect();
+        if fields.is_empty() {
+            format!("{} {{}}", name)
+        } else {
+            format!("{} {{ {} }}", name, fields.join(", "))
+        }
+    } else {
+        name.to_owned()
+    }
+}
+
+/// Computes the edit adding arms with the given patterns after the existing
+/// arms. `unit` is one level of indentation.
+fn arms_edit(
+    lexed: &Lexed,
+    expr: &Match,
+    arms: &[Arm],
+    patterns: &[String],
+    unit: &str,
+) -> (OffsetRange, String) {
+    let code = &lexed.code;
+    let line_indent = |offset: usize| -> String {
+        let row = lexed.position(offset).0;
+        lexed.line(row).chars().take_while(|c| c.is_whitespace()).collect()
+    };
+    let match_indent = line_indent(expr.keyword);
+    let indent = match arms.first() {
+        Some(arm) if lexed.position(arm.pattern.0).0 != lexed.position(expr.open).0 => {
+            line_indent(arm.pattern.0)
+        }
+        _ => format!("{}{}", match_indent, unit),
+    };
+
+    let start = arms.last().map(|arm| arm.end).unwrap_or(expr.open + 1);
+    let mut new_text = String::new();
+    if arms.last().map(|arm| arm.needs_comma).unwrap_or(false) {
+        new_text.push(',');
+    }
+    for pattern in patterns {
+        new_text.push_str(&format!("\n{}{} => {},", indent, pattern, ARM_BODY));
+    }
+    if code[start..expr.close].contains(&'\n') {
+        ((start, start), new_text)
+    } else {
+        // The closing brace is on the same line, move it to a line of its own.
+        let close_indent = if lexed.position(expr.close).0 == lexed.position(expr.keyword).0 {
+            match_indent
+        } else {
+            line_indent(expr.close)
+        };
+        new_text.push_str(&format!("\n{}", close_indent));
+        ((start, expr.close), new_text)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fill(text: &str, at: &str, patterns: &[&str]) -> String {
+        let lexed = Lexed::new(text);
+        let expr = enclosing_match(&lexed, text.find(at).unwrap()).expect("no match");
+        let arms = arms(&lexed.code, expr.open, expr.close).expect("bad arms");
+        let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+        let ((start, end), new_text) = arms_edit(&lexed, &expr, &arms, &patterns, "    ");
+        let mut text = text.to_owned();
+        text.replace_range(start..end, &new_text);
+        text
+    }
+
+    #[test]
+    fn test_arms() {
+        let text = "match x { A(y) if y > 0 => {} B { .. } => { f() }, C => g(1, 2), D => h }";
+        let lexed = Lexed::new(text);
+        let expr = enclosing_match(&lexed, text.find("y >").unwrap()).unwrap();
+        let arms = arms(&lexed.code, expr.open, expr.close).unwrap();
+        let patterns: Vec<&str> =
+            arms.iter().map(|arm| &text[arm.pattern.0..arm.pattern.1]).collect();
+        assert_eq!(patterns, vec!["A(y)", "B { .. }", "C", "D"]);
+        assert_eq!(
+            arms.iter().map(|arm| arm.has_guard).collect::<Vec<_>>(),
+            vec![true, false, false, false]
+        );
+        assert!(arms[3].needs_comma && !arms[2].needs_comma);
+
+        // The innermost match is found.
+        let text = "match a { X => match b { Y => 1 }, Z => 2 }";
+        let lexed = Lexed::new(text);
+        assert_eq!(enclosing_match(&lexed, text.find("Y").unwrap()).unwrap().keyword, 15);
+        assert_eq!(enclosing_match(&lexed, text.find("Z").unwrap()).unwrap().keyword, 0);
+    }
+
+    #[test]
+    fn test_patterns() {
+        assert_eq!(variant_pattern("A", "Foo::A"), "A");
+        assert_eq!(variant_pattern("B", "Foo::B(u8, HashMap<u8, String>)"), "B(_, _)");
+        assert_eq!(variant_pattern("C", "Foo::C { x, y }"), "C { x: _, y: _ }");
+        assert_eq!(enum_name("&'a mut foo::Kind<T, U>"), Some("Kind"));
+        assert_eq!(enum_name("(u8, u8)"), None);
+        assert!(is_catch_all(&['_']) && is_catch_all(&"ref x".chars().collect::<Vec<_>>()));
+        assert!(!is_catch_all(&"None".chars().collect::<Vec<_>>()));
+
+        assert_eq!(pattern_heads("Kind::C(Other::A)"), vec!["C"]);
+        assert_eq!(pattern_heads("| &A | self::Kind::B { a: A, .. }"), vec!["A", "B"]);
+        assert_eq!(pattern_heads("C(A | B)"), vec!["C"]);
+
+        let patterns = vec!["self::Kind::A | self::Kind::B(_)".to_owned()];
+        assert_eq!(variant_prefix(&patterns, &["B"]), Some("self::Kind::".to_owned()));
+        assert_eq!(variant_prefix(&["A".to_owned()], &["A"]), None);
+    }
+
+    #[test]
+    fn test_arms_edit() {
+        let text = "fn f(k: Kind) {
+    match k {
+        Kind::A => {}
+        Kind::B => 1
+    }
+}
+";
+        assert_eq!(
+            fill(text, "k {", &["Kind::C(_)", "Kind::D"]),
+            "fn f(k: Kind) {
+    match k {
+        Kind::A => {}
+        Kind::B => 1,
+        Kind::C(_) => unimplemented!(),
+        Kind::D => unimplemented!(),
+    }
+}
+"
+        );
+        assert_eq!(
+            fill("fn f() {\n    match k {}\n}\n", "k {", &["A"]),
+            "fn f() {\n    match k {\n        A => unimplemented!(),\n    }\n}\n"
+        );
+    }
+}
@@ -0,0 +1,248 @@
+//! Stubs for the trait items missing from an impl, for rustc's E0046 ("not all
+//! trait items implemented").
+//!
+//! The missing items are named by the diagnostic, their signatures are taken
+//! from the trait's definition in the save-analysis data.
+
+use std::path::Path;
+
+use log::trace;
+use rls_analysis::{Def, DefKind};
+use rls_vfs::FileContents;
+
+use crate::actions::diagnostics::Diagnostic;
+use crate::actions::lexical::Lexed;
+use crate::actions::InitActionContext;
+use crate::lsp_data::*;
+use crate::Span;
+
+/// A trait item to generate a stub for.
+#[derive(Debug, PartialEq)]
+enum Member {
+    /// A function or method, with its signature.
+    Fn(String),
+    /// An associated type, with its name.
+    Type(String),
+    /// An associated const, with its signature and a placeholder value.
+    Const(String, &'static str),
+}
+
+/// Computes the text which adds stubs for the missing trait items reported by
+/// `diagnostic` to the impl, and the position at which to insert it. Also
+/// returns the names of the missing items which get no stub.
+pub fn missing_member_stubs(
+    ctx: &InitActionContext,
+    file_path: &Path,
+    diagnostic: &Diagnostic,
+) -> Option<(Position, String, Vec<String>)> {
+    let names = missing_names(&diagnostic.message);
+    if names.is_empty() {
+        return None;
+    }
+
+    // The diagnostic points at the impl header, which refers to the trait.
+    let header = Span::from_range(ls_util::range_to_rls(diagnostic.range), file_path.to_owned());
+    let trait_id =
+        ctx.analysis.refs_within(&header).ok()?.into_iter().map(|(_, id)| id).find(|&id| {
+            ctx.analysis.get_def(id).map(|def| def.kind == DefKind::Trait).unwrap_or(false)
+        })?;
+    let mut items = ctx.analysis.for_each_child_def(trait_id, |_, def| def.clone()).ok()?;
+    items.retain(|def| names.contains(&def.name));
+    items.sort_by_key(|def| (def.span.range.row_start, def.span.range.col_start));
+    let mut members = vec![];
+    let mut stubbed = vec![];
+    for def in &items {
+        if let Some(member) = member(def) {
+            members.push(member);
+            stubbed.push(&def.name);
+        }
+    }
+    let left_out: Vec<String> =
+        names.iter().filter(|name| !stubbed.contains(name)).cloned().collect();
+    trace!("missing_member_stubs: {:?}, left out {:?}", members, left_out);
+    if members.is_empty() {
+        return None;
+    }
+
+    let text = match ctx.vfs.load_file(file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return None,
+    };
+    let config = ctx.fmt_config();
+    let config = config.get_rustfmt_config();
+    let unit = if config.hard_tabs() { "\t".to_owned() } else { " ".repeat(config.tab_spaces()) };
+
+    let lexed = Lexed::new(&text);
+    let start = diagnostic.range.start;
+    let impl_offset = lexed.offset(start.line as usize, start.character as usize);
+    let (offset, new_text) = stubs_edit(&lexed, impl_offset, &members, &unit)?;
+    let (row, col) = lexed.position(offset);
+    Some((Position::new(row as u64, col as u64), new_text, left_out))
+}
+
+/// The names of the missing items, from a message such as "not all trait
+/// items implemented, missing: `foo`, `Bar`".
+fn missing_names(message: &str) -> Vec<String> {
+    let first_line = message.lines().next().unwrap_or("");
+    match first_line.find("missing: ") {
+        Some(i) => first_line[i + "missing: ".len()..]
+            .split(',')
+            .map(|name| name.trim().trim_matches('`').to_owned())
+            .filter(|name| !name.is_empty())
+            .collect(),
+        None => vec![],
+    }
+}
+
+fn member(def: &Def) -> Option<Member> {
+    let sig = def.sig.as_ref().map(|sig| sig.text.clone()).unwrap_or_else(|| def.value.clone());
+    // Drop any default body or value, and the trailing semicolon.
+    let sig = sig.trim().trim_end_matches(';').trim_end();
+    let sig = if sig.ends_with("{}") { sig[..sig.len() - 2].trim_end() } else { sig };
+    match def.kind {
+        DefKind::Function | DefKind::Method if sig.contains("fn ") => {
+            Some(Member::Fn(sig.to_owned()))
+        }
+        DefKind::Type => Some(Member::Type(def.name.clone())),
+        DefKind::Const => {
+            let sig = sig.splitn(2, " = ").next().unwrap_or(sig);
+            let ty = sig.splitn(2, ':').nth(1)?.trim();
+            Some(Member::Const(sig.to_owned(), placeholder(ty)?))
+        }
+        _ => None,
+    }
+}
+
+/// A value of type `ty` to initialize a const stub with. `unimplemented!()`
+/// can't be used in a const, so consts of other types don't get a stub and
+/// are named in the title of the action instead.
+fn placeholder(ty: &str) -> Option<&'static str> {
+    match ty {
+        "i8" | "i16" | "i32" | "i64" | "i128" | "isize" | "u8" | "u16" | "u32" | "u64" | "u128"
+        | "usize" => Some("0"),
+        "f32" | "f64" => Some("0.0"),
+        "bool" => Some("false"),
+        "char" => Some("'\\0'"),
+        "&str" | "&'static str" => Some("\"\""),
+        "()" => Some("()"),
+        _ if ty.starts_with("Option<") => Some("None"),
+        _ if ty.starts_with("&[") || ty.starts_with("&'static [") => Some("&[]"),
+        _ => None,
+    }
+}
+
+/// The stub for a trait item, indented by `indent`.
+fn stub(member: &Member, indent: &str, unit: &str) -> String {
+    match member {
+        Member::Fn(sig) => {
+            format!("{}{} {{\n{}{}unimplemented!()\n{}}}", indent, sig, indent, unit, indent)
+        }
+        Member::Type(name) => format!("{}type {} = ();", indent, name),
+        Member::Const(sig, value) => format!("{}{} = {};", indent, sig, value),
+    }
+}
+
+/// Computes the text to insert into the impl whose header starts at
+/// `impl_offset`, and the offset to insert it at (in the line of the closing
+/// brace). `unit` is one level of indentation.
+fn stubs_edit(
+    lexed: &Lexed,
+    impl_offset: usize,
+    members: &[Member],
+    unit: &str,
+) -> Option<(usize, String)> {
+    let code = &lexed.code;
+    let close = lexed.block_end(impl_offset)?;
+    let (close_row, _) = lexed.position(close);
+    let close_line_start = lexed.offset(close_row, 0);
+    let (impl_row, _) = lexed.position(impl_offset);
+    let impl_indent: String =
+        lexed.line(impl_row).chars().take_while(|c| c.is_whitespace()).collect();
+
+    let indent = format!("{}{}", impl_indent, unit);
+    let stubs: Vec<String> = members.iter().map(|m| stub(m, &indent, unit)).collect();
+    let stubs = stubs.join("\n\n");
+
+    let mut depth = 0;
+    let open = (impl_offset..close).find(|&i| match code[i] {
+        '(' | '[' => {
+            depth += 1;
+            false
+        }
+        ')' | ']' => {
+            depth -= 1;
+            false
+        }
+        '{' => depth == 0,
+        _ => false,
+    })?;
+    let is_empty = code[open + 1..close].iter().all(|c| c.is_whitespace());
+
+    if close_line_start > open && code[close_line_start..close].iter().all(|c| c.is_whitespace()) {
+        // The closing brace is on a line of its own.
+        let separator = if is_empty { "" } else { "\n" };
+        Some((close_line_start, format!("{}{}\n", separator, stubs)))
+    } else {
+        Some((close, format!("\n{}\n{}", stubs, impl_indent)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn insert(text: &str, members: &[Member]) -> String {
+        let lexed = Lexed::new(text);
+        let (offset, new_text) =
+            stubs_edit(&lexed, text.find("impl").unwrap(), members, "    ").expect("no insertion");
+        let mut text = text.to_owned();
+        text.insert_str(offset, &new_text);
+        text
+    }
+
+    #[test]
+    fn test_missing_names() {
+        assert_eq!(
+            missing_names("not all trait items implemented, missing: `foo`, `Bar`\n\nmissing `foo` in implementation"),
+            vec!["foo", "Bar"]
+        );
+        assert!(missing_names("mismatched types").is_empty());
+    }
+
+    #[test]
+    fn test_placeholder() {
+        assert_eq!(placeholder("u32"), Some("0"));
+        assert_eq!(placeholder("&'static str"), Some("\"\""));
+        assert_eq!(placeholder("char"), Some("'\\0'"));
+        assert_eq!(placeholder("Self"), None);
+        assert_eq!(placeholder("Option<u8>"), Some("None"));
+        assert_eq!(placeholder("&'static [&'static str]"), Some("&[]"));
+    }
+
+    #[test]
+    fn test_stubs_edit() {
+        let members = vec![
+            Member::Type("Item".to_owned()),
+            Member::Fn("fn next(&mut self) -> Option<Self::Item>".to_owned()),
+        ];
+        assert_eq!(
+            insert("mod m {\n    impl Iterator for Foo {}\n}\n", &members),
+            "mod m {
+    impl Iterator for Foo {
+        type Item = ();
+
+        fn next(&mut self) -> Option<Self::Item> {
+            unimplemented!()
+        }
+    }
+}
+"
+        );
+
+        let members = vec![Member::Const("const N: usize".to_owned(), "0")];
+        assert_eq!(
+            insert("impl Tr for Foo {\n    fn f() {}\n}\n", &members),
+            "impl Tr for Foo {\n    fn f() {}\n\n    const N: usize = 0;\n}\n"
+        );
+    }
+}
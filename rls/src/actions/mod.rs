@@ -1,8 +1,8 @@
 //! Actions that the RLS can perform: responding to requests, watching files,
 //! etc.
 
-use crate::config::Config;
 use crate::config::FmtConfig;
+use crate::config::{BuildProfiles, Config};
 use crate::Span;
 use log::{debug, error, info, trace};
 use rls_analysis::AnalysisHost;
@@ -48,14 +48,33 @@ macro_rules! parse_file_path {
     };
 }
 
+pub mod auto_import;
+pub mod call_hierarchy;
+pub mod change_signature;
 pub mod diagnostics;
+pub mod document_link;
+pub mod extract;
+pub mod folding;
 pub mod format;
+pub mod generate;
 pub mod hover;
+pub mod inlay_hints;
+pub mod inline;
+pub mod lexical;
+pub mod match_arms;
+pub mod missing_members;
+pub mod move_module;
+pub mod organize_imports;
 pub mod notifications;
 pub mod post_build;
 pub mod progress;
+pub mod rename;
 pub mod requests;
 pub mod run;
+pub mod selection;
+pub mod semantic_tokens;
+pub mod signature_help;
+pub mod type_hierarchy;
 pub mod work_pool;
 
 /// Persistent context shared across all requests and notifications.
@@ -343,6 +362,7 @@ impl InitActionContext {
                 project_path: project_path.to_owned(),
                 show_warnings: config.show_warnings,
                 related_information_support: self.client_capabilities.related_information_support,
+                tag_build_profiles: config.build_profiles == BuildProfiles::Both,
                 shown_cargo_error: Arc::clone(&self.shown_cargo_error),
                 active_build_count: Arc::clone(&self.active_build_count),
                 crate_blacklist: config.crate_blacklist.as_ref().clone(),
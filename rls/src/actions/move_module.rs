@@ -0,0 +1,378 @@
+//! Moving modules between files: switching a module between the `foo.rs`
+//! and `foo/mod.rs` layouts, and moving an inline `mod foo { .. }` into a
+//! file of its own.
+
+use std::path::{Path, PathBuf};
+
+use log::trace;
+use rls_analysis::DefKind;
+use rls_span::Column;
+use rls_vfs::FileContents;
+
+use crate::actions::lexical::{is_ident_char, skip_whitespace, Lexed, OffsetRange};
+use crate::actions::InitActionContext;
+use crate::lsp_data::*;
+use crate::Span;
+
+/// The ways a module can be moved.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MoveModule {
+    /// `foo/mod.rs` to `foo.rs`.
+    ToFile,
+    /// `foo.rs` to `foo/mod.rs`.
+    ToModRs,
+    /// `mod foo { .. }` to `mod foo;` and `foo.rs`.
+    OutOfLine,
+}
+
+impl MoveModule {
+    const ALL: [MoveModule; 3] = [MoveModule::ToFile, MoveModule::ToModRs, MoveModule::OutOfLine];
+
+    /// The name of the move, as passed to the `rls.moveModule` command.
+    pub fn name(self) -> &'static str {
+        match self {
+            MoveModule::ToFile => "toFile",
+            MoveModule::ToModRs => "toModRs",
+            MoveModule::OutOfLine => "outOfLine",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<MoveModule> {
+        MoveModule::ALL.iter().cloned().find(|m| m.name() == name)
+    }
+
+    pub fn title(self, module: &str) -> String {
+        match self {
+            MoveModule::ToFile => format!("Move `{}` to `{}.rs`", module, module),
+            MoveModule::ToModRs => format!("Move `{}` to `{}/mod.rs`", module, module),
+            MoveModule::OutOfLine => "Move inline module to file".to_owned(),
+        }
+    }
+}
+
+/// A module declared with `mod` in the workspace.
+struct Module {
+    name: String,
+    /// The span of the name in the `mod` declaration.
+    decl: Span,
+    /// The file holding the body of the module, which is the declaring file
+    /// for inline modules.
+    file: PathBuf,
+    /// Whether the module is declared in the root file of its crate, if the
+    /// declaring module is known.
+    in_crate_root: Option<bool>,
+}
+
+impl Module {
+    fn is_inline(&self) -> bool {
+        self.file == self.decl.file
+    }
+}
+
+/// Returns the move available for the module declared on the line of
+/// `location`, and the name of the module.
+pub fn available(ctx: &InitActionContext, location: &Location) -> Option<(MoveModule, String)> {
+    let module = load(ctx, location)?;
+    let caps = &ctx.client_capabilities;
+
+    if module.is_inline() {
+        if !caps.resource_create_support {
+            return None;
+        }
+        let text = match ctx.vfs.load_file(&module.decl.file) {
+            Ok(FileContents::Text(text)) => text,
+            _ => return None,
+        };
+        let lexed = Lexed::new(&text);
+        let name = lexed
+            .offset(module.decl.range.row_start.0 as usize, module.decl.range.col_start.0 as usize);
+        let file = module_dir(&module.decl.file, module.in_crate_root?)
+            .join(format!("{}.rs", module.name));
+        if !is_top_level(&lexed, name) || file.exists() {
+            return None;
+        }
+        return Some((MoveModule::OutOfLine, module.name));
+    }
+
+    if !caps.resource_rename_support {
+        return None;
+    }
+    let (kind, _, to) = layout_move(&module.file, &module.name)?;
+    if to.exists() {
+        return None;
+    }
+    Some((kind, module.name))
+}
+
+/// Computes the edit which moves the module declared on the line of
+/// `location`.
+pub fn move_module(
+    ctx: &InitActionContext,
+    location: &Location,
+    kind: MoveModule,
+) -> Option<WorkspaceEdit> {
+    let module = load(ctx, location)?;
+    let operations = match kind {
+        MoveModule::ToFile | MoveModule::ToModRs => {
+            let (actual, from, to) = layout_move(&module.file, &module.name)?;
+            if actual != kind || to.exists() {
+                return None;
+            }
+            vec![DocumentChangeOperation::Op(ResourceOp::Rename(RenameFile {
+                old_uri: Url::from_file_path(from).ok()?,
+                new_uri: Url::from_file_path(to).ok()?,
+                options: None,
+            }))]
+        }
+        MoveModule::OutOfLine => {
+            if !module.is_inline() {
+                return None;
+            }
+            let text = match ctx.vfs.load_file(&module.decl.file) {
+                Ok(FileContents::Text(text)) => text,
+                _ => return None,
+            };
+            let (chars, lexed) = (text.chars().collect::<Vec<_>>(), Lexed::new(&text));
+            let range = module.decl.range;
+            let name = (
+                lexed.offset(range.row_start.0 as usize, range.col_start.0 as usize),
+                lexed.offset(range.row_end.0 as usize, range.col_end.0 as usize),
+            );
+            if !is_top_level(&lexed, name.0) {
+                return None;
+            }
+            let (body, contents) = out_of_line(&chars, &lexed, name.1)?;
+
+            let file = module_dir(&module.decl.file, module.in_crate_root?)
+                .join(format!("{}.rs", module.name));
+            let uri = Url::from_file_path(&file).ok()?;
+            let position = |offset| {
+                let (row, col) = lexed.position(offset);
+                Position::new(row as u64, col as u64)
+            };
+            let start = Position::new(0, 0);
+            vec![
+                DocumentChangeOperation::Op(ResourceOp::Create(CreateFile {
+                    uri: uri.clone(),
+                    options: None,
+                })),
+                DocumentChangeOperation::Edit(TextDocumentEdit {
+                    text_document: VersionedTextDocumentIdentifier { uri, version: None },
+                    edits: vec![TextEdit { range: Range::new(start, start), new_text: contents }],
+                }),
+                DocumentChangeOperation::Edit(TextDocumentEdit {
+                    text_document: VersionedTextDocumentIdentifier {
+                        uri: location.uri.clone(),
+                        version: None,
+                    },
+                    edits: vec![TextEdit {
+                        range: Range::new(position(body.0), position(body.1)),
+                        new_text: ";".to_owned(),
+                    }],
+                }),
+            ]
+        }
+    };
+    trace!("move_module {:?} {}: {:?}", kind, module.name, operations);
+
+    Some(WorkspaceEdit {
+        changes: None,
+        document_changes: Some(DocumentChanges::Operations(operations)),
+    })
+}
+
+/// Finds the module declared on the line of `location`, so that the action
+/// is available on the `mod` keyword as well as the name.
+fn load(ctx: &InitActionContext, location: &Location) -> Option<Module> {
+    let span = ls_util::location_to_rls(location).ok()?;
+    let row = span.range.row_start;
+    let line = ctx.vfs.load_line(&span.file, row).ok()?;
+    let (start, end) = declared_name(&line)?;
+    let decl = Span::new(
+        row,
+        row,
+        Column::new_zero_indexed(start as u32),
+        Column::new_zero_indexed(end as u32),
+        span.file,
+    );
+
+    let id = ctx.analysis.id(&decl).ok()?;
+    let def = ctx.analysis.get_def(id).ok()?;
+    if def.kind != DefKind::Mod || def.span.file != decl.file || def.distro_crate {
+        return None;
+    }
+    // The value of a module def is the file its body lives in.
+    let file = ctx.current_project.join(&def.value);
+    // Only the root module of a crate has no parent.
+    let in_crate_root = def
+        .parent
+        .and_then(|parent| ctx.analysis.get_def(parent).ok())
+        .map(|parent| parent.parent.is_none());
+    Some(Module { name: def.name, decl: def.span, file, in_crate_root })
+}
+
+/// The char range of the module name in a line such as `pub mod foo {`.
+fn declared_name(line: &str) -> Option<OffsetRange> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        if !is_ident_char(chars[i]) {
+            i += 1;
+            continue;
+        }
+        let word_start = i;
+        while i < chars.len() && is_ident_char(chars[i]) {
+            i += 1;
+        }
+        if chars[word_start..i] != ['m', 'o', 'd']
+            || (word_start > 0 && chars[word_start - 1] == '.')
+        {
+            continue;
+        }
+        let start = skip_whitespace(&chars, i);
+        let mut end = start;
+        while end < chars.len() && is_ident_char(chars[end]) {
+            end += 1;
+        }
+        if end > start && start > i {
+            return Some((start, end));
+        }
+    }
+    None
+}
+
+/// The move between the `foo.rs` and `foo/mod.rs` layouts for the module
+/// `name` with its body in `file`, as the kind of move and the paths to move
+/// from and to. Returns `None` if `file` doesn't follow the module's name,
+/// e.g., because of a `#[path]` attribute.
+fn layout_move(file: &Path, name: &str) -> Option<(MoveModule, PathBuf, PathBuf)> {
+    let dir = file.parent()?;
+    if file.file_name()? == "mod.rs" {
+        if dir.file_name()? != name {
+            return None;
+        }
+        let to = dir.with_file_name(format!("{}.rs", name));
+        Some((MoveModule::ToFile, file.to_owned(), to))
+    } else if file.file_stem()? == name && file.extension()? == "rs" {
+        let to = dir.join(name).join("mod.rs");
+        Some((MoveModule::ToModRs, file.to_owned(), to))
+    } else {
+        None
+    }
+}
+
+/// The directory holding the files of the modules declared in `file`. Crate
+/// roots and `mod.rs` files own their directory, while `foo.rs` owns `foo/`.
+fn module_dir(file: &Path, is_crate_root: bool) -> PathBuf {
+    let dir = file.parent().unwrap_or_else(|| Path::new(""));
+    let stem = file.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+    if is_crate_root || stem == "mod" {
+        dir.to_owned()
+    } else {
+        dir.join(stem)
+    }
+}
+
+/// `true` if `offset` is not nested in any braces, i.e., an item there is
+/// declared at the top level of its file.
+fn is_top_level(lexed: &Lexed, offset: usize) -> bool {
+    let mut depth = 0;
+    for &c in &lexed.code[..offset] {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    depth == 0
+}
+
+/// For an inline module whose name ends at `name_end`, finds the range from
+/// the end of the name to the closing brace, and the contents of the module
+/// unindented to be the text of a file.
+fn out_of_line(chars: &[char], lexed: &Lexed, name_end: usize) -> Option<(OffsetRange, String)> {
+    let close = lexed.block_end(name_end)?;
+    let open = (name_end..close).find(|&i| lexed.code[i] == '{')?;
+    let body: String = chars[open + 1..close].iter().collect();
+
+    let lines: Vec<&str> = body.lines().collect();
+    let indent = lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    let mut contents = lines
+        .iter()
+        .map(|line| if line.is_char_boundary(indent) { &line[indent..] } else { line.trim_start() })
+        .map(str::trim_end)
+        .skip_while(|line| line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n");
+    contents.truncate(contents.trim_end().len());
+    if !contents.is_empty() {
+        contents.push('\n');
+    }
+
+    Some(((name_end, close + 1), contents))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_declared_name() {
+        assert_eq!(declared_name("mod foo;"), Some((4, 7)));
+        assert_eq!(declared_name("    pub(crate) mod  foo_2 {"), Some((20, 25)));
+        assert_eq!(declared_name("let modulo = 1;"), None);
+        assert_eq!(declared_name("x.mod foo"), None);
+        assert_eq!(declared_name("mod"), None);
+    }
+
+    #[test]
+    fn test_layout_move() {
+        let moved = |file: &str| {
+            layout_move(Path::new(file), "foo").map(|(kind, from, to)| {
+                (kind, from.display().to_string(), to.display().to_string())
+            })
+        };
+        assert_eq!(
+            moved("/ws/src/foo/mod.rs"),
+            Some((MoveModule::ToFile, "/ws/src/foo/mod.rs".into(), "/ws/src/foo.rs".into()))
+        );
+        assert_eq!(
+            moved("/ws/src/foo.rs"),
+            Some((MoveModule::ToModRs, "/ws/src/foo.rs".into(), "/ws/src/foo/mod.rs".into()))
+        );
+        assert_eq!(moved("/ws/src/bar.rs"), None);
+        assert_eq!(moved("/ws/src/bar/mod.rs"), None);
+    }
+
+    #[test]
+    fn test_module_dir() {
+        let dir = |file: &str, is_crate_root| {
+            module_dir(Path::new(file), is_crate_root).display().to_string()
+        };
+        assert_eq!(dir("/ws/src/lib.rs", true), "/ws/src");
+        assert_eq!(dir("/ws/src/bin/tool.rs", true), "/ws/src/bin");
+        assert_eq!(dir("/ws/src/foo/mod.rs", false), "/ws/src/foo");
+        assert_eq!(dir("/ws/src/foo.rs", false), "/ws/src/foo");
+        // A module may be named like a crate root.
+        assert_eq!(dir("/ws/src/main.rs", false), "/ws/src/main");
+        assert_eq!(dir("/ws/src/tool.rs", true), "/ws/src");
+    }
+
+    #[test]
+    fn test_out_of_line() {
+        let text = "fn main() {}\n\nmod foo {\n    //! Docs.\n\n    fn bar() {\n        baz(\"}\");\n    }\n}\n";
+        let (chars, lexed): (Vec<char>, _) = (text.chars().collect(), Lexed::new(text));
+        let name_end = text.find("foo").unwrap() + 3;
+        assert!(is_top_level(&lexed, name_end));
+        assert!(!is_top_level(&lexed, text.find("baz").unwrap()));
+
+        let ((start, end), contents) = out_of_line(&chars, &lexed, name_end).unwrap();
+        assert_eq!(format!("{};{}", &text[..start], &text[end..]), "fn main() {}\n\nmod foo;\n");
+        assert_eq!(contents, "//! Docs.\n\nfn bar() {\n    baz(\"}\");\n}\n");
+    }
+}
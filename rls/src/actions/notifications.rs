@@ -8,10 +8,13 @@ use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
 use crate::build::*;
-use crate::lsp_data::request::{RangeFormatting, RegisterCapability, UnregisterCapability};
+use crate::lsp_data::request::{
+    OnTypeFormatting, RangeFormatting, RegisterCapability, UnregisterCapability,
+};
 use crate::lsp_data::*;
 use crate::server::Request;
 use lsp_types::notification::ShowMessage;
+use serde_json::json;
 
 pub use crate::lsp_data::notification::{
     Cancel, DidChangeConfiguration, DidChangeTextDocument, DidChangeWatchedFiles,
@@ -51,12 +54,16 @@ impl BlockingNotificationAction for DidOpenTextDocument {
     fn handle<O: Output>(
         params: Self::Params,
         ctx: &mut InitActionContext,
-        _out: O,
+        out: O,
     ) -> Result<(), ()> {
         trace!("on_open: {:?}", params.text_document.uri);
         let file_path = parse_file_path!(&params.text_document.uri, "on_open")?;
         ctx.reset_change_version(&file_path);
         ctx.vfs.set_file(&file_path, &params.text_document.text);
+        // Only part of the workspace may be built, without the file's package.
+        if ctx.build_queue.mark_file_open(file_path) {
+            ctx.build_current_project(BuildPriority::Normal, &out);
+        }
         Ok(())
     }
 }
@@ -163,6 +170,7 @@ impl BlockingNotificationAction for DidChangeConfiguration {
         };
 
         let unstable_features = new_config.unstable_features;
+        let format_on_type = new_config.format_on_type;
 
         {
             let mut config = ctx.config.lock().unwrap();
@@ -198,29 +206,47 @@ impl BlockingNotificationAction for DidChangeConfiguration {
         ctx.build_current_project(BuildPriority::Cargo, &out);
 
         const RANGE_FORMATTING_ID: &str = "rls-range-formatting";
-        // FIXME should handle the response
-        let id = out.provide_id();
-        if unstable_features {
-            let params = RegistrationParams {
-                registrations: vec![Registration {
-                    id: RANGE_FORMATTING_ID.to_owned(),
-                    method: <RangeFormatting as LSPRequest>::METHOD.to_owned(),
-                    register_options: None,
-                }],
-            };
-
-            let request = Request::<RegisterCapability>::new(id, params);
-            out.request(request);
-        } else {
-            let params = UnregistrationParams {
-                unregisterations: vec![Unregistration {
-                    id: RANGE_FORMATTING_ID.to_owned(),
-                    method: <RangeFormatting as LSPRequest>::METHOD.to_owned(),
-                }],
-            };
-
-            let request = Request::<UnregisterCapability>::new(id, params);
-            out.request(request);
+        const ON_TYPE_FORMATTING_ID: &str = "rls-on-type-formatting";
+        // On-type formatting reformats a range too, so it is only enabled
+        // with unstable features (`normalise` turns it off otherwise).
+        let capabilities = vec![
+            (RANGE_FORMATTING_ID, <RangeFormatting as LSPRequest>::METHOD, unstable_features, None),
+            (
+                ON_TYPE_FORMATTING_ID,
+                <OnTypeFormatting as LSPRequest>::METHOD,
+                format_on_type,
+                Some(json!({
+                    "documentSelector": null,
+                    "firstTriggerCharacter": "}",
+                    "moreTriggerCharacter": [";"],
+                })),
+            ),
+        ];
+        for (registration_id, method, enabled, register_options) in capabilities {
+            // FIXME should handle the response
+            let id = out.provide_id();
+            if enabled {
+                let params = RegistrationParams {
+                    registrations: vec![Registration {
+                        id: registration_id.to_owned(),
+                        method: method.to_owned(),
+                        register_options,
+                    }],
+                };
+
+                let request = Request::<RegisterCapability>::new(id, params);
+                out.request(request);
+            } else {
+                let params = UnregistrationParams {
+                    unregisterations: vec![Unregistration {
+                        id: registration_id.to_owned(),
+                        method: method.to_owned(),
+                    }],
+                };
+
+                let request = Request::<UnregisterCapability>::new(id, params);
+                out.request(request);
+            }
         }
         Ok(())
     }
@@ -0,0 +1,472 @@
+//! Organize imports: merges the top-level `use` declarations of a file by
+//! module, sorts them, and removes the ones reported by the `unused_imports`
+//! lint.
+//!
+//! Declarations are organized in blocks, i.e., runs of `use` declarations
+//! which are not separated by blank lines or comments, so that the grouping
+//! chosen by the user is kept.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+use log::trace;
+use rls_vfs::FileContents;
+
+use crate::actions::lexical::{contains_word, sort_deglob_str, top_level_uses, Lexed, OffsetRange};
+use crate::actions::InitActionContext;
+use crate::lsp_data::*;
+
+/// A single imported name, e.g., `Read` in `use std::io::{Read, Write};`.
+#[derive(Debug)]
+struct Leaf {
+    /// The full path of the name, without any trailing `self`.
+    path: Vec<String>,
+    alias: Option<String>,
+    /// The extent of the leaf's use tree, including its prefix when it is not
+    /// shared with other leaves.
+    range: OffsetRange,
+}
+
+impl Leaf {
+    /// The name the lint reports the leaf by.
+    fn name(&self) -> &str {
+        self.alias.as_ref().or_else(|| self.path.last()).map(|s| &**s).unwrap_or("")
+    }
+}
+
+/// Computes the edits which organize the imports of `file_path`.
+pub fn organize_imports(ctx: &InitActionContext, file_path: &Path) -> Vec<TextEdit> {
+    let text = match ctx.vfs.load_file(file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return vec![],
+    };
+    let lexed = Lexed::new(&text);
+    let chars: Vec<char> = text.chars().collect();
+    let offset =
+        |position: Position| lexed.offset(position.line as usize, position.character as usize);
+
+    // Unused imports are only removed if the diagnostics are up to date.
+    let unused: Vec<(OffsetRange, String)> = if ctx.build_ready() {
+        let results = ctx.previous_build_results.lock().unwrap();
+        results
+            .get(file_path)
+            .map(|results| {
+                results
+                    .iter()
+                    .map(|(diag, _)| diag)
+                    .filter(|diag| {
+                        diag.code == Some(NumberOrString::String("unused_imports".to_owned()))
+                    })
+                    .map(|diag| {
+                        let range = (offset(diag.range.start), offset(diag.range.end));
+                        (range, diag.message.lines().next().unwrap_or("").to_owned())
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    } else {
+        vec![]
+    };
+    let import_spans: Vec<OffsetRange> = ctx
+        .analysis
+        .imports(file_path)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|span| {
+            let range = span.range;
+            (
+                lexed.offset(range.row_start.0 as usize, range.col_start.0 as usize),
+                lexed.offset(range.row_end.0 as usize, range.col_end.0 as usize),
+            )
+        })
+        .collect();
+
+    let edits = organize_edits(&chars, &lexed, &unused, &import_spans);
+    trace!("organize_imports: {:?}", edits);
+    let position = |offset| {
+        let (row, col) = lexed.position(offset);
+        Position::new(row as u64, col as u64)
+    };
+    edits
+        .into_iter()
+        .map(|((start, end), new_text)| TextEdit {
+            range: Range::new(position(start), position(end)),
+            new_text,
+        })
+        .collect()
+}
+
+/// Computes the edits which organize each block of `use` declarations.
+/// `unused` are the ranges and messages of the `unused_imports` lints, and
+/// `import_spans` the spans of the imported names from the save-analysis data.
+fn organize_edits(
+    chars: &[char],
+    lexed: &Lexed,
+    unused: &[(OffsetRange, String)],
+    import_spans: &[OffsetRange],
+) -> Vec<(OffsetRange, String)> {
+    let mut edits = vec![];
+    'blocks: for block in use_blocks(chars, lexed) {
+        let (start, end) = (block[0].0, block[block.len() - 1].1);
+        let old_text: String = chars[start..end].iter().collect();
+        if old_text.contains("//") || old_text.contains("/*") {
+            // Comments can't be moved along with the imports.
+            continue;
+        }
+        let mut leaves = vec![];
+        for &(start, end) in &block {
+            match parse_use(&lexed.code, start, end) {
+                Some(parsed) => leaves.extend(parsed),
+                None => continue 'blocks,
+            }
+        }
+        leaves.retain(|leaf| !is_unused(leaf, unused, import_spans));
+
+        let new_text = render(&leaves).join("\n");
+        if new_text.is_empty() {
+            edits.push((removal_range(chars, lexed, start, end), String::new()));
+        } else if new_text != old_text {
+            edits.push(((start, end), new_text));
+        }
+    }
+    edits
+}
+
+/// Groups the top-level `use` declarations into blocks of consecutive lines.
+fn use_blocks(chars: &[char], lexed: &Lexed) -> Vec<Vec<OffsetRange>> {
+    let mut blocks: Vec<Vec<OffsetRange>> = vec![];
+    for (range, _) in top_level_uses(chars, lexed) {
+        if let Some(block) = blocks.last_mut() {
+            let gap = &chars[block[block.len() - 1].1..range.0];
+            if gap.iter().all(|c| c.is_whitespace())
+                && gap.iter().filter(|&&c| c == '\n').count() <= 1
+            {
+                block.push(range);
+                continue;
+            }
+        }
+        blocks.push(vec![range]);
+    }
+    blocks
+}
+
+/// The range to delete when all the imports of a block are removed: its
+/// lines, and a blank line following them unless that would join two
+/// paragraphs of code.
+fn removal_range(chars: &[char], lexed: &Lexed, start: usize, end: usize) -> OffsetRange {
+    let first_row = lexed.position(start).0;
+    let last_row = lexed.position(end).0;
+    let line_end = lexed.offset(last_row, 0) + lexed.line(last_row).chars().count();
+    if !chars[end..line_end].iter().all(|c| c.is_whitespace()) {
+        return (start, end);
+    }
+    let is_blank = |row: usize| lexed.line(row).trim().is_empty();
+    let mut end = lexed.offset(last_row + 1, 0);
+    if last_row + 1 < lexed.line_count()
+        && is_blank(last_row + 1)
+        && (first_row == 0 || is_blank(first_row - 1))
+    {
+        end = lexed.offset(last_row + 2, 0);
+    }
+    (lexed.offset(first_row, 0), end)
+}
+
+/// `true` if the `unused_imports` lint was reported for `leaf`. The message
+/// must name it, in case the diagnostics are stale.
+fn is_unused(leaf: &Leaf, unused: &[(OffsetRange, String)], import_spans: &[OffsetRange]) -> bool {
+    let span = import_spans
+        .iter()
+        .find(|(start, end)| leaf.range.0 <= *start && *end <= leaf.range.1)
+        .unwrap_or(&leaf.range);
+    unused.iter().any(|((start, end), message)| {
+        *start <= span.0 && span.1 <= *end && contains_word(message, leaf.name())
+    })
+}
+
+/// Splits the text of a use declaration (or tree) into tokens, with their
+/// ranges.
+fn tokens(code: &[char], start: usize, end: usize) -> Vec<(String, OffsetRange)> {
+    let is_ident_char = |c: char| c.is_alphanumeric() || c == '_' || c == '#';
+    let mut tokens = vec![];
+    let mut i = start;
+    while i < end {
+        let c = code[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if is_ident_char(c) {
+            let token_start = i;
+            while i < end && is_ident_char(code[i]) {
+                i += 1;
+            }
+            tokens.push((code[token_start..i].iter().collect(), (token_start, i)));
+        } else if c == ':' && code.get(i + 1) == Some(&':') {
+            tokens.push(("::".to_owned(), (i, i + 2)));
+            i += 2;
+        } else {
+            tokens.push((c.to_string(), (i, i + 1)));
+            i += 1;
+        }
+    }
+    tokens
+}
+
+/// Parses the `use` declaration in `code[start..end]` into the names it
+/// imports. Returns `None` if the declaration can't be parsed.
+fn parse_use(code: &[char], start: usize, end: usize) -> Option<Vec<Leaf>> {
+    let tokens = tokens(code, start, end);
+    if tokens.first()?.0 != "use" || tokens.last()?.0 != ";" {
+        return None;
+    }
+    let mut leaves = vec![];
+    let mut i = 1;
+    parse_tree(&tokens, &mut i, &[], &mut leaves)?;
+    if i == tokens.len() - 1 {
+        Some(leaves)
+    } else {
+        None
+    }
+}
+
+fn parse_tree(
+    tokens: &[(String, OffsetRange)],
+    i: &mut usize,
+    prefix: &[String],
+    leaves: &mut Vec<Leaf>,
+) -> Option<()> {
+    let token = |i: usize| tokens.get(i).map(|(token, _)| &**token).unwrap_or("");
+    let start = tokens.get(*i)?.1 .0;
+    let mut path = prefix.to_vec();
+    if token(*i) == "::" {
+        // A path starting with `::`.
+        path.push(String::new());
+        *i += 1;
+    }
+    loop {
+        match token(*i) {
+            "{" => {
+                *i += 1;
+                while token(*i) != "}" {
+                    parse_tree(tokens, i, &path, leaves)?;
+                    match token(*i) {
+                        "," => *i += 1,
+                        "}" => {}
+                        _ => return None,
+                    }
+                }
+                *i += 1;
+                return Some(());
+            }
+            "*" => {
+                path.push("*".to_owned());
+                leaves.push(Leaf { path, alias: None, range: (start, tokens[*i].1 .1) });
+                *i += 1;
+                return Some(());
+            }
+            t if t.starts_with(|c: char| c.is_alphanumeric() || c == '_') => {
+                path.push(t.to_owned());
+                *i += 1;
+                if token(*i) == "::" {
+                    *i += 1;
+                } else {
+                    break;
+                }
+            }
+            _ => return None,
+        }
+    }
+
+    let mut alias = None;
+    if token(*i) == "as" {
+        alias = Some(tokens.get(*i + 1)?.0.clone());
+        *i += 2;
+    }
+    if path.last().map(|s| s == "self").unwrap_or(false) {
+        path.pop();
+    }
+    if path.is_empty() {
+        return None;
+    }
+    leaves.push(Leaf { path, alias, range: (start, tokens[*i - 1].1 .1) });
+    Some(())
+}
+
+/// Orders path segments as rustfmt does, with `self`, `super` and `crate`
+/// first.
+fn compare_segments(a: &[String], b: &[String]) -> Ordering {
+    let key = |segment: &String| {
+        let rank = match &**segment {
+            "self" => 0,
+            "super" => 1,
+            "crate" => 2,
+            _ => 3,
+        };
+        (rank, segment.clone())
+    };
+    a.iter().map(key).cmp(b.iter().map(key))
+}
+
+/// Renders the `use` declarations importing `leaves`, one per module, in
+/// order.
+fn render(leaves: &[Leaf]) -> Vec<String> {
+    let modules: HashSet<&[String]> = leaves
+        .iter()
+        .filter(|leaf| leaf.path.len() > 1)
+        .map(|l| &l.path[..l.path.len() - 1])
+        .collect();
+
+    // Maps a module to the names imported from it.
+    let mut groups: BTreeMap<Vec<String>, Vec<String>> = BTreeMap::new();
+    let mut uses = vec![];
+    for leaf in leaves {
+        let is_glob = leaf.path.last().map(|s| s == "*").unwrap_or(false);
+        let (module, name) = if modules.contains(&leaf.path[..]) && !is_glob {
+            (leaf.path.clone(), "self".to_owned())
+        } else {
+            let (name, module) = leaf.path.split_last().unwrap();
+            (module.to_vec(), name.clone())
+        };
+        let name = match leaf.alias {
+            Some(ref alias) => format!("{} as {}", name, alias),
+            None => name,
+        };
+        if module.is_empty() {
+            // An extern crate, imported by itself.
+            let text = format!("use {};", name);
+            if !uses.iter().any(|(_, t)| *t == text) {
+                uses.push((vec![name], text));
+            }
+            continue;
+        }
+        let names = groups.entry(module).or_default();
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+
+    for (module, names) in groups {
+        let prefix = module.join("::");
+        let (key, text) = match &names[..] {
+            [name] if name == "self" => (module.clone(), format!("use {};", prefix)),
+            [name] if name.starts_with("self ") => {
+                (module.clone(), format!("use {}{};", prefix, &name["self".len()..]))
+            }
+            [name] => {
+                let mut key = module.clone();
+                key.push(name.clone());
+                (key, format!("use {}::{};", prefix, name))
+            }
+            _ => {
+                let mut key = module.clone();
+                key.push("{".to_owned());
+                (key, format!("use {}::{{{}}};", prefix, sort_names(&names)))
+            }
+        };
+        uses.push((key, text));
+    }
+    uses.sort_by(|(a, _), (b, _)| compare_segments(a, b));
+    uses.into_iter().map(|(_, text)| text).collect()
+}
+
+/// Sorts the names in a use list: `self` first, then as in rustfmt, and globs
+/// last.
+fn sort_names(names: &[String]) -> String {
+    let is_self = |name: &&String| *name == "self" || name.starts_with("self ");
+    let mut sorted: Vec<&str> = names.iter().filter(is_self).map(|s| &**s).collect();
+    let others: Vec<&str> =
+        names.iter().filter(|name| !is_self(name) && *name != "*").map(|s| &**s).collect();
+    let others = sort_deglob_str(&others.join(", "));
+    if !others.is_empty() {
+        sorted.push(&others);
+    }
+    if names.iter().any(|name| name == "*") {
+        sorted.push("*");
+    }
+    sorted.join(", ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn organize(text: &str, unused: &[(&str, &str)]) -> String {
+        let lexed = Lexed::new(text);
+        let chars: Vec<char> = text.chars().collect();
+        let unused: Vec<_> = unused
+            .iter()
+            .map(|(span, message)| {
+                let start = text.find(span).unwrap();
+                ((start, start + span.len()), message.to_string())
+            })
+            .collect();
+        let mut text = text.to_owned();
+        for ((start, end), new_text) in
+            organize_edits(&chars, &lexed, &unused, &[]).into_iter().rev()
+        {
+            text.replace_range(start..end, &new_text);
+        }
+        text
+    }
+
+    #[test]
+    fn test_merge_and_sort() {
+        let text = "use std::io::Write;
+use std::collections::HashMap;
+use std::io::{self, Read};
+use crate::foo::Bar;
+use std::io::BufRead as _;
+use std::fs;
+
+use url::Url;
+use serde_json;
+use url::ParseError;
+
+fn main() {
+    use std::fs;
+}
+";
+        assert_eq!(
+            organize(text, &[]),
+            "use crate::foo::Bar;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead as _, Read, Write};
+
+use serde_json;
+use url::{ParseError, Url};
+
+fn main() {
+    use std::fs;
+}
+"
+        );
+        assert_eq!(
+            organize("use a::b;\n// A comment.\nuse a::c;\n", &[]),
+            "use a::b;\n// A comment.\nuse a::c;\n"
+        );
+    }
+
+    #[test]
+    fn test_remove_unused() {
+        let text = "use std::fs;
+
+use std::io::{Read, Write};
+use std::path::Path;
+
+fn main() {}
+";
+        let unused = [("Read", "unused import: `Read`"), ("std::fs", "unused import: `std::fs`")];
+        assert_eq!(
+            organize(text, &unused),
+            "use std::io::Write;
+use std::path::Path;
+
+fn main() {}
+"
+        );
+
+        // Stale diagnostics are ignored.
+        let unused = [("Read", "unused import: `HashMap`")];
+        assert_eq!(organize(text, &unused), text);
+    }
+}
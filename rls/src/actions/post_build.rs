@@ -14,7 +14,7 @@ use std::thread::{self, Thread};
 
 use crate::actions::diagnostics::{parse_diagnostics, Diagnostic, ParsedDiagnostics, Suggestion};
 use crate::actions::progress::DiagnosticsNotifier;
-use crate::build::{BuildResult, Crate};
+use crate::build::{BuildProfile, BuildResult, CompilerMessage, Crate};
 use crate::concurrency::JobToken;
 use crate::config::CrateBlacklist;
 use crate::lsp_data::{PublishDiagnosticsParams, Range};
@@ -37,6 +37,9 @@ pub struct PostBuildHandler {
     pub show_warnings: bool,
     pub crate_blacklist: CrateBlacklist,
     pub related_information_support: bool,
+    /// Whether the diagnostics emitted by only one of the checked
+    /// configurations are tagged with it.
+    pub tag_build_profiles: bool,
     pub shown_cargo_error: Arc<AtomicBool>,
     pub active_build_count: Arc<AtomicUsize>,
     pub notifier: Box<dyn DiagnosticsNotifier>,
@@ -147,7 +150,7 @@ impl PostBuildHandler {
         self.emit_notifications(&results);
     }
 
-    fn handle_messages(&self, cwd: &Path, messages: &[String]) {
+    fn handle_messages(&self, cwd: &Path, messages: &[CompilerMessage]) {
         // These notifications will include empty sets of errors for files
         // which had errors, but now don't. This instructs the IDE to clear
         // errors for those files.
@@ -161,11 +164,50 @@ impl PostBuildHandler {
         let file_diagnostics = messages
             .iter()
             .unique()
-            .filter_map(|msg| parse_diagnostics(msg, cwd, self.related_information_support))
-            .flat_map(|ParsedDiagnostics { diagnostics }| diagnostics);
+            .filter_map(|msg| {
+                let parsed =
+                    parse_diagnostics(&msg.message, cwd, self.related_information_support)?;
+                Some((parsed, msg.profile))
+            })
+            .flat_map(|(ParsedDiagnostics { diagnostics }, profile)| {
+                diagnostics.into_iter().map(move |(file, diagnostics)| (file, diagnostics, profile))
+            });
+
+        // A diagnostic emitted for several configurations is reported once,
+        // so we track the configurations of every reported diagnostic.
+        let mut profiles = HashMap::<PathBuf, Vec<Vec<BuildProfile>>>::new();
+        for (file_path, diagnostics, profile) in file_diagnostics {
+            let file_results = results.entry(file_path.clone()).or_insert_with(Vec::new);
+            let file_profiles = profiles.entry(file_path).or_default();
+            for (diagnostic, suggestions) in diagnostics {
+                let existing = file_results.iter().position(|(existing, _)| {
+                    existing.range == diagnostic.range
+                        && existing.severity == diagnostic.severity
+                        && existing.message == diagnostic.message
+                });
+                match existing {
+                    Some(idx) if !file_profiles[idx].contains(&profile) => {
+                        file_profiles[idx].push(profile)
+                    }
+                    Some(_) => {}
+                    None => {
+                        file_results.push((diagnostic, suggestions));
+                        file_profiles.push(vec![profile]);
+                    }
+                }
+            }
+        }
 
-        for (file_path, diagnostics) in file_diagnostics {
-            results.entry(file_path).or_insert_with(Vec::new).extend(diagnostics);
+        if self.tag_build_profiles {
+            for (file_path, file_profiles) in &profiles {
+                let file_results = results.get_mut(file_path).unwrap();
+                for ((diagnostic, _), profiles) in file_results.iter_mut().zip(file_profiles) {
+                    if let [profile] = profiles[..] {
+                        let source = diagnostic.source.get_or_insert_with(|| "rustc".to_owned());
+                        *source = format!("{} ({})", source, profile);
+                    }
+                }
+            }
         }
 
         self.emit_notifications(&results);
@@ -355,3 +397,92 @@ impl Job {
         self.handler.finalize();
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::concurrency::ConcurrentJob;
+    use rls_analysis::Target;
+
+    #[derive(Clone, Default)]
+    struct TestNotifier(Arc<Mutex<Vec<PublishDiagnosticsParams>>>);
+
+    impl DiagnosticsNotifier for TestNotifier {
+        fn notify_begin_diagnostics(&self) {}
+        fn notify_publish_diagnostics(&self, params: PublishDiagnosticsParams) {
+            self.0.lock().unwrap().push(params);
+        }
+        fn notify_error_diagnostics(&self, _: String) {}
+        fn notify_end_diagnostics(&self) {}
+    }
+
+    fn message(fixture: &str, profile: BuildProfile) -> CompilerMessage {
+        let path = Path::new(env!("FIXTURES_DIR")).join("compiler_message").join(fixture);
+        CompilerMessage { message: std::fs::read_to_string(path).unwrap(), profile }
+    }
+
+    /// Returns the `(severity, source)` of the diagnostics published for `messages`.
+    fn publish(
+        messages: &[CompilerMessage],
+        tag_build_profiles: bool,
+    ) -> Vec<(Option<DiagnosticSeverity>, Option<String>)> {
+        let (_job, token) = ConcurrentJob::new();
+        let notifier = TestNotifier::default();
+        let handler = PostBuildHandler {
+            analysis: Arc::new(AnalysisHost::new(Target::Debug)),
+            analysis_queue: Arc::new(AnalysisQueue::init()),
+            previous_build_results: Arc::default(),
+            file_to_crates: Arc::default(),
+            project_path: PathBuf::new(),
+            show_warnings: true,
+            crate_blacklist: CrateBlacklist::default(),
+            related_information_support: true,
+            tag_build_profiles,
+            shown_cargo_error: Arc::default(),
+            active_build_count: Arc::default(),
+            notifier: Box::new(notifier.clone()),
+            blocked_threads: vec![],
+            _token: token,
+        };
+
+        handler.handle_messages(&std::env::current_dir().unwrap(), messages);
+        let published = notifier.0.lock().unwrap();
+        published
+            .iter()
+            .flat_map(|params| &params.diagnostics)
+            .map(|diagnostic| (diagnostic.severity, diagnostic.source.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn diagnostics_of_both_profiles_are_reported_once() {
+        let error = message("mismatched-types.json", BuildProfile::Normal);
+        let single = publish(&[error.clone()], false);
+        let both = publish(
+            &[error.clone(), error, message("mismatched-types.json", BuildProfile::Test)],
+            false,
+        );
+        assert_eq!(single.len(), 1);
+        assert_eq!(both, single);
+    }
+
+    #[test]
+    fn diagnostics_of_one_profile_are_tagged() {
+        let messages = [
+            message("mismatched-types.json", BuildProfile::Normal),
+            message("mismatched-types.json", BuildProfile::Test),
+            message("unused-use.json", BuildProfile::Test),
+        ];
+        let tag = |severity: Option<DiagnosticSeverity>| match severity {
+            Some(DiagnosticSeverity::Warning) => Some("rustc (cfg(test))".to_owned()),
+            _ => Some("rustc".to_owned()),
+        };
+
+        let tagged = publish(&messages, true);
+        assert!(tagged.iter().any(|(severity, _)| *severity == Some(DiagnosticSeverity::Warning)));
+        assert!(tagged.iter().all(|(severity, source)| *source == tag(*severity)));
+
+        let untagged = publish(&messages, false);
+        assert!(untagged.iter().all(|(_, source)| source.as_deref() == Some("rustc")));
+    }
+}
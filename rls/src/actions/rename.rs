@@ -0,0 +1,171 @@
+//! Checks performed before renaming a symbol, and the file moves needed when
+//! the symbol is a module with its own file.
+
+use std::path::{Path, PathBuf};
+
+use rls_analysis::{name_space_for_def_kind, Def, DefKind, Id};
+use rls_span as span;
+
+use crate::actions::InitActionContext;
+
+type Span = span::Span<span::ZeroIndexed>;
+
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "box", "break", "const", "continue", "crate", "dyn", "else", "enum",
+    "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move",
+    "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true",
+    "try", "type", "unsafe", "use", "where", "while", "yield",
+];
+
+/// Returns `true` if `name` can be used as the new name of a symbol.
+pub fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    name != "_" && chars.all(|c| c.is_alphanumeric() || c == '_') && !KEYWORDS.contains(&name)
+}
+
+/// Finds the definition of the symbol at `span`, or the reason it can't be
+/// renamed.
+pub fn target(ctx: &InitActionContext, span: &Span) -> Result<(Id, Def), String> {
+    let analysis = &ctx.analysis;
+    let id = analysis.id(span).map_err(|_| "no information for symbol".to_owned())?;
+    let def = analysis.get_def(id).map_err(|_| "no definition for symbol".to_owned())?;
+
+    if def.name == "self" || def.name == "Self" {
+        return Err(format!("cannot rename `{}`", def.name));
+    }
+    if def.distro_crate || !def.span.file.starts_with(&ctx.current_project) {
+        return Err(format!("`{}` is defined outside the workspace", def.name));
+    }
+    if analysis.crate_local_id(span).is_err() {
+        return Err(format!("`{}` is defined in another crate", def.name));
+    }
+    if def.kind == DefKind::Mod && def.parent.is_none() {
+        return Err("cannot rename the crate root".to_owned());
+    }
+    // Spans that come out of a macro expansion point at the macro invocation,
+    // not at an identifier we could rewrite.
+    if !spells(ctx, span, &def.name) || !spells(ctx, &def.span, &def.name) {
+        return Err(format!("`{}` is generated by a macro", def.name));
+    }
+
+    Ok((id, def))
+}
+
+/// Returns an existing definition that would clash with `def` once renamed
+/// to `new_name`, i.e., one in the same scope and namespace.
+pub fn conflict(ctx: &InitActionContext, id: Id, def: &Def, new_name: &str) -> Option<Def> {
+    let parent = def.parent?;
+    let name_space = name_space_for_def_kind(def.kind);
+
+    ctx.analysis
+        .search_for_id(new_name)
+        .ok()?
+        .into_iter()
+        .filter(|&other| other != id)
+        .filter_map(|other| ctx.analysis.get_def(other).ok())
+        .find(|other| {
+            other.parent == Some(parent) && name_space_for_def_kind(other.kind) == name_space
+        })
+}
+
+/// Lists the files and directories to move, as `(from, to)` pairs, when the
+/// module `def` is renamed to `new_name`.
+pub fn module_moves(ctx: &InitActionContext, def: &Def, new_name: &str) -> Vec<(PathBuf, PathBuf)> {
+    if def.kind != DefKind::Mod {
+        return vec![];
+    }
+
+    // The value of a module def is the file its body lives in.
+    let file = ctx.current_project.join(&def.value);
+    let mut moves = file_moves(&def.span.file, &file, &def.name, new_name);
+    // The analysis may be older than the files on disk.
+    moves.retain(|(from, _)| from.exists());
+
+    // `foo.rs` may keep its submodules in a `foo/` directory next to it.
+    if let Some((from, to)) = moves.first().filter(|(from, _)| from.is_file()) {
+        let (dir, new_dir) = (from.with_file_name(&def.name), to.with_file_name(new_name));
+        if dir.is_dir() {
+            moves.push((dir, new_dir));
+        }
+    }
+    moves
+}
+
+/// The move of a module's own file: `foo.rs` to `new_name.rs`, or the
+/// `foo/` directory for a `foo/mod.rs`. Modules declared inline, or whose
+/// `#[path]` doesn't follow the module's name, have nothing to move.
+fn file_moves(decl: &Path, file: &Path, name: &str, new_name: &str) -> Vec<(PathBuf, PathBuf)> {
+    if file == decl {
+        return vec![];
+    }
+    let dir = match file.parent() {
+        Some(dir) => dir,
+        None => return vec![],
+    };
+
+    if file.file_name().map_or(false, |f| f == "mod.rs") {
+        if dir.file_name().map_or(false, |d| d == name) {
+            return vec![(dir.to_owned(), dir.with_file_name(new_name))];
+        }
+    } else if file.file_stem().map_or(false, |f| f == name)
+        && file.extension().map_or(false, |e| e == "rs")
+    {
+        return vec![(file.to_owned(), file.with_file_name(format!("{}.rs", new_name)))];
+    }
+    vec![]
+}
+
+/// Returns `true` if the source text at `span` is exactly `name`.
+fn spells(ctx: &InitActionContext, span: &Span, name: &str) -> bool {
+    if span.range.row_start != span.range.row_end {
+        return false;
+    }
+    let line = match ctx.vfs.load_line(&span.file, span.range.row_start) {
+        Ok(line) => line,
+        Err(_) => return false,
+    };
+    let (start, end) = (span.range.col_start.0 as usize, span.range.col_end.0 as usize);
+    let text: String = line.chars().skip(start).take(end.saturating_sub(start)).collect();
+    text == name
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_identifier() {
+        assert!(is_identifier("foo"));
+        assert!(is_identifier("_foo1"));
+        assert!(is_identifier("Über"));
+        assert!(!is_identifier(""));
+        assert!(!is_identifier("_"));
+        assert!(!is_identifier("1foo"));
+        assert!(!is_identifier("foo-bar"));
+        assert!(!is_identifier("foo::bar"));
+        assert!(!is_identifier("match"));
+        assert!(!is_identifier("Self"));
+    }
+
+    #[test]
+    fn test_file_moves() {
+        let lib = Path::new("/ws/src/lib.rs");
+        let moved = |file: &str| {
+            file_moves(lib, Path::new(file), "foo", "bar")
+                .into_iter()
+                .map(|(from, to)| (from.display().to_string(), to.display().to_string()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(moved("/ws/src/foo.rs"), [("/ws/src/foo.rs".into(), "/ws/src/bar.rs".into())]);
+        assert_eq!(moved("/ws/src/foo/mod.rs"), [("/ws/src/foo".into(), "/ws/src/bar".into())]);
+        // Inline modules and modules moved with `#[path]`.
+        assert!(moved("/ws/src/lib.rs").is_empty());
+        assert!(moved("/ws/src/other.rs").is_empty());
+        assert!(moved("/ws/src/other/mod.rs").is_empty());
+    }
+}
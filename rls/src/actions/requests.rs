@@ -15,22 +15,56 @@ use rustfmt_nightly::{Edition as RustfmtEdition, FileLines, FileName, Range as R
 use serde_derive::{Deserialize, Serialize};
 use url::Url;
 
+use crate::actions::auto_import;
+use crate::actions::call_hierarchy;
+use crate::actions::change_signature::{self, Parameter};
+use crate::actions::document_link;
+use crate::actions::extract;
+use crate::actions::folding;
+use crate::actions::format;
+use crate::actions::generate::{self, Boilerplate};
 use crate::actions::hover;
+use crate::actions::inlay_hints;
+use crate::actions::inline;
+use crate::actions::lexical::sort_deglob_str;
+use crate::actions::match_arms;
+use crate::actions::missing_members;
+use crate::actions::move_module::{self, MoveModule};
+use crate::actions::organize_imports;
+use crate::actions::rename;
 use crate::actions::run::collect_run_actions;
-use crate::actions::InitActionContext;
-use crate::build::Edition;
+use crate::actions::selection;
+use crate::actions::semantic_tokens;
+use crate::actions::signature_help;
+use crate::actions::type_hierarchy;
+use crate::actions::{ActionContext, InitActionContext};
+use crate::build::{BuildPriority, Edition};
 use crate::lsp_data;
 use crate::lsp_data::request::ApplyWorkspaceEdit;
 pub use crate::lsp_data::request::{
     CodeActionRequest as CodeAction, CodeLensRequest, Completion,
-    DocumentHighlightRequest as DocumentHighlight, DocumentSymbolRequest as Symbols,
-    ExecuteCommand, Formatting, GotoDefinition as Definition, GotoImplementation as Implementation,
-    HoverRequest as Hover, RangeFormatting, References, Rename,
-    ResolveCompletionItem as ResolveCompletion, WorkspaceSymbol,
+    DocumentHighlightRequest as DocumentHighlight, DocumentLinkRequest,
+    DocumentSymbolRequest as Symbols, ExecuteCommand, FoldingRangeRequest as FoldingRange,
+    Formatting, GotoDefinition as Definition, GotoImplementation as Implementation,
+    GotoTypeDefinition as TypeDefinition, HoverRequest as Hover, OnTypeFormatting,
+    PrepareRenameRequest as PrepareRename, RangeFormatting, References, Rename,
+    ResolveCompletionItem as ResolveCompletion, SelectionRangeRequest as SelectionRange,
+    SignatureHelpRequest as SignatureHelp, WorkspaceSymbol,
 };
 use crate::lsp_data::*;
+pub use crate::lsp_data::{
+    BuildPackage, CallHierarchyIncomingCalls, CallHierarchyOutgoingCalls, CallHierarchyPrepare,
+    InlayHints, SemanticTokensRequest, TypeHierarchyPrepare, TypeHierarchySubtypes,
+    TypeHierarchySupertypes,
+};
 use crate::server;
-use crate::server::{Ack, Output, Request, RequestAction, ResponseError, ResponseWithMessage};
+use crate::server::{
+    Ack, BlockingRequestAction, Notification, Output, Request, RequestAction, RequestId,
+    ResponseError, ResponseWithMessage, ShowMessage, NOT_INITIALIZED_CODE,
+};
+
+/// The kind of the "Organize imports" code action.
+pub const ORGANIZE_IMPORTS_KIND: &str = "source.organizeImports";
 
 /// The result of a deglob action for a single wildcard import.
 ///
@@ -116,6 +150,171 @@ impl RequestAction for Symbols {
     }
 }
 
+impl RequestAction for FoldingRange {
+    type Response = Vec<lsp_data::FoldingRange>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(vec![])
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        folding::folding_ranges(&ctx, &params)
+    }
+}
+
+impl RequestAction for SelectionRange {
+    type Response = Vec<lsp_data::SelectionRange>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(vec![])
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        selection::selection_ranges(&ctx, &params)
+    }
+}
+
+impl RequestAction for CallHierarchyPrepare {
+    type Response = Vec<CallHierarchyItem>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(vec![])
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        call_hierarchy::prepare(&ctx, &params)
+    }
+}
+
+impl RequestAction for CallHierarchyIncomingCalls {
+    type Response = Vec<CallHierarchyIncomingCall>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(vec![])
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        call_hierarchy::incoming_calls(&ctx, &params)
+    }
+}
+
+impl RequestAction for CallHierarchyOutgoingCalls {
+    type Response = Vec<CallHierarchyOutgoingCall>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(vec![])
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        call_hierarchy::outgoing_calls(&ctx, &params)
+    }
+}
+
+impl RequestAction for TypeHierarchyPrepare {
+    type Response = Vec<TypeHierarchyItem>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(vec![])
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        type_hierarchy::prepare(&ctx, &params)
+    }
+}
+
+impl RequestAction for TypeHierarchySupertypes {
+    type Response = Vec<TypeHierarchyItem>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(vec![])
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        type_hierarchy::supertypes(&ctx, &params)
+    }
+}
+
+impl RequestAction for TypeHierarchySubtypes {
+    type Response = Vec<TypeHierarchyItem>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(vec![])
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        type_hierarchy::subtypes(&ctx, &params)
+    }
+}
+
+impl RequestAction for InlayHints {
+    type Response = Vec<InlayHint>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(vec![])
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        inlay_hints::inlay_hints(&ctx, &params)
+    }
+}
+
+impl RequestAction for SemanticTokensRequest {
+    type Response = SemanticTokens;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(SemanticTokens { data: vec![] })
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        semantic_tokens::semantic_tokens(&ctx, &params)
+    }
+}
+
+impl RequestAction for DocumentLinkRequest {
+    type Response = Vec<DocumentLink>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(vec![])
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        document_link::document_links(&ctx, &params)
+    }
+}
+
 impl RequestAction for Hover {
     type Response = lsp_data::Hover;
 
@@ -136,6 +335,21 @@ impl RequestAction for Hover {
     }
 }
 
+impl RequestAction for SignatureHelp {
+    type Response = Option<lsp_data::SignatureHelp>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(None)
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        signature_help::signature_help(&ctx, &params)
+    }
+}
+
 impl RequestAction for Implementation {
     type Response = Vec<Location>;
 
@@ -210,6 +424,108 @@ impl RequestAction for Definition {
     }
 }
 
+impl RequestAction for TypeDefinition {
+    type Response = Vec<Location>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(vec![])
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        let file_path = parse_file_path!(&params.text_document.uri, "goto_type_def")?;
+        let span = ctx.convert_pos_to_span(file_path, params.position);
+        let analysis = &ctx.analysis;
+
+        let def = match analysis.id(&span).and_then(|id| analysis.get_def(id)) {
+            Ok(def) => def,
+            Err(_) => return Self::fallback_response(),
+        };
+        if is_type_def_kind(def.kind) {
+            return Ok(vec![ls_util::rls_to_location(&def.span)]);
+        }
+
+        // Signatures record exactly which types they refer to, so prefer those.
+        let from_sig = def.sig.as_ref().and_then(|sig| {
+            sig.refs
+                .iter()
+                .filter_map(|r| analysis.get_def(r.id).ok())
+                .find(|def| is_type_def_kind(def.kind))
+        });
+        if let Some(type_def) = from_sig {
+            trace!("goto_type_def (signature): {:?}", type_def.span);
+            return Ok(vec![ls_util::rls_to_location(&type_def.span)]);
+        }
+
+        // Otherwise, look the type up by its name.
+        let ty = analysis.show_type(&span).unwrap_or(def.value);
+        let path = match base_type_path(&ty) {
+            Some(path) => path,
+            None => return Self::fallback_response(),
+        };
+        let name = path.rsplit("::").next().unwrap_or(path);
+        let candidates: Vec<_> = analysis
+            .search_for_id(name)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|id| analysis.get_def(id).ok())
+            .filter(|def| is_type_def_kind(def.kind))
+            .collect();
+        // If the type is qualified, we can narrow the candidates down further.
+        let qualified: Vec<_> =
+            candidates.iter().filter(|def| def.qualname.ends_with(path)).cloned().collect();
+        let result = if qualified.is_empty() { candidates } else { qualified };
+
+        trace!("goto_type_def ({}): {:?}", ty, result);
+        Ok(result.iter().map(|def| ls_util::rls_to_location(&def.span)).collect())
+    }
+}
+
+fn is_type_def_kind(kind: data::DefKind) -> bool {
+    match kind {
+        data::DefKind::Struct
+        | data::DefKind::Enum
+        | data::DefKind::Union
+        | data::DefKind::Trait
+        | data::DefKind::Type
+        | data::DefKind::ExternType => true,
+        _ => false,
+    }
+}
+
+/// Returns the path of the outermost named type of `ty`, skipping over any
+/// references or pointers, e.g. `&mut std::vec::Vec<u8>` -> `std::vec::Vec`.
+fn base_type_path(ty: &str) -> Option<&str> {
+    let mut ty = ty.trim();
+    loop {
+        let stripped = ["&", "*const ", "*mut ", "mut ", "dyn ", "impl ", "["]
+            .iter()
+            .find(|prefix| ty.starts_with(*prefix))
+            .map(|prefix| &ty[prefix.len()..]);
+        ty = match stripped {
+            Some(rest) => rest.trim_start(),
+            // Skip lifetimes of references.
+            None if ty.starts_with('\'') => match ty.find(char::is_whitespace) {
+                Some(end) => ty[end..].trim_start(),
+                None => return None,
+            },
+            None => break,
+        };
+    }
+
+    let end = ty
+        .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == ':'))
+        .unwrap_or_else(|| ty.len());
+    let path = ty[..end].trim_start_matches("::");
+    if path.is_empty() {
+        None
+    } else {
+        Some(path)
+    }
+}
+
 impl RequestAction for References {
     type Response = Vec<Location>;
 
@@ -313,6 +629,32 @@ impl RequestAction for DocumentHighlight {
     }
 }
 
+impl RequestAction for PrepareRename {
+    type Response = Option<PrepareRenameResponse>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(None)
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        ctx.block_on_build();
+
+        let file_path = parse_file_path!(&params.text_document.uri, "prepare_rename")?;
+        let span = ctx.convert_pos_to_span(file_path, params.position);
+
+        match rename::target(&ctx, &span) {
+            Ok(_) => Ok(Some(PrepareRenameResponse::Range(ls_util::rls_to_range(span.range)))),
+            Err(reason) => Err(ResponseError::Message(
+                ErrorCode::InvalidRequest,
+                format!("Cannot rename: {}", reason),
+            )),
+        }
+    }
+}
+
 impl RequestAction for Rename {
     type Response = ResponseWithMessage<WorkspaceEdit>;
 
@@ -332,46 +674,42 @@ impl RequestAction for Rename {
         let file_path =
             parse_file_path!(&params.text_document_position.text_document.uri, "rename")?;
         let span = ctx.convert_pos_to_span(file_path, params.text_document_position.position);
+        let new_name = params.new_name;
 
-        let analysis = ctx.analysis;
-
-        macro_rules! unwrap_or_fallback {
-            ($e: expr, $msg: expr) => {
-                match $e {
-                    Ok(e) => e,
-                    Err(_) => {
-                        return Ok(ResponseWithMessage::Warn($msg.to_owned()));
-                    }
-                }
+        macro_rules! fail {
+            ($($arg: tt)*) => {
+                return Ok(ResponseWithMessage::Warn(
+                    format!("Rename failed: {}", format_args!($($arg)*)),
+                ))
             };
         }
 
-        let id = unwrap_or_fallback!(
-            analysis.crate_local_id(&span),
-            "Rename failed: no information for symbol"
-        );
-        let def =
-            unwrap_or_fallback!(analysis.get_def(id), "Rename failed: no definition for symbol");
-        if def.name == "self" || def.name == "Self"
-            // FIXME(#578)
-            || def.kind == data::DefKind::Mod
-        {
-            return Ok(ResponseWithMessage::Warn(format!(
-                "Rename failed: cannot rename {}",
-                if def.kind == data::DefKind::Mod { "modules" } else { &def.name }
-            )));
+        if !rename::is_identifier(&new_name) {
+            fail!("`{}` is not a valid identifier", new_name);
+        }
+        let (id, def) = match rename::target(&ctx, &span) {
+            Ok(target) => target,
+            Err(reason) => fail!("{}", reason),
+        };
+        if rename::conflict(&ctx, id, &def, &new_name).is_some() {
+            fail!("`{}` is already defined in this scope", new_name);
+        }
+
+        let moves = rename::module_moves(&ctx, &def, &new_name);
+        if !moves.is_empty() && !ctx.client_capabilities.resource_rename_support {
+            fail!("moving the file of module `{}` is not supported by the client", def.name);
+        }
+        if let Some((_, to)) = moves.iter().find(|(_, to)| to.exists()) {
+            fail!("`{}` already exists", to.display());
         }
 
-        let result = unwrap_or_fallback!(
-            analysis.find_all_refs(&span, true, true),
-            "Rename failed: error finding references"
-        );
+        let result = match ctx.analysis.find_all_refs(&span, true, true) {
+            Ok(result) => result,
+            Err(_) => fail!("error finding references"),
+        };
 
         if result.is_empty() {
-            return Ok(ResponseWithMessage::Warn(
-                "Rename failed: RLS found nothing to rename - possibly due to multiple defs"
-                    .to_owned(),
-            ));
+            fail!("RLS found nothing to rename - possibly due to multiple defs");
         }
 
         let mut edits: HashMap<Url, Vec<TextEdit>> = HashMap::new();
@@ -381,18 +719,42 @@ impl RequestAction for Rename {
             edits
                 .entry(loc.uri)
                 .or_insert_with(Vec::new)
-                .push(TextEdit { range: loc.range, new_text: params.new_name.clone() });
+                .push(TextEdit { range: loc.range, new_text: new_name.clone() });
         }
 
         if !ctx.quiescent.load(Ordering::SeqCst) {
-            return Ok(ResponseWithMessage::Warn(
-                "Rename failed: RLS busy, please retry".to_owned(),
-            ));
+            fail!("RLS busy, please retry");
+        }
+
+        if moves.is_empty() {
+            return Ok(ResponseWithMessage::Response(WorkspaceEdit {
+                changes: Some(edits),
+                document_changes: None,
+            }));
         }
 
+        // Edit the files before moving them, so that the edits still refer
+        // to the old paths.
+        let mut operations: Vec<_> = edits
+            .into_iter()
+            .map(|(uri, edits)| {
+                DocumentChangeOperation::Edit(TextDocumentEdit {
+                    text_document: VersionedTextDocumentIdentifier { uri, version: None },
+                    edits,
+                })
+            })
+            .collect();
+        operations.extend(moves.into_iter().map(|(from, to)| {
+            DocumentChangeOperation::Op(ResourceOp::Rename(RenameFile {
+                old_uri: Url::from_file_path(from).unwrap(),
+                new_uri: Url::from_file_path(to).unwrap(),
+                options: None,
+            }))
+        }));
+
         Ok(ResponseWithMessage::Response(WorkspaceEdit {
-            changes: Some(edits),
-            document_changes: None,
+            changes: None,
+            document_changes: Some(DocumentChanges::Operations(operations)),
         }))
     }
 }
@@ -401,6 +763,8 @@ impl RequestAction for Rename {
 pub enum ExecuteCommandResponse {
     /// Response/client request containing workspace edits.
     ApplyEdit(ApplyWorkspaceEditParams),
+    /// Like `ApplyEdit`, followed by a warning about what could not be done.
+    ApplyEditWithWarning(ApplyWorkspaceEditParams, String),
 }
 
 impl server::Response for ExecuteCommandResponse {
@@ -414,6 +778,14 @@ impl server::Response for ExecuteCommandResponse {
                 let request = Request::<ApplyWorkspaceEdit>::new(id, params);
                 out.request(request);
             }
+            ExecuteCommandResponse::ApplyEditWithWarning(params, message) => {
+                let id = out.provide_id();
+                out.request(Request::<ApplyWorkspaceEdit>::new(id, params));
+                out.notify(Notification::<ShowMessage>::new(ShowMessageParams {
+                    typ: MessageType::Warning,
+                    message,
+                }));
+            }
         }
 
         // The formal request response is a simple ACK, though the objective
@@ -429,7 +801,10 @@ impl RequestAction for ExecuteCommand {
         Err(ResponseError::Empty)
     }
 
-    /// Currently supports "rls.applySuggestion", "rls.deglobImports".
+    /// Currently supports "rls.applySuggestion", "rls.deglobImports",
+    /// "rls.extractFunction", "rls.extractVariable", "rls.inlineLocal",
+    /// "rls.inlineFunction", "rls.organizeImports", "rls.generate",
+    /// "rls.changeSignature", "rls.moveModule".
     fn handle(
         ctx: InitActionContext,
         params: ExecuteCommandParams,
@@ -438,6 +813,26 @@ impl RequestAction for ExecuteCommand {
             apply_suggestion(&params.arguments).map(ExecuteCommandResponse::ApplyEdit)
         } else if params.command.starts_with("rls.deglobImports") {
             apply_deglobs(params.arguments, &ctx).map(ExecuteCommandResponse::ApplyEdit)
+        } else if params.command.starts_with("rls.extractFunction") {
+            apply_extract(&params.arguments, &ctx, extract::extract_function)
+                .map(ExecuteCommandResponse::ApplyEdit)
+        } else if params.command.starts_with("rls.extractVariable") {
+            apply_extract(&params.arguments, &ctx, extract::extract_variable)
+                .map(ExecuteCommandResponse::ApplyEdit)
+        } else if params.command.starts_with("rls.inlineLocal") {
+            apply_inline(&params.arguments, &ctx, inline::inline_local)
+                .map(ExecuteCommandResponse::ApplyEdit)
+        } else if params.command.starts_with("rls.inlineFunction") {
+            apply_inline(&params.arguments, &ctx, inline::inline_function)
+                .map(ExecuteCommandResponse::ApplyEdit)
+        } else if params.command.starts_with("rls.generate") {
+            apply_generate(&params.arguments, &ctx).map(ExecuteCommandResponse::ApplyEdit)
+        } else if params.command.starts_with("rls.moveModule") {
+            apply_move_module(&params.arguments, &ctx).map(ExecuteCommandResponse::ApplyEdit)
+        } else if params.command.starts_with("rls.changeSignature") {
+            apply_change_signature(&params.arguments, &ctx)
+        } else if params.command.starts_with("rls.organizeImports") {
+            apply_organize_imports(&params.arguments, &ctx).map(ExecuteCommandResponse::ApplyEdit)
         } else {
             debug!("Unknown command: {}", params.command);
             Err(ResponseError::Message(ErrorCode::MethodNotFound, "Unknown command".to_owned()))
@@ -445,6 +840,46 @@ impl RequestAction for ExecuteCommand {
     }
 }
 
+impl BlockingRequestAction for BuildPackage {
+    type Response = Ack;
+
+    /// Adds the package named by the only argument to the build, for when
+    /// only part of the workspace is built, and starts a build.
+    fn handle<O: Output>(
+        _id: RequestId,
+        params: ExecuteCommandParams,
+        ctx: &mut ActionContext,
+        out: O,
+    ) -> Result<Self::Response, ResponseError> {
+        let ctx = ctx.inited().map_err(|_| {
+            ResponseError::Message(
+                NOT_INITIALIZED_CODE,
+                "not yet received `initialize` request".to_owned(),
+            )
+        })?;
+
+        let package: String = match params.arguments.first().cloned().map(serde_json::from_value) {
+            Some(Ok(package)) => package,
+            _ => {
+                return Err(ResponseError::Message(
+                    ErrorCode::InvalidParams,
+                    "Expected the name of a package".to_owned(),
+                ));
+            }
+        };
+        trace!("build_package {}", package);
+
+        ctx.build_queue.request_package(&package).map_err(|reason| {
+            ResponseError::Message(
+                ErrorCode::InvalidRequest,
+                format!("Cannot build `{}`: {}", package, reason),
+            )
+        })?;
+        ctx.build_current_project(BuildPriority::Immediate, &out);
+        Ok(Ack)
+    }
+}
+
 fn apply_suggestion(args: &[serde_json::Value]) -> Result<ApplyWorkspaceEditParams, ResponseError> {
     let location = serde_json::from_value(args[0].clone()).expect("Bad argument");
     let new_text = serde_json::from_value(args[1].clone()).expect("Bad argument");
@@ -481,13 +916,146 @@ fn apply_deglobs(
     Ok(ApplyWorkspaceEditParams { edit })
 }
 
+fn apply_extract(
+    args: &[serde_json::Value],
+    ctx: &InitActionContext,
+    extract: fn(&InitActionContext, &Location) -> Option<WorkspaceEdit>,
+) -> Result<ApplyWorkspaceEditParams, ResponseError> {
+    let location: Location = serde_json::from_value(args[0].clone()).expect("Bad argument");
+    trace!("apply_extract {:?}", location);
+
+    // The edit is computed again, since the file may have changed since the
+    // action was offered.
+    match extract(ctx, &location) {
+        Some(edit) => Ok(ApplyWorkspaceEditParams { edit }),
+        None => Err(ResponseError::Message(
+            ErrorCode::InvalidParams,
+            "Cannot extract the selection".to_owned(),
+        )),
+    }
+}
+
+fn apply_inline(
+    args: &[serde_json::Value],
+    ctx: &InitActionContext,
+    inline: fn(&InitActionContext, &Location) -> Option<Result<WorkspaceEdit, String>>,
+) -> Result<ApplyWorkspaceEditParams, ResponseError> {
+    let location: Location = serde_json::from_value(args[0].clone()).expect("Bad argument");
+    trace!("apply_inline {:?}", location);
+
+    match inline(ctx, &location) {
+        Some(Ok(edit)) => Ok(ApplyWorkspaceEditParams { edit }),
+        // Inlining would change the meaning of the code, tell the user why.
+        Some(Err(message)) => Err(ResponseError::Message(
+            ErrorCode::InvalidParams,
+            format!("Cannot inline: {}", message),
+        )),
+        None => Err(ResponseError::Message(
+            ErrorCode::InvalidParams,
+            "Nothing to inline at this position".to_owned(),
+        )),
+    }
+}
+
+fn apply_generate(
+    args: &[serde_json::Value],
+    ctx: &InitActionContext,
+) -> Result<ApplyWorkspaceEditParams, ResponseError> {
+    let location: Location = serde_json::from_value(args[0].clone()).expect("Bad argument");
+    let name: String = serde_json::from_value(args[1].clone()).expect("Bad argument");
+    trace!("apply_generate {:?} {}", location, name);
+
+    let boilerplate = Boilerplate::from_name(&name).expect("Bad argument");
+    match generate::generate(ctx, &location, boilerplate) {
+        Some(edit) => Ok(ApplyWorkspaceEditParams { edit }),
+        None => Err(ResponseError::Message(
+            ErrorCode::InvalidParams,
+            "No struct with named fields at this position".to_owned(),
+        )),
+    }
+}
+
+fn apply_move_module(
+    args: &[serde_json::Value],
+    ctx: &InitActionContext,
+) -> Result<ApplyWorkspaceEditParams, ResponseError> {
+    let location: Location = serde_json::from_value(args[0].clone()).expect("Bad argument");
+    let name: String = serde_json::from_value(args[1].clone()).expect("Bad argument");
+    trace!("apply_move_module {:?} {}", location, name);
+
+    let kind = MoveModule::from_name(&name).expect("Bad argument");
+    match move_module::move_module(ctx, &location, kind) {
+        Some(edit) => Ok(ApplyWorkspaceEditParams { edit }),
+        None => Err(ResponseError::Message(
+            ErrorCode::InvalidParams,
+            "No module which can be moved at this position".to_owned(),
+        )),
+    }
+}
+
+fn apply_change_signature(
+    args: &[serde_json::Value],
+    ctx: &InitActionContext,
+) -> Result<ExecuteCommandResponse, ResponseError> {
+    let arguments = serde_json::Value::Array(args.to_vec());
+    let (location, parameters): (Location, Vec<Parameter>) = serde_json::from_value(arguments)
+        .map_err(|_| {
+            ResponseError::Message(
+                ErrorCode::InvalidParams,
+                "Expected a location and the new parameters".to_owned(),
+            )
+        })?;
+    trace!("apply_change_signature {:?} {:?}", location, parameters);
+
+    let change =
+        change_signature::change_signature(ctx, &location, &parameters).map_err(|message| {
+            ResponseError::Message(
+                ErrorCode::InvalidParams,
+                format!("Cannot change signature: {}", message),
+            )
+        })?;
+    let params = ApplyWorkspaceEditParams { edit: change.edit };
+    if change.skipped.is_empty() {
+        return Ok(ExecuteCommandResponse::ApplyEdit(params));
+    }
+
+    // Tell the user which call sites are left for them to update.
+    let skipped = change.skipped.iter().map(|(location, reason)| {
+        let path = location.uri.to_file_path().unwrap_or_default();
+        let path = path.strip_prefix(&ctx.current_project).unwrap_or_else(|_| path.as_path());
+        let start = location.range.start;
+        format!("{}:{}:{} ({})", path.display(), start.line + 1, start.character + 1, reason)
+    });
+    let message = format!(
+        "Could not update {} call site(s): {}",
+        change.skipped.len(),
+        skipped.collect::<Vec<_>>().join(", ")
+    );
+    Ok(ExecuteCommandResponse::ApplyEditWithWarning(params, message))
+}
+
+fn apply_organize_imports(
+    args: &[serde_json::Value],
+    ctx: &InitActionContext,
+) -> Result<ApplyWorkspaceEditParams, ResponseError> {
+    let uri: Url = serde_json::from_value(args[0].clone()).expect("Bad argument");
+    trace!("apply_organize_imports {:?}", uri);
+
+    let file_path = parse_file_path!(&uri, "organize_imports")?;
+    let edits = organize_imports::organize_imports(ctx, &file_path);
+    let changes: HashMap<_, _> = vec![(uri, edits)].into_iter().collect();
+    Ok(ApplyWorkspaceEditParams {
+        edit: WorkspaceEdit { changes: Some(changes), document_changes: None },
+    })
+}
+
 /// Creates `CodeAction`s for fixes suggested by the compiler.
 /// The results are appended to `code_actions_result`.
 fn make_suggestion_fix_actions(
     params: &<CodeAction as lsp_data::request::Request>::Params,
     file_path: &Path,
     ctx: &InitActionContext,
-    code_actions_result: &mut <CodeAction as RequestAction>::Response,
+    code_actions_result: &mut Vec<Command>,
 ) {
     // Search for compiler suggestions.
     if let Some(results) = ctx.previous_build_results.lock().unwrap().get(file_path) {
@@ -509,13 +1077,138 @@ fn make_suggestion_fix_actions(
     }
 }
 
+/// Creates `CodeAction`s for adding stubs of the missing trait items to an
+/// impl, for the "not all trait items implemented" (E0046) errors in range.
+/// The results are appended to `code_actions_result`.
+fn make_missing_members_actions(
+    params: &<CodeAction as lsp_data::request::Request>::Params,
+    file_path: &Path,
+    ctx: &InitActionContext,
+    code_actions_result: &mut Vec<Command>,
+) {
+    let diagnostics: Vec<_> = match ctx.previous_build_results.lock().unwrap().get(file_path) {
+        Some(results) => results
+            .iter()
+            .map(|(diag, _)| diag)
+            .filter(|diag| diag.code == Some(NumberOrString::String("E0046".to_owned())))
+            .filter(|diag| diag.range.overlaps(&params.range))
+            .cloned()
+            .collect(),
+        None => return,
+    };
+    for diag in diagnostics {
+        if let Some((position, new_text, left_out)) =
+            missing_members::missing_member_stubs(ctx, file_path, &diag)
+        {
+            let location =
+                Location::new(params.text_document.uri.clone(), Range::new(position, position));
+            let title = if left_out.is_empty() {
+                "Implement missing members".to_owned()
+            } else {
+                let names: Vec<_> = left_out.iter().map(|name| format!("`{}`", name)).collect();
+                format!("Implement missing members except {}", names.join(", "))
+            };
+            let cmd = Command {
+                title,
+                command: format!("rls.applySuggestion-{}", ctx.pid),
+                arguments: Some(vec![
+                    serde_json::to_value(&location).unwrap(),
+                    serde_json::to_value(&new_text).unwrap(),
+                ]),
+            };
+            code_actions_result.push(cmd);
+        }
+    }
+}
+
+/// Creates `CodeAction`s for importing the names which could not be resolved
+/// in range, one for each candidate path.
+/// The results are appended to `code_actions_result`.
+fn make_auto_import_actions(
+    params: &<CodeAction as lsp_data::request::Request>::Params,
+    file_path: &Path,
+    ctx: &InitActionContext,
+    code_actions_result: &mut Vec<Command>,
+) {
+    let diagnostics: Vec<_> = match ctx.previous_build_results.lock().unwrap().get(file_path) {
+        Some(results) => results
+            .iter()
+            .map(|(diag, _)| diag)
+            .filter(|diag| auto_import::is_unresolved_name(diag))
+            .filter(|diag| diag.range.overlaps(&params.range))
+            .cloned()
+            .collect(),
+        None => return,
+    };
+    for diag in diagnostics {
+        for (path, edit) in auto_import::import_candidates(ctx, file_path, &diag) {
+            let location = Location::new(params.text_document.uri.clone(), edit.range);
+            let cmd = Command {
+                title: format!("Import `{}`", path),
+                command: format!("rls.applySuggestion-{}", ctx.pid),
+                arguments: Some(vec![
+                    serde_json::to_value(&location).unwrap(),
+                    serde_json::to_value(&edit.new_text).unwrap(),
+                ]),
+            };
+            code_actions_result.push(cmd);
+        }
+    }
+}
+
+/// Creates the "Organize imports" action. Unlike the other actions, this is a
+/// proper `CodeAction` (of the `source.organizeImports` kind) if the client
+/// supports them, so that it can be run on save.
+///
+/// Organizing the imports needs to lex the whole file, so the edits are only
+/// computed here when the client explicitly asks for the action (`eager`), and
+/// the action is only offered then if there is anything to organize. Otherwise
+/// the edits are computed by the `rls.organizeImports` command.
+fn make_organize_imports_action(
+    params: &<CodeAction as lsp_data::request::Request>::Params,
+    file_path: &Path,
+    ctx: &InitActionContext,
+    eager: bool,
+    code_actions_result: &mut <CodeAction as RequestAction>::Response,
+) {
+    let title = "Organize imports".to_owned();
+    let uri = params.text_document.uri.clone();
+    let command = Command {
+        title: title.clone(),
+        command: format!("rls.organizeImports-{}", ctx.pid),
+        arguments: Some(vec![serde_json::to_value(&uri).unwrap()]),
+    };
+    if !ctx.client_capabilities.code_action_literal_support {
+        code_actions_result.push(CodeActionOrCommand::Command(command));
+        return;
+    }
+
+    let (edit, command) = if eager {
+        let edits = organize_imports::organize_imports(ctx, file_path);
+        if edits.is_empty() {
+            return;
+        }
+        let changes: HashMap<_, _> = vec![(uri, edits)].into_iter().collect();
+        (Some(WorkspaceEdit { changes: Some(changes), document_changes: None }), None)
+    } else {
+        (None, Some(command))
+    };
+    code_actions_result.push(CodeActionOrCommand::CodeAction(lsp_data::CodeAction {
+        title,
+        kind: Some(ORGANIZE_IMPORTS_KIND.to_owned()),
+        diagnostics: None,
+        edit,
+        command,
+    }));
+}
+
 /// Creates `CodeAction`s for performing deglobbing when a wildcard import is found.
 /// The results are appended to `code_actions_result`.
 fn make_deglob_actions(
     params: &<CodeAction as lsp_data::request::Request>::Params,
     file_path: &Path,
     ctx: &InitActionContext,
-    code_actions_result: &mut <CodeAction as RequestAction>::Response,
+    code_actions_result: &mut Vec<Command>,
 ) {
     // Search for a glob in the line.
     if let Ok(line) = ctx.vfs.load_line(file_path, ls_util::range_to_rls(params.range).row_start) {
@@ -564,37 +1257,124 @@ fn make_deglob_actions(
     };
 }
 
-// Ideally we'd use Rustfmt for this, but reparsing is a bit of a pain.
-fn sort_deglob_str(s: &str) -> String {
-    let mut substrings = s.split(',').map(str::trim).collect::<Vec<_>>();
-    substrings.sort_by(|a, b| {
-        use std::cmp::Ordering;
+/// Creates `CodeAction`s for extracting the selection into a new function or
+/// local variable. The results are appended to `code_actions_result`.
+fn make_extract_actions(
+    params: &<CodeAction as lsp_data::request::Request>::Params,
+    ctx: &InitActionContext,
+    code_actions_result: &mut Vec<Command>,
+) {
+    if params.range.start == params.range.end {
+        return;
+    }
+    let location = Location::new(params.text_document.uri.clone(), params.range);
+    let mut push_action = |title: &str, command: &str| {
+        code_actions_result.push(Command {
+            title: title.to_owned(),
+            command: format!("{}-{}", command, ctx.pid),
+            arguments: Some(vec![serde_json::to_value(&location).unwrap()]),
+        });
+    };
+    if extract::extract_function(ctx, &location).is_some() {
+        push_action("Extract into function", "rls.extractFunction");
+    }
+    if extract::extract_variable(ctx, &location).is_some() {
+        push_action("Extract into local variable", "rls.extractVariable");
+    }
+}
 
-        // Algorithm taken from rustfmt (`rustfmt/src/imports.rs`).
+/// Creates `CodeAction`s for inlining the local variable or function call at
+/// the start of the selection. These are offered even if inlining would be
+/// unsafe, in which case executing them reports the reason.
+/// The results are appended to `code_actions_result`.
+fn make_inline_actions(
+    params: &<CodeAction as lsp_data::request::Request>::Params,
+    ctx: &InitActionContext,
+    code_actions_result: &mut Vec<Command>,
+) {
+    let range = Range::new(params.range.start, params.range.start);
+    let location = Location::new(params.text_document.uri.clone(), range);
+    let mut push_action = |title: &str, command: &str| {
+        code_actions_result.push(Command {
+            title: title.to_owned(),
+            command: format!("{}-{}", command, ctx.pid),
+            arguments: Some(vec![serde_json::to_value(&location).unwrap()]),
+        });
+    };
+    if inline::inline_local(ctx, &location).is_some() {
+        push_action("Inline local variable", "rls.inlineLocal");
+    }
+    if inline::inline_function(ctx, &location).is_some() {
+        push_action("Inline function call", "rls.inlineFunction");
+    }
+}
 
-        let is_upper_snake_case =
-            |s: &str| s.chars().all(|c| c.is_uppercase() || c == '_' || c.is_numeric());
+/// Creates `CodeAction`s for generating boilerplate for the struct at the
+/// start of the range. The results are appended to `code_actions_result`.
+fn make_generate_actions(
+    params: &<CodeAction as lsp_data::request::Request>::Params,
+    ctx: &InitActionContext,
+    code_actions_result: &mut Vec<Command>,
+) {
+    let range = Range::new(params.range.start, params.range.start);
+    let location = Location::new(params.text_document.uri.clone(), range);
+    for boilerplate in generate::available(ctx, &location) {
+        code_actions_result.push(Command {
+            title: boilerplate.title().to_owned(),
+            command: format!("rls.generate-{}", ctx.pid),
+            arguments: Some(vec![
+                serde_json::to_value(&location).unwrap(),
+                serde_json::to_value(boilerplate.name()).unwrap(),
+            ]),
+        });
+    }
+}
 
-        // snake_case < CamelCase < UPPER_SNAKE_CASE
-        if a.starts_with(char::is_uppercase) && b.starts_with(char::is_lowercase) {
-            return Ordering::Greater;
-        }
-        if a.starts_with(char::is_lowercase) && b.starts_with(char::is_uppercase) {
-            return Ordering::Less;
-        }
-        if is_upper_snake_case(a) && !is_upper_snake_case(b) {
-            return Ordering::Greater;
-        }
-        if !is_upper_snake_case(a) && is_upper_snake_case(b) {
-            return Ordering::Less;
-        }
-        a.cmp(b)
-    });
-    substrings.join(", ")
+/// Creates a `CodeAction` for moving the module declared on the line of the
+/// start of the range. The results are appended to `code_actions_result`.
+fn make_move_module_actions(
+    params: &<CodeAction as lsp_data::request::Request>::Params,
+    ctx: &InitActionContext,
+    code_actions_result: &mut Vec<Command>,
+) {
+    let range = Range::new(params.range.start, params.range.start);
+    let location = Location::new(params.text_document.uri.clone(), range);
+    if let Some((kind, module)) = move_module::available(ctx, &location) {
+        code_actions_result.push(Command {
+            title: kind.title(&module),
+            command: format!("rls.moveModule-{}", ctx.pid),
+            arguments: Some(vec![
+                serde_json::to_value(&location).unwrap(),
+                serde_json::to_value(kind.name()).unwrap(),
+            ]),
+        });
+    }
+}
+
+/// Creates a `CodeAction` for adding the missing arms of the `match` on an
+/// enum at the start of the range. The results are appended to
+/// `code_actions_result`.
+fn make_match_arms_actions(
+    params: &<CodeAction as lsp_data::request::Request>::Params,
+    file_path: &Path,
+    ctx: &InitActionContext,
+    code_actions_result: &mut Vec<Command>,
+) {
+    if let Some((range, new_text)) = match_arms::missing_arms(ctx, file_path, params.range.start) {
+        let location = Location::new(params.text_document.uri.clone(), range);
+        code_actions_result.push(Command {
+            title: "Fill match arms".to_owned(),
+            command: format!("rls.applySuggestion-{}", ctx.pid),
+            arguments: Some(vec![
+                serde_json::to_value(&location).unwrap(),
+                serde_json::to_value(&new_text).unwrap(),
+            ]),
+        });
+    }
 }
 
 impl RequestAction for CodeAction {
-    type Response = Vec<Command>;
+    type Response = Vec<CodeActionOrCommand>;
 
     fn fallback_response() -> Result<Self::Response, ResponseError> {
         Ok(vec![])
@@ -608,14 +1388,42 @@ impl RequestAction for CodeAction {
 
         let file_path = parse_file_path!(&params.text_document.uri, "code_action")?;
 
+        // Clients may only ask for source actions, e.g., to organize imports
+        // on save.
+        let only_source = match params.context.only {
+            Some(ref kinds) => kinds.iter().all(|kind| kind.starts_with("source")),
+            None => false,
+        };
         let mut cmds = vec![];
-        if ctx.build_ready() {
+        if ctx.build_ready() && !only_source {
             make_suggestion_fix_actions(&params, &file_path, &ctx, &mut cmds);
         }
-        if ctx.analysis_ready() {
+        if ctx.analysis_ready() && !only_source {
             make_deglob_actions(&params, &file_path, &ctx, &mut cmds);
+            make_extract_actions(&params, &ctx, &mut cmds);
+            make_inline_actions(&params, &ctx, &mut cmds);
+            make_match_arms_actions(&params, &file_path, &ctx, &mut cmds);
+            make_generate_actions(&params, &ctx, &mut cmds);
+            make_move_module_actions(&params, &ctx, &mut cmds);
+        }
+        if ctx.build_ready() && ctx.analysis_ready() && !only_source {
+            make_missing_members_actions(&params, &file_path, &ctx, &mut cmds);
+            make_auto_import_actions(&params, &file_path, &ctx, &mut cmds);
+        }
+        let mut actions: Vec<_> = cmds.into_iter().map(CodeActionOrCommand::Command).collect();
+        match params.context.only {
+            Some(ref kinds) => {
+                let wants_organize_imports = kinds.iter().any(|kind| {
+                    kind == ORGANIZE_IMPORTS_KIND
+                        || ORGANIZE_IMPORTS_KIND.starts_with(&format!("{}.", kind))
+                });
+                if wants_organize_imports {
+                    make_organize_imports_action(&params, &file_path, &ctx, true, &mut actions);
+                }
+            }
+            None => make_organize_imports_action(&params, &file_path, &ctx, false, &mut actions),
         }
-        Ok(cmds)
+        Ok(actions)
     }
 }
 
@@ -655,6 +1463,42 @@ impl RequestAction for RangeFormatting {
     }
 }
 
+impl RequestAction for OnTypeFormatting {
+    type Response = Vec<TextEdit>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(vec![])
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        if !ctx.config.lock().unwrap().format_on_type {
+            return Ok(vec![]);
+        }
+        let path = parse_file_path!(&params.text_document.uri, "on_type_formatting")?;
+        let input = match ctx.vfs.load_file(&path) {
+            Ok(FileContents::Text(s)) => s,
+            _ => return Ok(vec![]),
+        };
+        let range = match format::on_type_range(&input, params.position, &params.ch) {
+            Some(range) => range,
+            None => return Ok(vec![]),
+        };
+
+        // The file is often incomplete while typing, so Rustfmt failing to
+        // parse it is not worth reporting.
+        match reformat(&params.text_document, Some(range), &params.options, &ctx) {
+            Ok(edits) => Ok(format::minimal_edits(&input, edits)),
+            Err(e) => {
+                debug!("on_type_formatting: reformat failed: {:?}", e);
+                Ok(vec![])
+            }
+        }
+    }
+}
+
 fn reformat(
     doc: &TextDocumentIdentifier,
     selection: Option<Range>,
@@ -816,15 +1660,13 @@ mod test {
     use super::*;
 
     #[test]
-    fn test_sort_deglob_str() {
-        assert_eq!(sort_deglob_str(""), "");
-        assert_eq!(sort_deglob_str("foo"), "foo");
-        assert_eq!(sort_deglob_str("a, b"), "a, b");
-        assert_eq!(sort_deglob_str("b, a"), "a, b");
-        assert_eq!(sort_deglob_str("foo, bar, baz"), "bar, baz, foo");
-        assert_eq!(
-            sort_deglob_str("Curve, curve, ARC, bow, Bow, arc, Arc"),
-            "arc, bow, curve, Arc, Bow, Curve, ARC",
-        );
+    fn test_base_type_path() {
+        assert_eq!(base_type_path("Foo"), Some("Foo"));
+        assert_eq!(base_type_path("&mut std::vec::Vec<u8>"), Some("std::vec::Vec"));
+        assert_eq!(base_type_path("&'a [Foo; 3]"), Some("Foo"));
+        assert_eq!(base_type_path("*const Bar"), Some("Bar"));
+        assert_eq!(base_type_path("Box<dyn Fn()>"), Some("Box"));
+        assert_eq!(base_type_path("(u8, u8)"), None);
+        assert_eq!(base_type_path(""), None);
     }
 }
@@ -0,0 +1,295 @@
+//! Selection ranges ("expand selection") for Rust source files.
+//!
+//! Identifiers and items are taken from the save-analysis data, the levels in
+//! between (arguments, expressions, statements and blocks) are found by
+//! matching up brackets and punctuation in the source text.
+
+use std::collections::HashSet;
+
+use log::trace;
+use rls_analysis::{DefKind, Id};
+use rls_vfs::FileContents;
+
+use crate::actions::lexical::{is_ident_char, span_to_offsets, Lexed, OffsetRange};
+use crate::actions::InitActionContext;
+use crate::lsp_data::*;
+use crate::server::ResponseError;
+use crate::Span;
+
+/// Computes the selection ranges for each of the given positions.
+pub fn selection_ranges(
+    ctx: &InitActionContext,
+    params: &SelectionRangeParams,
+) -> Result<Vec<SelectionRange>, ResponseError> {
+    let file_path = parse_file_path!(&params.text_document.uri, "selection_range")?;
+
+    let text = match ctx.vfs.load_file(&file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return Ok(vec![]),
+    };
+    let chars: Vec<char> = text.chars().collect();
+    let lexed = Lexed::new(&text);
+
+    // Top-level items of this file, i.e., those not nested in another item
+    // in this file. Nested items are found with `for_each_child_def`.
+    let symbols = ctx.analysis.symbols(&file_path).unwrap_or_else(|_| vec![]);
+    let ids: HashSet<Id> = symbols.iter().map(|s| s.id).collect();
+    let roots: Vec<_> = symbols
+        .into_iter()
+        .filter(|s| s.parent.map(|p| !ids.contains(&p)).unwrap_or(true))
+        .map(|s| (s.id, s.kind, s.span))
+        .collect();
+
+    Ok(params
+        .positions
+        .iter()
+        .map(|&pos| {
+            let offset = lexed.offset(pos.line as usize, pos.character as usize);
+            let mut ranges = vec![];
+
+            let span = ctx.convert_pos_to_span(file_path.clone(), pos);
+            for ident in ctx.analysis.idents(&span).unwrap_or_else(|_| vec![]) {
+                ranges.push(span_to_offsets(&lexed, &ident.span));
+            }
+
+            let mut level = roots.clone();
+            while let Some((id, extent)) = level
+                .iter()
+                .filter(|(_, kind, span)| is_item(*kind) && span.file == file_path)
+                .filter_map(|(id, _, span)| item_extent(&lexed, span).map(|e| (*id, e)))
+                .find(|&(_, (start, end))| start <= offset && offset <= end)
+            {
+                ranges.push(extent);
+                level = ctx
+                    .analysis
+                    .for_each_child_def(id, |id, def| (id, def.kind, def.span.clone()))
+                    .unwrap_or_else(|_| vec![]);
+            }
+
+            ranges.extend(lexical_ranges(&chars, &lexed, offset));
+            trace!("selection_range: {} candidate ranges at {:?}", ranges.len(), pos);
+            nest_ranges(&lexed, offset, ranges)
+        })
+        .collect())
+}
+
+fn is_item(kind: DefKind) -> bool {
+    match kind {
+        DefKind::Function
+        | DefKind::Method
+        | DefKind::Mod
+        | DefKind::Struct
+        | DefKind::Enum
+        | DefKind::Union
+        | DefKind::Trait
+        | DefKind::Type
+        | DefKind::Const
+        | DefKind::Static
+        | DefKind::Macro => true,
+        _ => false,
+    }
+}
+
+/// The extent of an item, given the span of its name.
+fn item_extent(lexed: &Lexed, name: &Span) -> Option<OffsetRange> {
+    lexed.item_extent(span_to_offsets(lexed, name).0)
+}
+
+/// Computes the ranges which can be found from the text alone: the word at
+/// `offset`, and the arguments, expressions, statements and blocks around it.
+fn lexical_ranges(chars: &[char], lexed: &Lexed, offset: usize) -> Vec<OffsetRange> {
+    let code = &lexed.code;
+    let mut ranges = vec![];
+
+    // The word under the cursor (this also works in comments and strings).
+    let mut start = offset;
+    while start > 0 && is_ident_char(chars[start - 1]) {
+        start -= 1;
+    }
+    let mut end = offset;
+    while end < chars.len() && is_ident_char(chars[end]) {
+        end += 1;
+    }
+    ranges.push((start, end));
+
+    // Match up all brackets to find those enclosing the cursor.
+    let mut stack = vec![];
+    let mut enclosing = vec![];
+    for (i, &c) in code.iter().enumerate() {
+        match c {
+            '(' | '[' | '{' => stack.push(i),
+            ')' | ']' | '}' => {
+                if let Some(open) = stack.pop() {
+                    if open < offset && offset <= i {
+                        enclosing.push((open, i));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for &(open, close) in &enclosing {
+        ranges.push(lexed.trim(open + 1, close));
+        ranges.push((open, close + 1));
+
+        // Include the callee, e.g., `foo::bar(..)`, `x.baz[..]` or `vec![..]`.
+        if code[open] != '{' {
+            let mut start = open;
+            while start > 0 && (is_ident_char(code[start - 1]) || ":.!".contains(code[start - 1])) {
+                start -= 1;
+            }
+            ranges.push((start, close + 1));
+        }
+
+        if let Some(segment) = enclosing_segment(lexed, open + 1, close, offset) {
+            ranges.push(segment);
+        }
+    }
+    if let Some(segment) = enclosing_segment(lexed, 0, code.len(), offset) {
+        ranges.push(segment);
+    }
+
+    ranges
+}
+
+/// Finds the part of `code[start..end]` which contains `offset`, split by
+/// commas between brackets or by statements in blocks.
+pub fn enclosing_segment(
+    lexed: &Lexed,
+    start: usize,
+    end: usize,
+    offset: usize,
+) -> Option<OffsetRange> {
+    let code = &lexed.code;
+    let in_block = start == 0 || code[start - 1] == '{';
+    let mut depth = 0;
+    let mut segment_start = start;
+    let mut i = start;
+    while i < end {
+        let c = code[i];
+        let split = match c {
+            '(' | '[' | '{' => {
+                depth += 1;
+                None
+            }
+            ')' | ']' => {
+                depth -= 1;
+                None
+            }
+            '}' => {
+                depth -= 1;
+                // A block ends a statement, unless the expression continues
+                // after it (e.g., `if .. {} else {}` or `match .. {}.foo()`).
+                let rest: String = code[i + 1..end].iter().collect();
+                let rest = rest.trim_start();
+                let continues = rest.starts_with(|c| ".?;,)=".contains(c))
+                    || rest.starts_with("else")
+                    || rest.starts_with("as ");
+                if in_block && depth == 0 && !continues {
+                    Some(i + 1)
+                } else {
+                    None
+                }
+            }
+            ';' if in_block && depth == 0 => Some(i + 1),
+            ',' if !in_block && depth == 0 => Some(i),
+            _ => None,
+        };
+        if let Some(split) = split {
+            if segment_start <= offset && offset <= split {
+                return Some(lexed.trim(segment_start, split));
+            }
+            segment_start = i + 1;
+        }
+        i += 1;
+    }
+    if segment_start <= offset {
+        Some(lexed.trim(segment_start, end))
+    } else {
+        None
+    }
+}
+
+/// Orders the candidate ranges from smallest to largest, dropping any which
+/// don't contain the cursor or don't nest properly, and links them up.
+fn nest_ranges(lexed: &Lexed, offset: usize, mut ranges: Vec<OffsetRange>) -> SelectionRange {
+    ranges.push((0, lexed.code.len()));
+    ranges.retain(|&(start, end)| start <= offset && offset <= end);
+    ranges.sort_by_key(|&(start, end)| (end - start, start));
+    ranges.dedup();
+
+    let mut nested: Vec<OffsetRange> = vec![];
+    for range in ranges {
+        match nested.last() {
+            Some(&(start, end)) if range.0 > start || range.1 < end => {}
+            _ => nested.push(range),
+        }
+    }
+
+    let to_range = |(start, end): OffsetRange| {
+        let (start_row, start_col) = lexed.position(start);
+        let (end_row, end_col) = lexed.position(end);
+        Range::new(
+            Position::new(start_row as u64, start_col as u64),
+            Position::new(end_row as u64, end_col as u64),
+        )
+    };
+    let mut selection: Option<SelectionRange> = None;
+    for range in nested.into_iter().rev() {
+        selection =
+            Some(SelectionRange { range: to_range(range), parent: selection.map(Box::new) });
+    }
+    selection.expect("the whole text always contains the cursor")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn selections(text: &str, row: usize, col: usize) -> Vec<String> {
+        let chars: Vec<char> = text.chars().collect();
+        let lexed = Lexed::new(text);
+        let offset = lexed.offset(row, col);
+        let ranges = lexical_ranges(&chars, &lexed, offset);
+
+        let mut result = vec![];
+        let mut selection = Some(nest_ranges(&lexed, offset, ranges));
+        while let Some(s) = selection {
+            let start = lexed.offset(s.range.start.line as usize, s.range.start.character as usize);
+            let end = lexed.offset(s.range.end.line as usize, s.range.end.character as usize);
+            result.push(chars[start..end].iter().collect());
+            selection = s.parent.map(|p| *p);
+        }
+        result
+    }
+
+    #[test]
+    fn test_lexical_selection_ranges() {
+        let text = "fn foo() {\n    let x = bar(a, b + 1);\n    baz();\n}\n";
+        assert_eq!(
+            selections(text, 1, 20),
+            vec![
+                "b",
+                "b + 1",
+                "a, b + 1",
+                "(a, b + 1)",
+                "bar(a, b + 1)",
+                "let x = bar(a, b + 1);",
+                "let x = bar(a, b + 1);\n    baz();",
+                "{\n    let x = bar(a, b + 1);\n    baz();\n}",
+                "fn foo() {\n    let x = bar(a, b + 1);\n    baz();\n}",
+                text,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_selection_ranges_block_statements() {
+        let text = "if a {\n    b\n} else {\n    c\n}\nd;";
+        assert_eq!(
+            selections(text, 3, 4),
+            vec!["c", "{\n    c\n}", "if a {\n    b\n} else {\n    c\n}", text,]
+        );
+    }
+}
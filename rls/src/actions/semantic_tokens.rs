@@ -0,0 +1,266 @@
+//! Semantic tokens: a classification of the identifiers of a document, so
+//! that editors can highlight them beyond what a grammar can tell.
+//!
+//! Identifiers are classified using the save-analysis data, macros and
+//! lifetimes (which are not recorded there) are found by a lexical scan.
+
+use std::collections::HashMap;
+
+use log::trace;
+use rls_analysis::{Def, DefKind, Id, IdentKind};
+use rls_span::{Column, Row};
+
+use crate::actions::lexical::{self, is_ident_char, Lexed, SourceFiles};
+use crate::actions::InitActionContext;
+use crate::lsp_data::*;
+use crate::server::ResponseError;
+use crate::Span;
+
+/// The token types, in the order of the legend.
+const TOKEN_TYPES: &[&str] = &[
+    "namespace",
+    "type",
+    "struct",
+    "union",
+    "enum",
+    "enumMember",
+    "interface",
+    "function",
+    "method",
+    "macro",
+    "variable",
+    "parameter",
+    "property",
+    "lifetime",
+];
+
+/// The token modifiers, in the order of the legend.
+const TOKEN_MODIFIERS: &[&str] =
+    &["declaration", "static", "readonly", "mutable", "unsafe", "defaultLibrary"];
+
+/// The legend advertised in the server capabilities.
+pub fn legend() -> SemanticTokensLegend {
+    SemanticTokensLegend {
+        token_types: TOKEN_TYPES.iter().map(|s| s.to_string()).collect(),
+        token_modifiers: TOKEN_MODIFIERS.iter().map(|s| s.to_string()).collect(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Token {
+    row: u32,
+    col: u32,
+    len: u32,
+    token_type: u32,
+    modifiers: u32,
+}
+
+impl Token {
+    fn new(row: u32, col: u32, len: u32, token_type: &str, modifiers: &[&str]) -> Token {
+        let index = |legend: &[&str], name| legend.iter().position(|n| *n == name).unwrap() as u32;
+        Token {
+            row,
+            col,
+            len,
+            token_type: index(TOKEN_TYPES, token_type),
+            modifiers: modifiers.iter().fold(0, |bits, m| bits | 1 << index(TOKEN_MODIFIERS, m)),
+        }
+    }
+}
+
+/// Computes the semantic tokens of the given document.
+pub fn semantic_tokens(
+    ctx: &InitActionContext,
+    params: &SemanticTokensParams,
+) -> Result<SemanticTokens, ResponseError> {
+    let file_path = parse_file_path!(&params.text_document.uri, "semantic_tokens")?;
+
+    let mut sources = SourceFiles::new(&ctx.vfs);
+    let line_count = match sources.lexed(&file_path) {
+        Some(lexed) => lexed.line_count(),
+        None => return Ok(SemanticTokens { data: vec![] }),
+    };
+
+    // `idents` checks the columns of the span on every line, so ask for all
+    // the columns of every line.
+    let file_span = Span::new(
+        Row::new_zero_indexed(0),
+        Row::new_zero_indexed(line_count as u32),
+        Column::new_zero_indexed(0),
+        Column::new_zero_indexed(u32::max_value()),
+        file_path.clone(),
+    );
+    let mut tokens = vec![];
+    // Whether a def is `mut`, by id. A variable is usually used many times,
+    // but its declaration only needs to be checked once.
+    let mut mutability: HashMap<Id, bool> = HashMap::new();
+    for ident in ctx.analysis.idents(&file_span).unwrap_or_else(|_| vec![]) {
+        let def = match ctx.analysis.get_def(ident.id) {
+            Ok(def) => def,
+            Err(_) => continue,
+        };
+        let token_type = token_type(&def);
+        let mut modifiers = vec![];
+        if ident.kind == IdentKind::Def {
+            modifiers.push("declaration");
+        }
+        match def.kind {
+            DefKind::Static | DefKind::ForeignStatic => modifiers.push("static"),
+            DefKind::Const => modifiers.push("readonly"),
+            _ => {}
+        }
+        match def.kind {
+            DefKind::Local | DefKind::Static
+                if *mutability
+                    .entry(ident.id)
+                    .or_insert_with(|| is_mut(&mut sources, &def.span)) =>
+            {
+                modifiers.push("mutable")
+            }
+            DefKind::Function | DefKind::Method if is_unsafe(&def) => modifiers.push("unsafe"),
+            DefKind::ForeignFunction => modifiers.push("unsafe"),
+            _ => {}
+        }
+        if def.distro_crate {
+            modifiers.push("defaultLibrary");
+        }
+
+        let range = ident.span.range;
+        let len = range.col_end.0.saturating_sub(range.col_start.0);
+        tokens.push(Token::new(range.row_start.0, range.col_start.0, len, token_type, &modifiers));
+    }
+    trace!("semantic_tokens: {} tokens from analysis", tokens.len());
+
+    if let Some(lexed) = sources.lexed(&file_path) {
+        tokens.extend(lexical_tokens(lexed));
+    }
+    Ok(SemanticTokens { data: encode(tokens) })
+}
+
+fn token_type(def: &Def) -> &'static str {
+    match def.kind {
+        DefKind::Mod => "namespace",
+        DefKind::Type | DefKind::ExternType => "type",
+        DefKind::Struct | DefKind::Tuple => "struct",
+        DefKind::Union => "union",
+        DefKind::Enum => "enum",
+        DefKind::TupleVariant | DefKind::StructVariant => "enumMember",
+        DefKind::Trait => "interface",
+        DefKind::Function | DefKind::ForeignFunction => "function",
+        DefKind::Method => "method",
+        DefKind::Macro => "macro",
+        // Function arguments don't have a `$` in their qualname, other
+        // locals do (see also `hover::tooltip`).
+        DefKind::Local if !def.qualname.contains('$') => "parameter",
+        DefKind::Local | DefKind::Static | DefKind::ForeignStatic | DefKind::Const => "variable",
+        DefKind::Field => "property",
+    }
+}
+
+/// `true` if the variable or static defined at `name` is declared `mut`.
+fn is_mut(sources: &mut SourceFiles, name: &Span) -> bool {
+    let offset = match sources.offset(name) {
+        Some(offset) => offset,
+        None => return false,
+    };
+    let code = match sources.lexed(&name.file) {
+        Some(lexed) => &lexed.code,
+        None => return false,
+    };
+    lexical::is_mut(code, offset)
+}
+
+fn is_unsafe(def: &Def) -> bool {
+    let sig = def.sig.as_ref().map(|sig| &sig.text).unwrap_or(&def.value);
+    sig.starts_with("unsafe ") || sig.contains(" unsafe ")
+}
+
+/// Finds the macro invocations and lifetimes in the text.
+fn lexical_tokens(lexed: &Lexed) -> Vec<Token> {
+    let code = &lexed.code;
+    let mut tokens = vec![];
+
+    let mut i = 0;
+    while i < code.len() {
+        let is_start = i == 0 || !is_ident_char(code[i - 1]);
+        if code[i] == '\'' || (is_start && (code[i].is_alphabetic() || code[i] == '_')) {
+            let start = i;
+            i += 1;
+            while i < code.len() && is_ident_char(code[i]) {
+                i += 1;
+            }
+            let (row, col) = lexed.position(start);
+            let token = |token_type| {
+                Token::new(row as u32, col as u32, (i - start) as u32, token_type, &[])
+            };
+
+            if code[start] == '\'' {
+                if i > start + 1 {
+                    tokens.push(token("lifetime"));
+                }
+                continue;
+            }
+            // A macro invocation, `foo!(..)`, but not `foo != bar`.
+            if code.get(i) == Some(&'!') {
+                let next = code[i + 1..].iter().find(|c| !c.is_whitespace());
+                if let Some('(') | Some('[') | Some('{') = next {
+                    tokens.push(token("macro"));
+                }
+            }
+            continue;
+        }
+        i += 1;
+    }
+    tokens
+}
+
+/// Sorts the tokens and encodes them relative to the previous token, as
+/// described on `SemanticTokens`.
+fn encode(mut tokens: Vec<Token>) -> Vec<u32> {
+    tokens.sort_by_key(|t| (t.row, t.col));
+    tokens.dedup_by_key(|t| (t.row, t.col));
+
+    let mut data = Vec::with_capacity(tokens.len() * 5);
+    let (mut row, mut col) = (0, 0);
+    for token in tokens {
+        let delta_col = if token.row == row { token.col - col } else { token.col };
+        data.extend_from_slice(&[
+            token.row - row,
+            delta_col,
+            token.len,
+            token.token_type,
+            token.modifiers,
+        ]);
+        row = token.row;
+        col = token.col;
+    }
+    data
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lexical_tokens() {
+        let text = "fn f<'a>(x: &'a str) {\n    println!(\"{}\", x != 'b');\n    vec! [1];\n}";
+        let tokens = encode(lexical_tokens(&Lexed::new(text)));
+        let lifetime = TOKEN_TYPES.iter().position(|t| *t == "lifetime").unwrap() as u32;
+        let mac = TOKEN_TYPES.iter().position(|t| *t == "macro").unwrap() as u32;
+        assert_eq!(
+            tokens,
+            vec![0, 5, 2, lifetime, 0, 0, 8, 2, lifetime, 0, 1, 4, 7, mac, 0, 1, 4, 3, mac, 0]
+        );
+    }
+
+    #[test]
+    fn test_encode_tokens() {
+        let tokens = vec![
+            Token::new(2, 4, 3, "variable", &["declaration", "mutable"]),
+            Token::new(0, 3, 4, "function", &["declaration"]),
+            Token::new(2, 10, 1, "struct", &[]),
+            Token::new(0, 3, 4, "function", &["declaration"]),
+        ];
+        assert_eq!(encode(tokens), vec![0, 3, 4, 7, 1, 2, 4, 3, 10, 9, 0, 6, 1, 2, 0]);
+    }
+}
@@ -0,0 +1,388 @@
+//! Signature help (parameter hints) for function and method calls.
+
+use log::{debug, trace};
+use rls_analysis::{Def, DefKind};
+use rls_span::{Column, Row, Span, ZeroIndexed};
+use rls_vfs::FileContents;
+
+use crate::actions::hover;
+use crate::actions::lexical::{is_ident_char, skip_whitespace, Lexed};
+use crate::actions::requests;
+use crate::actions::InitActionContext;
+use crate::lsp_data::*;
+use crate::server::ResponseError;
+
+/// The call surrounding the cursor, as found by scanning the source text.
+#[derive(Debug, PartialEq, Eq)]
+struct CallSite {
+    /// Zero-indexed row of the callee name.
+    row: usize,
+    /// Zero-indexed char columns of the callee name.
+    name_start: usize,
+    name_end: usize,
+    /// The number of arguments preceding the cursor.
+    active_arg: usize,
+    /// `true` if the callee is called with the method-call syntax (`a.foo()`).
+    is_method_call: bool,
+}
+
+/// Computes signature help for the call surrounding the cursor. The signature
+/// is taken from the save-analysis data if available, otherwise we fall back
+/// to racer (if enabled).
+pub fn signature_help(
+    ctx: &InitActionContext,
+    params: &TextDocumentPositionParams,
+) -> Result<Option<SignatureHelp>, ResponseError> {
+    let file_path = parse_file_path!(&params.text_document.uri, "signature_help")?;
+
+    let text = match ctx.vfs.load_file(&file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return Ok(None),
+    };
+    let call = match find_call_site(
+        &text,
+        params.position.line as usize,
+        params.position.character as usize,
+    ) {
+        Some(call) => call,
+        None => return Ok(None),
+    };
+    trace!("signature_help: call site: {:?}", call);
+
+    let row = Row::new_zero_indexed(call.row as u32);
+    let span = Span::new(
+        row,
+        row,
+        Column::new_zero_indexed(call.name_start as u32),
+        Column::new_zero_indexed(call.name_end as u32),
+        file_path.clone(),
+    );
+
+    let def = ctx.analysis.id(&span).and_then(|id| ctx.analysis.get_def(id)).ok();
+    let signature = match def {
+        Some(ref def) if ctx.analysis_ready() || def.sig.is_some() => signature_from_def(def),
+        _ => {
+            let racer_enabled = ctx.config.lock().unwrap().racer_completion;
+            if racer_enabled {
+                debug!("signature_help: no analysis for callee, attempting with racer");
+                racer_signature(ctx, &span)
+            } else {
+                None
+            }
+        }
+    };
+
+    Ok(signature.map(|(label, docs)| {
+        let active_parameter = active_parameter(&label, &call);
+        SignatureHelp {
+            signatures: vec![signature_information(label, docs)],
+            active_signature: Some(0),
+            active_parameter: Some(active_parameter as i64),
+        }
+    }))
+}
+
+/// Returns the signature text and documentation for a callable `Def`.
+fn signature_from_def(def: &Def) -> Option<(String, String)> {
+    match def.kind {
+        DefKind::Function
+        | DefKind::Method
+        | DefKind::ForeignFunction
+        | DefKind::Struct
+        | DefKind::Tuple
+        | DefKind::TupleVariant => {}
+        _ => return None,
+    }
+
+    let label = match def.sig {
+        Some(ref sig) => sig.text.clone(),
+        // Without signatures in the save-analysis data, we can still make do
+        // with the value, which contains the type of the function.
+        None if !def.value.is_empty() => def.value.clone(),
+        None => return None,
+    };
+
+    Some((label, def.docs.clone()))
+}
+
+/// Uses racer to find the definition of the callee at `span`.
+fn racer_signature(ctx: &InitActionContext, span: &Span<ZeroIndexed>) -> Option<(String, String)> {
+    let cache = ctx.racer_cache();
+    let session = ctx.racer_session(&cache);
+    let coord = requests::racer_coord(span.range.row_end.one_indexed(), span.range.col_end);
+    let location = racer::Location::Coords(coord);
+
+    let m = racer::find_definition(&span.file, location, &session)?;
+    trace!("signature_help: racer match: {:?}", m);
+    match m.mtype {
+        racer::MatchType::Function | racer::MatchType::Method(_) => {}
+        _ => return None,
+    }
+    let label = m.contextstr.trim_end_matches('{').trim().to_string();
+    Some((label, m.docs))
+}
+
+fn signature_information(label: String, docs: String) -> SignatureInformation {
+    let parameters = parameter_ranges(&label)
+        .into_iter()
+        .map(|(start, end)| ParameterInformation {
+            label: ParameterLabel::LabelOffsets([
+                utf16_len(&label[..start]),
+                utf16_len(&label[..end]),
+            ]),
+            documentation: None,
+        })
+        .collect();
+    let documentation = if docs.trim().is_empty() {
+        None
+    } else {
+        Some(Documentation::MarkupContent(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: hover::process_docs(&docs),
+        }))
+    };
+
+    SignatureInformation { label, documentation, parameters: Some(parameters) }
+}
+
+/// The index of the parameter under the cursor. If the callee is a method
+/// called using the method-call syntax, its `self` parameter is skipped.
+fn active_parameter(label: &str, call: &CallSite) -> usize {
+    let has_self_param = parameter_ranges(label)
+        .first()
+        .map(|&(start, end)| is_self_param(&label[start..end]))
+        .unwrap_or(false);
+
+    if call.is_method_call && has_self_param {
+        call.active_arg + 1
+    } else {
+        call.active_arg
+    }
+}
+
+pub fn is_self_param(param: &str) -> bool {
+    let param = param.trim_start_matches('&').trim_start();
+    let param = if param.starts_with('\'') {
+        // Skip a lifetime, e.g. `&'a self`.
+        param.splitn(2, char::is_whitespace).nth(1).unwrap_or("").trim_start()
+    } else {
+        param
+    };
+    let param = if param.starts_with("mut ") { &param[4..] } else { param };
+    param == "self" || param.starts_with("self:")
+}
+
+fn utf16_len(s: &str) -> u64 {
+    s.encode_utf16().count() as u64
+}
+
+/// Returns byte ranges of the parameters of the first parameter list in a
+/// signature such as `pub fn foo<T: Fn(u8)>(a: T, b: (u8, u8)) -> bool`.
+pub fn parameter_ranges(sig: &str) -> Vec<(usize, usize)> {
+    // Find the opening parenthesis of the parameter list, skipping any
+    // generics which might also contain parentheses.
+    let mut generics_depth = 0usize;
+    let mut prev = ' ';
+    let mut open = None;
+    for (i, c) in sig.char_indices() {
+        match c {
+            '<' => generics_depth += 1,
+            '>' if prev != '-' => generics_depth = generics_depth.saturating_sub(1),
+            '(' if generics_depth == 0 => {
+                open = Some(i);
+                break;
+            }
+            _ => {}
+        }
+        prev = c;
+    }
+    let open = match open {
+        Some(open) => open,
+        None => return vec![],
+    };
+
+    let mut result = vec![];
+    let mut depth = 0usize;
+    let mut start = open + 1;
+    let mut prev = ' ';
+    for (i, c) in sig[open + 1..].char_indices() {
+        let i = i + open + 1;
+        match c {
+            '(' | '[' | '{' | '<' => depth += 1,
+            '>' if prev == '-' => {}
+            ')' | ']' | '}' | '>' if depth > 0 => depth -= 1,
+            ',' if depth == 0 => {
+                push_trimmed(sig, start, i, &mut result);
+                start = i + 1;
+            }
+            ')' => {
+                push_trimmed(sig, start, i, &mut result);
+                break;
+            }
+            _ => {}
+        }
+        prev = c;
+    }
+    result
+}
+
+fn push_trimmed(text: &str, start: usize, end: usize, ranges: &mut Vec<(usize, usize)>) {
+    let slice = &text[start..end];
+    let trimmed_start = start + (slice.len() - slice.trim_start().len());
+    let trimmed_end = end - (slice.len() - slice.trim_end().len());
+    if trimmed_start < trimmed_end {
+        ranges.push((trimmed_start, trimmed_end));
+    }
+}
+
+/// Scans backwards from the cursor (given as a zero-indexed row and char
+/// column) for the opening parenthesis of the enclosing call, counting the
+/// arguments before the cursor on the way.
+fn find_call_site(text: &str, row: usize, col: usize) -> Option<CallSite> {
+    if row >= text.lines().count() {
+        return None;
+    }
+    let lexed = Lexed::new(text);
+    let cursor = lexed.offset(row, col);
+    // The code before the cursor, reversed so that it can be scanned
+    // forwards: index `i` is at offset `cursor - 1 - i`.
+    let rev: Vec<char> = lexed.code[..cursor].iter().rev().cloned().collect();
+
+    let mut i = 0;
+    let mut depth = 0usize;
+    let mut commas = 0;
+    let open_paren = loop {
+        match *rev.get(i)? {
+            ')' | ']' | '}' => depth += 1,
+            '(' if depth == 0 => break i,
+            '[' | '{' if depth == 0 => return None,
+            '(' | '[' | '{' => depth -= 1,
+            ',' if depth == 0 => commas += 1,
+            ';' if depth == 0 => return None,
+            _ => {}
+        }
+        i += 1;
+    };
+
+    // Skip whitespace between the callee and the parenthesis.
+    let mut i = skip_whitespace(&rev, open_paren + 1);
+
+    // Skip a turbofish, e.g. `foo::<T>(`.
+    if rev.get(i) == Some(&'>') {
+        let mut depth = 0usize;
+        loop {
+            match *rev.get(i)? {
+                '>' => depth += 1,
+                '<' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+        if !rev[i + 1..].starts_with(&[':', ':']) {
+            return None;
+        }
+        i = skip_whitespace(&rev, i + 3);
+    }
+
+    if i == rev.len() || !is_ident_char(rev[i]) {
+        // E.g., a macro call, a closure call or just a parenthesized expression.
+        return None;
+    }
+    let mut j = i;
+    while j < rev.len() && is_ident_char(rev[j]) {
+        j += 1;
+    }
+    if rev[j - 1].is_numeric() {
+        return None;
+    }
+    let is_method_call = rev.get(skip_whitespace(&rev, j)) == Some(&'.');
+
+    let (row, name_start) = lexed.position(cursor - j);
+    let name_end = name_start + (j - i);
+    Some(CallSite { row, name_start, name_end, active_arg: commas, is_method_call })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Finds the call site for the cursor marked with `|` in `text`.
+    fn call_site(text: &str) -> Option<CallSite> {
+        let (row, line) = text.lines().enumerate().find(|(_, line)| line.contains('|')).unwrap();
+        let col = line.chars().take_while(|c| *c != '|').count();
+        find_call_site(&text.replace('|', ""), row, col)
+    }
+
+    fn params(sig: &str) -> Vec<&str> {
+        parameter_ranges(sig).into_iter().map(|(start, end)| &sig[start..end]).collect()
+    }
+
+    #[test]
+    fn test_find_call_site() {
+        assert_eq!(
+            call_site("let x = foo(|"),
+            Some(CallSite {
+                row: 0,
+                name_start: 8,
+                name_end: 11,
+                active_arg: 0,
+                is_method_call: false
+            })
+        );
+        assert_eq!(
+            call_site("foo(a, bar(b, c), |").map(|c| (c.name_start, c.active_arg)),
+            Some((0, 2))
+        );
+        assert_eq!(
+            call_site("foo(a, bar(b, |").map(|c| (c.name_start, c.active_arg)),
+            Some((7, 1))
+        );
+        assert_eq!(call_site("foo(\"a, b\", |").map(|c| c.active_arg), Some(1));
+        assert_eq!(call_site("foo(a,\n    |").map(|c| (c.row, c.active_arg)), Some((0, 1)));
+        assert_eq!(call_site("x.bar(1, |").map(|c| c.is_method_call), Some(true));
+        assert_eq!(call_site("x\n    .bar(|").map(|c| (c.row, c.is_method_call)), Some((1, true)));
+        assert_eq!(call_site("foo::<u8>(|").map(|c| (c.name_start, c.name_end)), Some((0, 3)));
+        assert_eq!(call_site("vec![1, |"), None);
+        assert_eq!(call_site("println!(|"), None);
+        assert_eq!(call_site("foo(a); |"), None);
+        assert_eq!(call_site("let x = (|"), None);
+    }
+
+    #[test]
+    fn test_parameter_ranges() {
+        assert_eq!(params("fn foo()"), Vec::<&str>::new());
+        assert_eq!(params("fn foo(a: u8, b: &str) -> bool"), vec!["a: u8", "b: &str"]);
+        assert_eq!(
+            params("pub fn foo<F: Fn(u8) -> u8>(f: F, (a, b): (u8, u8))"),
+            vec!["f: F", "(a, b): (u8, u8)"]
+        );
+        assert_eq!(
+            params("fn foo(&self, x: HashMap<u8, Vec<u8>>, f: impl Fn() -> u8)"),
+            vec!["&self", "x: HashMap<u8, Vec<u8>>", "f: impl Fn() -> u8"]
+        );
+    }
+
+    #[test]
+    fn test_active_parameter_skips_self() {
+        let call = |is_method_call| CallSite {
+            row: 0,
+            name_start: 0,
+            name_end: 0,
+            active_arg: 0,
+            is_method_call,
+        };
+        assert_eq!(active_parameter("fn foo(&mut self, a: u8)", &call(true)), 1);
+        assert_eq!(active_parameter("fn foo(&mut self, a: u8)", &call(false)), 0);
+        assert_eq!(active_parameter("fn foo(a: u8)", &call(true)), 0);
+
+        assert!(is_self_param("self"));
+        assert!(is_self_param("&'a mut self"));
+        assert!(is_self_param("self: Box<Self>"));
+        assert!(!is_self_param("selfish: u8"));
+    }
+}
@@ -0,0 +1,140 @@
+//! Type hierarchy: the traits implemented by a type, and the supertraits,
+//! subtraits and implementors of a trait.
+
+use log::trace;
+use rls_analysis::{Def, DefKind, Id};
+use url::Url;
+
+use crate::actions::lexical::SourceFiles;
+use crate::actions::InitActionContext;
+use crate::lsp_data::*;
+use crate::server::ResponseError;
+use crate::Span;
+
+/// Returns the type hierarchy item for the type or trait at the given position.
+pub fn prepare(
+    ctx: &InitActionContext,
+    params: &TextDocumentPositionParams,
+) -> Result<Vec<TypeHierarchyItem>, ResponseError> {
+    let file_path = parse_file_path!(&params.text_document.uri, "type_hierarchy")?;
+    let span = ctx.convert_pos_to_span(file_path, params.position);
+
+    let def = match ctx.analysis.id(&span).and_then(|id| ctx.analysis.get_def(id)) {
+        Ok(ref def) if is_type(def.kind) || def.kind == DefKind::Trait => def.clone(),
+        _ => return Ok(vec![]),
+    };
+    let mut sources = SourceFiles::new(&ctx.vfs);
+    Ok(type_hierarchy_item(&mut sources, &def, &[]).into_iter().collect())
+}
+
+/// Returns the traits implemented by a type, or the supertraits of a trait.
+pub fn supertypes(
+    ctx: &InitActionContext,
+    params: &TypeHierarchyParams,
+) -> Result<Vec<TypeHierarchyItem>, ResponseError> {
+    let (id, def) = match item_def(ctx, &params.item) {
+        Some(def) => def,
+        None => return Ok(vec![]),
+    };
+    let analysis = &ctx.analysis;
+
+    let related = if def.kind == DefKind::Trait {
+        no_impls(analysis.super_traits(id).unwrap_or_else(|_| vec![]))
+    } else {
+        group_impls(analysis.implemented_traits(id).unwrap_or_else(|_| vec![]))
+    };
+    trace!("supertypes of {}: {:?}", def.name, related);
+    Ok(items(ctx, related))
+}
+
+/// Returns the implementors and subtraits of a trait.
+pub fn subtypes(
+    ctx: &InitActionContext,
+    params: &TypeHierarchyParams,
+) -> Result<Vec<TypeHierarchyItem>, ResponseError> {
+    let (id, def) = match item_def(ctx, &params.item) {
+        Some(def) => def,
+        None => return Ok(vec![]),
+    };
+    if def.kind != DefKind::Trait {
+        return Ok(vec![]);
+    }
+    let analysis = &ctx.analysis;
+
+    let mut related = group_impls(analysis.implementors(id).unwrap_or_else(|_| vec![]));
+    related.extend(no_impls(analysis.sub_traits(id).unwrap_or_else(|_| vec![])));
+    trace!("subtypes of {}: {:?}", def.name, related);
+    Ok(items(ctx, related))
+}
+
+fn is_type(kind: DefKind) -> bool {
+    match kind {
+        DefKind::Struct | DefKind::Enum | DefKind::Union | DefKind::Type => true,
+        _ => false,
+    }
+}
+
+/// Groups the impl spans of related items by the id of the item.
+fn group_impls(impls: Vec<(Id, Span)>) -> Vec<(Id, Vec<Span>)> {
+    let mut result: Vec<(Id, Vec<Span>)> = vec![];
+    for (id, span) in impls {
+        match result.iter_mut().find(|(i, _)| *i == id) {
+            Some((_, spans)) => spans.push(span),
+            None => result.push((id, vec![span])),
+        }
+    }
+    result
+}
+
+fn no_impls(ids: Vec<Id>) -> Vec<(Id, Vec<Span>)> {
+    ids.into_iter().map(|id| (id, vec![])).collect()
+}
+
+fn items(ctx: &InitActionContext, related: Vec<(Id, Vec<Span>)>) -> Vec<TypeHierarchyItem> {
+    let mut sources = SourceFiles::new(&ctx.vfs);
+    related
+        .into_iter()
+        .filter_map(|(id, impls)| {
+            let def = ctx.analysis.get_def(id).ok()?;
+            // The impl spans only cover the name of the trait or type, so
+            // expand them to the whole impl block.
+            let impls: Vec<_> = impls
+                .iter()
+                .map(|span| {
+                    let span = sources.item_span(span).unwrap_or_else(|| span.clone());
+                    ls_util::rls_to_location(&span)
+                })
+                .collect();
+            type_hierarchy_item(&mut sources, &def, &impls)
+        })
+        .collect()
+}
+
+/// Finds the definition of the type or trait a type hierarchy item refers to.
+fn item_def(ctx: &InitActionContext, item: &TypeHierarchyItem) -> Option<(Id, Def)> {
+    let location = Location { uri: item.uri.clone(), range: item.selection_range };
+    let span = ls_util::location_to_rls(&location).ok()?;
+    let id = ctx.analysis.id(&span).ok()?;
+    Some((id, ctx.analysis.get_def(id).ok()?))
+}
+
+fn type_hierarchy_item(
+    sources: &mut SourceFiles,
+    def: &Def,
+    impls: &[Location],
+) -> Option<TypeHierarchyItem> {
+    let selection_range = ls_util::rls_to_range(def.span.range);
+    let range = sources
+        .item_span(&def.span)
+        .map(|span| ls_util::rls_to_range(span.range))
+        .unwrap_or(selection_range);
+    Some(TypeHierarchyItem {
+        name: def.name.clone(),
+        kind: source_kind_from_def_kind(def.kind),
+        detail: if def.qualname.is_empty() { None } else { Some(def.qualname.clone()) },
+        uri: Url::from_file_path(&def.span.file).ok()?,
+        range,
+        selection_range,
+        data: impls.to_vec(),
+    })
+}
@@ -25,11 +25,13 @@ use rls_data::Analysis;
 use rls_vfs::Vfs;
 
 use crate::actions::progress::ProgressUpdate;
-use crate::build::cargo_plan::CargoPlan;
+use crate::build::cargo_plan::{CargoPlan, UnitKey};
 use crate::build::environment::{self, Environment, EnvironmentLock};
 use crate::build::plan::{BuildPlan, Crate};
-use crate::build::{BufWriter, BuildResult, CompilationContext, Internals, PackageArg};
-use crate::config::Config;
+use crate::build::{
+    BufWriter, BuildResult, CompilationContext, CompilerMessage, Internals, PackageArg,
+};
+use crate::config::{BuildProfiles, Config};
 use crate::lsp_data::{Position, Range};
 
 // Runs an in-process instance of Cargo.
@@ -98,7 +100,7 @@ fn run_cargo(
     rls_config: Arc<Mutex<Config>>,
     vfs: Arc<Vfs>,
     env_lock: Arc<EnvironmentLock>,
-    compiler_messages: Arc<Mutex<Vec<String>>>,
+    compiler_messages: Arc<Mutex<Vec<CompilerMessage>>>,
     analysis: Arc<Mutex<Vec<Analysis>>>,
     input_files: Arc<Mutex<HashMap<PathBuf, HashSet<Crate>>>>,
     out: Arc<Mutex<Vec<u8>>>,
@@ -157,7 +159,7 @@ fn run_cargo_ws(
     package_arg: PackageArg,
     rls_config: Arc<Mutex<Config>>,
     vfs: Arc<Vfs>,
-    compiler_messages: Arc<Mutex<Vec<String>>>,
+    compiler_messages: Arc<Mutex<Vec<CompilerMessage>>>,
     analysis: Arc<Mutex<Vec<Analysis>>>,
     input_files: Arc<Mutex<HashMap<PathBuf, HashSet<Crate>>>>,
     progress_sender: Sender<ProgressUpdate>,
@@ -174,7 +176,7 @@ fn run_cargo_ws(
 
     // TODO: it might be feasible to keep this `CargoOptions` structure cached and regenerate
     // it on every relevant configuration change.
-    let (opts, rustflags, clear_env_rust_log, cfg_test) = {
+    let (opts, rustflags, clear_env_rust_log, cfg_tests) = {
         // We mustn't lock configuration for the whole build process
         let rls_config = rls_config.lock().unwrap();
 
@@ -192,10 +194,16 @@ fn run_cargo_ws(
             }
         }
 
-        (opts, rustflags, rls_config.clear_env_rust_log, rls_config.cfg_test)
+        // Every configuration is checked by a separate Cargo compilation.
+        let cfg_tests = match rls_config.build_profiles {
+            BuildProfiles::Single => vec![rls_config.cfg_test],
+            BuildProfiles::Both => vec![false, true],
+        };
+
+        (opts, rustflags, rls_config.clear_env_rust_log, cfg_tests)
     };
 
-    let spec = Packages::from_flags(all, Vec::new(), packages)?;
+    let spec = Packages::from_flags(all, Vec::new(), packages.clone())?;
 
     let pkg_names = spec
         .to_package_id_specs(&ws)?
@@ -209,32 +217,34 @@ fn run_cargo_ws(
     compilation_cx.lock().unwrap().build_plan =
         BuildPlan::Cargo(CargoPlan::with_packages(manifest_path, pkg_names));
 
-    let compile_opts = CompileOptions {
-        spec,
-        filter: CompileFilter::from_raw_arguments(
-            opts.lib,
-            opts.bin,
-            opts.bins,
-            // TODO: support more crate target types.
-            Vec::new(),
-            // Check all integration tests under `tests/`.
-            cfg_test,
-            Vec::new(),
-            false,
-            Vec::new(),
-            false,
-            opts.all_targets,
-        ),
-        build_config: BuildConfig::new(
-            &config,
-            opts.jobs,
-            opts.target.iter().map(|x| x.into()).collect::<Vec<String>>().as_slice(),
-            CompileMode::Check { test: cfg_test },
-        )?,
-        features: opts.features,
-        all_features: opts.all_features,
-        no_default_features: opts.no_default_features,
-        ..CompileOptions::new(&config, CompileMode::Check { test: cfg_test })?
+    let compile_opts = |cfg_test: bool| -> CargoResult<CompileOptions> {
+        Ok(CompileOptions {
+            spec: Packages::from_flags(all, Vec::new(), packages.clone())?,
+            filter: CompileFilter::from_raw_arguments(
+                opts.lib,
+                opts.bin.clone(),
+                opts.bins,
+                // TODO: support more crate target types.
+                Vec::new(),
+                // Check all integration tests under `tests/`.
+                cfg_test,
+                Vec::new(),
+                false,
+                Vec::new(),
+                false,
+                opts.all_targets,
+            ),
+            build_config: BuildConfig::new(
+                &config,
+                opts.jobs,
+                opts.target.iter().map(|x| x.into()).collect::<Vec<String>>().as_slice(),
+                CompileMode::Check { test: cfg_test },
+            )?,
+            features: opts.features.clone(),
+            all_features: opts.all_features,
+            no_default_features: opts.no_default_features,
+            ..CompileOptions::new(&config, CompileMode::Check { test: cfg_test })?
+        })
     };
 
     // Create a custom environment for running cargo, the environment is reset
@@ -261,19 +271,21 @@ fn run_cargo_ws(
     );
 
     let exec = Arc::new(exec) as Arc<dyn Executor>;
-    match compile_with_exec(&ws, &compile_opts, &exec) {
-        Ok(_) => {
-            trace!(
-                "created build plan after Cargo compilation routine: {:?}",
-                compilation_cx.lock().unwrap().build_plan
-            );
-        }
-        Err(e) => {
-            if !reached_primary.load(Ordering::SeqCst) {
-                debug!("error running `compile_with_exec`: {:?}", e);
-                return Err(e);
-            } else {
-                warn!("ignoring error running `compile_with_exec`: {:?}", e);
+    for cfg_test in cfg_tests {
+        match compile_with_exec(&ws, &compile_opts(cfg_test)?, &exec) {
+            Ok(_) => {
+                trace!(
+                    "created build plan after Cargo compilation routine: {:?}",
+                    compilation_cx.lock().unwrap().build_plan
+                );
+            }
+            Err(e) => {
+                if !reached_primary.load(Ordering::SeqCst) {
+                    debug!("error running `compile_with_exec`: {:?}", e);
+                    return Err(e);
+                } else {
+                    warn!("ignoring error running `compile_with_exec`: {:?}", e);
+                }
             }
         }
     }
@@ -302,9 +314,12 @@ struct RlsExecutor {
     /// Packages which are directly a member of the workspace, for which
     /// analysis and diagnostics will be provided.
     member_packages: Mutex<HashSet<PackageId>>,
+    /// Primary units which were already checked by a previous Cargo
+    /// compilation of this build (e.g., of another configuration).
+    checked_units: Mutex<HashSet<UnitKey>>,
     input_files: Arc<Mutex<HashMap<PathBuf, HashSet<Crate>>>>,
     /// JSON compiler messages emitted for each primary compiled crate.
-    compiler_messages: Arc<Mutex<Vec<String>>>,
+    compiler_messages: Arc<Mutex<Vec<CompilerMessage>>>,
     progress_sender: Mutex<Sender<ProgressUpdate>>,
     /// Set to true if attempt to compile a primary crate. If we don't track
     /// this then errors which prevent giving type info won't be shown to the
@@ -321,7 +336,7 @@ impl RlsExecutor {
         config: Arc<Mutex<Config>>,
         env_lock: environment::InnerLock,
         vfs: Arc<Vfs>,
-        compiler_messages: Arc<Mutex<Vec<String>>>,
+        compiler_messages: Arc<Mutex<Vec<CompilerMessage>>>,
         analysis: Arc<Mutex<Vec<Analysis>>>,
         input_files: Arc<Mutex<HashMap<PathBuf, HashSet<Crate>>>>,
         progress_sender: Sender<ProgressUpdate>,
@@ -337,6 +352,7 @@ impl RlsExecutor {
             analysis,
             input_files,
             member_packages: Mutex::new(member_packages),
+            checked_units: Mutex::default(),
             compiler_messages,
             progress_sender: Mutex::new(progress_sender),
             reached_primary,
@@ -380,6 +396,7 @@ impl Executor for RlsExecutor {
         // well as the primary crate. But this is not too bad -- it means
         // we will rarely rebuild more than we have to.
         self.is_primary_package(id)
+            && !self.checked_units.lock().unwrap().contains(&UnitKey::from(unit))
     }
 
     fn exec(
@@ -521,6 +538,13 @@ impl Executor for RlsExecutor {
             return cmd.exec();
         }
 
+        // Units shared by the checked configurations (e.g., a library which
+        // integration tests depend on) only need to be checked once.
+        if !self.checked_units.lock().unwrap().insert(UnitKey::new(id, target, mode)) {
+            trace!("rustc not intercepted - {} was already checked", crate_name);
+            return Ok(());
+        }
+
         trace!("rustc intercepted - args: {:?} envs: {:?}", args, envs,);
 
         self.reached_primary.store(true, Ordering::SeqCst);
@@ -557,6 +581,8 @@ impl Executor for RlsExecutor {
                 &build_dir,
                 Arc::clone(&self.config),
                 &self.env_lock.as_facade(),
+                // Cargo builds can't be cancelled.
+                None,
             )
         {
             self.compiler_messages.lock().unwrap().append(&mut messages);
@@ -641,6 +667,18 @@ fn prepare_cargo_rustflags(config: &Config) -> String {
     dedup_flags(&flags)
 }
 
+/// Describes the options which a Cargo build plan depends on, so that a saved
+/// plan is only reused with the same configuration.
+pub(super) fn plan_options(config: &Config) -> String {
+    format!(
+        "{:?} {} {} {:?}",
+        CargoOptions::new(config),
+        prepare_cargo_rustflags(config),
+        config.cfg_test,
+        config.build_profiles
+    )
+}
+
 /// Constructs a cargo configuration for the given build and target directories
 /// and shell.
 pub fn make_cargo_config(
@@ -14,29 +14,98 @@
 //! with a same package and crate target kind (e.g. both building and running
 //! build scripts).
 
-use std::collections::{HashMap, HashSet};
-use std::fmt;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsString;
 use std::path::{Path, PathBuf};
+use std::process::Command;
 use std::sync::Mutex;
+use std::time::SystemTime;
+use std::{env, fmt, fs, io};
 
-use cargo::core::compiler::{CompileKind, CompileMode, Context, Unit};
-use cargo::core::profiles::Profile;
+use cargo::core::compiler::{CompileMode, Context, Unit};
 use cargo::core::{PackageId, Target, TargetKind};
-use cargo::util::ProcessBuilder;
+use cargo::util::{process, ProcessBuilder};
 use log::{error, trace};
+use serde_derive::{Deserialize, Serialize};
 
 use crate::build::plan::{BuildGraph, BuildKey, JobQueue, WorkStatus};
-use crate::build::rustc::src_path;
+use crate::build::rustc::{current_sysroot, src_path};
 use crate::build::PackageArg;
+use crate::config::BuildScope;
 
 /// Main key type by which `Unit`s will be distinguished in the build plan.
 /// In `Target` we're mostly interested in `TargetKind` (Lib, Bin, ...) and name
 /// (e.g., we can have 2 binary targets with different names).
-#[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+/// Only owned copies of these are kept, so that the plan can be saved to disk.
+#[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub(crate) struct UnitKey {
-    pkg_id: PackageId,
-    target: Target,
-    mode: CompileMode,
+    /// The package id, as displayed by Cargo.
+    pkg_id: String,
+    /// The target name.
+    target: String,
+    kind: UnitKind,
+    mode: UnitMode,
+}
+
+impl UnitKey {
+    pub(crate) fn new(id: PackageId, target: &Target, mode: CompileMode) -> UnitKey {
+        UnitKey {
+            pkg_id: id.to_string(),
+            target: target.name().to_owned(),
+            kind: UnitKind::from(target.kind()),
+            mode: UnitMode::from(mode),
+        }
+    }
+}
+
+/// The crate target kind of a unit.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub(crate) enum UnitKind {
+    Lib,
+    Bin,
+    Test,
+    Bench,
+    Example,
+    CustomBuild,
+}
+
+impl From<&TargetKind> for UnitKind {
+    fn from(kind: &TargetKind) -> UnitKind {
+        match kind {
+            TargetKind::Lib(..) => UnitKind::Lib,
+            TargetKind::Bin => UnitKind::Bin,
+            TargetKind::Test => UnitKind::Test,
+            TargetKind::Bench => UnitKind::Bench,
+            TargetKind::ExampleLib(..) | TargetKind::ExampleBin => UnitKind::Example,
+            TargetKind::CustomBuild => UnitKind::CustomBuild,
+        }
+    }
+}
+
+/// What a unit is compiled (or run) for, mirroring Cargo's `CompileMode`.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub(crate) enum UnitMode {
+    Build,
+    Check,
+    CheckTest,
+    Test,
+    Bench,
+    Doc,
+    RunCustomBuild,
+}
+
+impl From<CompileMode> for UnitMode {
+    fn from(mode: CompileMode) -> UnitMode {
+        match mode {
+            CompileMode::Build => UnitMode::Build,
+            CompileMode::Check { test: false } => UnitMode::Check,
+            CompileMode::Check { test: true } => UnitMode::CheckTest,
+            CompileMode::Test => UnitMode::Test,
+            CompileMode::Bench => UnitMode::Bench,
+            CompileMode::Doc { .. } | CompileMode::Doctest => UnitMode::Doc,
+            CompileMode::RunCustomBuild => UnitMode::RunCustomBuild,
+        }
+    }
 }
 
 /// Holds the information how exactly the build will be performed for a given
@@ -60,6 +129,9 @@ pub(crate) struct CargoPlan {
     /// Packages (names) for which this build plan was prepared.
     /// Used to detect if the plan can reused when building certain packages.
     built_packages: HashSet<String>,
+    /// Set when the plan was loaded from disk, until every unit is rebuilt
+    /// once to collect its analysis.
+    pub(crate) restored: bool,
 }
 
 impl CargoPlan {
@@ -77,6 +149,127 @@ impl CargoPlan {
         !self.compiler_jobs.is_empty()
     }
 
+    /// Saves the plan into `target_dir`, so that a restarted RLS can load it
+    /// with `CargoPlan::load` instead of running Cargo again.
+    pub(crate) fn save(
+        &self,
+        target_dir: &Path,
+        manifest_path: &Path,
+        options: &str,
+        cwd: Option<&Path>,
+    ) -> io::Result<()> {
+        let package_map = match self.package_map {
+            Some(ref package_map) => package_map,
+            None => return Ok(()),
+        };
+
+        let saved = SavedPlan {
+            rls_version: env!("CARGO_PKG_VERSION").to_owned(),
+            manifest_path: manifest_path.to_owned(),
+            options: options.to_owned(),
+            toolchain: SavedPlan::toolchain(),
+            fingerprint: self.fingerprint(manifest_path, package_map),
+            cwd: cwd.map(Path::to_path_buf),
+            built_packages: self.built_packages.clone(),
+            units: self.units.values().cloned().collect(),
+            dep_graph: self.dep_graph.iter().map(|(k, deps)| (k.clone(), deps.clone())).collect(),
+            compiler_jobs: self
+                .compiler_jobs
+                .iter()
+                .map(|(k, cmd)| (k.clone(), cmd.into()))
+                .collect(),
+            input_files: self.input_files.iter().map(|(k, f)| (k.clone(), f.clone())).collect(),
+        };
+
+        fs::create_dir_all(target_dir)?;
+        fs::write(target_dir.join(SAVED_PLAN), serde_json::to_vec(&saved)?)
+    }
+
+    /// The modification times of the files whose changes invalidate a saved
+    /// plan: the workspace manifests and `Cargo.lock`, and the build scripts
+    /// along with what their runs output, since a restored plan doesn't
+    /// rebuild them.
+    fn fingerprint(
+        &self,
+        manifest_path: &Path,
+        package_map: &PackageMap,
+    ) -> Vec<(PathBuf, Option<SystemTime>)> {
+        let mut files = vec![manifest_path.to_owned(), manifest_path.with_file_name("Cargo.lock")];
+        files.extend(package_map.package_paths.keys().map(|dir| dir.join("Cargo.toml")));
+        for (key, unit) in &self.units {
+            if key.kind == UnitKind::CustomBuild {
+                files.extend(unit.src_path.iter().cloned());
+                files.extend(self.input_files.get(key).into_iter().flatten().cloned());
+            }
+        }
+        // Cargo records the output of a build script run next to its `OUT_DIR`.
+        for cmd in self.compiler_jobs.values() {
+            if let Some(Some(out_dir)) = cmd.get_envs().get("OUT_DIR") {
+                let out_dir = PathBuf::from(out_dir);
+                files.push(out_dir.with_file_name("output"));
+                files.push(out_dir);
+            }
+        }
+        files.sort();
+        files.dedup();
+
+        files.into_iter().map(|file| (file.clone(), modified(&file))).collect()
+    }
+
+    /// Loads a plan saved by `CargoPlan::save`, along with the working
+    /// directory of its compiler invocations. Returns `None` if there's no
+    /// saved plan, or if it's out of date because the workspace manifests,
+    /// `Cargo.lock`, the build scripts, the toolchain or the build options
+    /// have changed since.
+    pub(crate) fn load(
+        target_dir: &Path,
+        manifest_path: &Path,
+        options: &str,
+    ) -> Option<(CargoPlan, Option<PathBuf>)> {
+        let contents = fs::read(target_dir.join(SAVED_PLAN)).ok()?;
+        let saved: SavedPlan = match serde_json::from_slice(&contents) {
+            Ok(saved) => saved,
+            Err(e) => {
+                error!("Couldn't read the saved build plan: {}", e);
+                return None;
+            }
+        };
+
+        if saved.rls_version != env!("CARGO_PKG_VERSION")
+            || saved.manifest_path != manifest_path
+            || saved.options != options
+            || saved.toolchain != SavedPlan::toolchain()
+            || !saved.is_fresh()
+        {
+            trace!("The saved build plan is out of date");
+            return None;
+        }
+
+        let mut plan = CargoPlan::with_packages(manifest_path, saved.built_packages);
+        plan.units = saved.units.into_iter().map(|unit| (unit.key.clone(), unit)).collect();
+        for (key, deps) in saved.dep_graph {
+            plan.rev_dep_graph.entry(key.clone()).or_insert_with(HashSet::new);
+            for dep in &deps {
+                plan.rev_dep_graph
+                    .entry(dep.clone())
+                    .or_insert_with(HashSet::new)
+                    .insert(key.clone());
+            }
+            plan.dep_graph.insert(key, deps);
+        }
+        plan.compiler_jobs =
+            saved.compiler_jobs.into_iter().map(|(key, job)| (key, job.into_process())).collect();
+        for (key, files) in saved.input_files {
+            for file in &files {
+                plan.file_key_mapping.entry(file.clone()).or_default().insert(key.clone());
+            }
+            plan.input_files.insert(key, files);
+        }
+        plan.restored = true;
+
+        Some((plan, saved.cwd))
+    }
+
     /// Caches a given compiler invocation in `ProcessBuilder` for a given
     /// `PackageId` and `TargetKind` in `Target`, to be used when processing
     /// cached build plan.
@@ -87,7 +280,7 @@ impl CargoPlan {
         mode: CompileMode,
         cmd: &ProcessBuilder,
     ) {
-        let unit_key = UnitKey { pkg_id: id, target: target.clone(), mode };
+        let unit_key = UnitKey::new(id, target, mode);
         self.compiler_jobs.insert(unit_key, cmd.clone());
     }
 
@@ -111,7 +304,7 @@ impl CargoPlan {
             })
             .collect();
 
-        let unit_key = UnitKey { pkg_id: id, target: target.clone(), mode };
+        let unit_key = UnitKey::new(id, target, mode);
         trace!("Caching these files: {:#?} for {:?} key", &input_files, &unit_key);
 
         // Create reverse file -> unit mapping (to be used for dirty unit calculation).
@@ -193,21 +386,18 @@ impl CargoPlan {
         let build_scripts: HashMap<&Path, UnitKey> = self
             .units
             .iter()
-            .filter(|(UnitKey { target, .. }, _)| {
-                target.is_custom_build() && target.src_path().is_path()
-            })
-            .map(|(key, unit)| (unit.target.src_path().path().unwrap(), key.clone()))
+            .filter(|(key, _)| key.kind == UnitKind::CustomBuild)
+            .filter_map(|(key, unit)| Some((unit.src_path.as_deref()?, key.clone())))
             .collect();
         let other_targets: HashMap<UnitKey, &Path> = self
             .units
             .iter()
-            .filter(|(UnitKey { target, .. }, _)| !target.is_custom_build())
+            .filter(|(key, _)| key.kind != UnitKind::CustomBuild)
             .map(|(key, unit)| {
                 (
                     key.clone(),
-                    unit.target
-                        .src_path()
-                        .path()
+                    unit.src_path
+                        .as_deref()
                         .expect("normal targets should have a path")
                         .parent()
                         .expect("no parent for src_path"),
@@ -336,12 +526,70 @@ impl CargoPlan {
         }
     }
 
-    pub(crate) fn prepare_work<T: AsRef<Path> + fmt::Debug>(&self, modified: &[T]) -> WorkStatus {
+    /// Returns `true` if the packages of the workspace are known, i.e., the
+    /// plan was prepared by Cargo or given a package map.
+    pub(crate) fn knows_packages(&self) -> bool {
+        self.package_map.is_some()
+    }
+
+    /// Sets the packages of the workspace, discovered before Cargo prepares
+    /// the plan.
+    pub(crate) fn set_package_map(&mut self, package_map: PackageMap) {
+        self.package_map = Some(package_map);
+    }
+
+    /// Returns the packages to build when only the packages of `files` (and
+    /// the packages depending on them) are built, in addition to the ones
+    /// this plan was already prepared for. Returns `None` if the packages of
+    /// the workspace aren't known yet.
+    pub(crate) fn scoped_packages<T: AsRef<Path> + fmt::Debug>(
+        &self,
+        files: &[T],
+    ) -> Option<HashSet<String>> {
+        let package_map = self.package_map.as_ref()?;
+        let mut packages = package_map.with_rev_deps(package_map.compute_dirty_packages(files));
+        packages.extend(self.built_packages.iter().cloned());
+        Some(packages)
+    }
+
+    /// Returns `true` if `package` is a member of the workspace, or `None` if
+    /// the packages of the workspace aren't known yet.
+    pub(crate) fn has_package(&self, package: &str) -> Option<bool> {
+        let package_map = self.package_map.as_ref()?;
+        Some(package_map.package_paths.values().any(|p| p == package))
+    }
+
+    /// Returns the workspace package of `file`, if this plan wasn't prepared
+    /// for it.
+    pub(crate) fn unbuilt_package(&self, file: &Path) -> Option<String> {
+        let package = self.package_map.as_ref()?.map(file)?;
+        if self.built_packages.contains(&package) {
+            None
+        } else {
+            Some(package)
+        }
+    }
+
+    /// Prepares the work needed to rebuild the `modified` files, and to
+    /// include the explicitly `requested` packages in the build.
+    pub(crate) fn prepare_work<T: AsRef<Path> + fmt::Debug>(
+        &self,
+        modified: &[T],
+        requested: &HashSet<String>,
+        scope: BuildScope,
+    ) -> WorkStatus {
         if !self.is_ready() || self.package_map.is_none() {
             return WorkStatus::NeedsCargo(PackageArg::Default);
         }
 
-        let dirty_packages = self.package_map.as_ref().unwrap().compute_dirty_packages(modified);
+        let package_map = self.package_map.as_ref().unwrap();
+        let mut dirty_packages = package_map.compute_dirty_packages(modified);
+        dirty_packages.extend(requested.iter().cloned());
+        // When only part of the workspace is built, the packages depending on
+        // the dirty ones have to be rebuilt with them.
+        if scope == BuildScope::OpenFiles {
+            dirty_packages = package_map.with_rev_deps(dirty_packages);
+        }
 
         let needs_more_packages = dirty_packages.difference(&self.built_packages).next().is_some();
 
@@ -353,10 +601,20 @@ impl CargoPlan {
             return WorkStatus::NeedsCargo(PackageArg::Packages(needed_packages));
         }
 
-        let dirties = self.fetch_dirty_units(modified);
+        let dirties = if self.restored {
+            // Nothing was analyzed since the plan was loaded, so rebuild every
+            // unit that has a cached compiler invocation.
+            self.compiler_jobs
+                .keys()
+                .filter(|key| key.kind != UnitKind::CustomBuild)
+                .cloned()
+                .collect()
+        } else {
+            self.fetch_dirty_units(modified)
+        };
         trace!("fetch_dirty_units: for files {:?}, these units are dirty: {:?}", modified, dirties,);
 
-        if dirties.iter().any(|UnitKey { target, .. }| *target.kind() == TargetKind::CustomBuild) {
+        if dirties.iter().any(|key| key.kind == UnitKind::CustomBuild) {
             WorkStatus::NeedsCargo(PackageArg::Packages(needed_packages))
         } else {
             let graph = self.dirty_rev_dep_graph(&dirties);
@@ -380,13 +638,99 @@ impl CargoPlan {
                 None => WorkStatus::NeedsCargo(PackageArg::Default),
                 Some(jobs) => {
                     assert!(!jobs.is_empty());
-                    WorkStatus::Execute(JobQueue::with_commands(jobs))
+                    let rev_deps = |unit: &UnitKey| graph.get(unit).into_iter().flatten();
+                    WorkStatus::Execute(JobQueue::with_dependencies(&queue, jobs, rev_deps))
                 }
             }
         }
     }
 }
 
+/// The file name of a saved build plan, in the RLS target directory.
+const SAVED_PLAN: &str = "rls-build-plan.json";
+
+/// A `CargoPlan` as saved to disk, along with what it has to be validated
+/// against when it's loaded. Maps are stored as lists of pairs, since their
+/// keys aren't strings.
+#[derive(Serialize, Deserialize)]
+struct SavedPlan {
+    rls_version: String,
+    manifest_path: PathBuf,
+    /// The Cargo options the plan was prepared with.
+    options: String,
+    /// The version of rustc and the sysroot the plan was prepared with.
+    toolchain: String,
+    /// The files the plan depends on, with their modification times.
+    fingerprint: Vec<(PathBuf, Option<SystemTime>)>,
+    cwd: Option<PathBuf>,
+    built_packages: HashSet<String>,
+    units: Vec<OwnedUnit>,
+    dep_graph: Vec<(UnitKey, HashSet<UnitKey>)>,
+    compiler_jobs: Vec<(UnitKey, SavedJob)>,
+    input_files: Vec<(UnitKey, Vec<PathBuf>)>,
+}
+
+impl SavedPlan {
+    /// Identifies the toolchain by the output of `rustc -vV` and the sysroot.
+    fn toolchain() -> String {
+        let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_owned());
+        let version = Command::new(rustc)
+            .arg("-vV")
+            .output()
+            .ok()
+            .and_then(|out| String::from_utf8(out.stdout).ok())
+            .unwrap_or_default();
+        format!("{}sysroot: {}", version, current_sysroot().unwrap_or_default())
+    }
+
+    /// Returns `true` if none of the fingerprinted files were modified (or
+    /// created or removed) since the plan was saved.
+    fn is_fresh(&self) -> bool {
+        self.fingerprint.iter().all(|(file, mtime)| modified(file) == *mtime)
+    }
+}
+
+fn modified(file: &Path) -> Option<SystemTime> {
+    fs::metadata(file).and_then(|meta| meta.modified()).ok()
+}
+
+/// A saved compiler invocation.
+#[derive(Serialize, Deserialize)]
+struct SavedJob {
+    program: OsString,
+    args: Vec<OsString>,
+    env: BTreeMap<String, Option<OsString>>,
+    cwd: Option<PathBuf>,
+}
+
+impl SavedJob {
+    fn into_process(self) -> ProcessBuilder {
+        let mut cmd = process(&self.program);
+        cmd.args(&self.args);
+        for (key, value) in &self.env {
+            match value {
+                Some(value) => cmd.env(key, value),
+                None => cmd.env_remove(key),
+            };
+        }
+        if let Some(cwd) = &self.cwd {
+            cmd.cwd(cwd);
+        }
+        cmd
+    }
+}
+
+impl From<&ProcessBuilder> for SavedJob {
+    fn from(cmd: &ProcessBuilder) -> SavedJob {
+        SavedJob {
+            program: cmd.get_program().clone(),
+            args: cmd.get_args().to_vec(),
+            env: cmd.get_envs().clone(),
+            cwd: cmd.get_cwd().map(Path::to_path_buf),
+        }
+    }
+}
+
 /// Maps paths to packages.
 ///
 /// The point of the PackageMap is detect if additional packages need to be
@@ -395,44 +739,76 @@ impl CargoPlan {
 /// that's outside the cached build plan - if so, we need to recreate it,
 /// including the new package.
 #[derive(Debug)]
-struct PackageMap {
+pub(crate) struct PackageMap {
     // A map from a manifest directory to the package name.
     package_paths: HashMap<PathBuf, String>,
+    // A map from a package name to the workspace packages depending on it.
+    rev_deps: HashMap<String, HashSet<String>>,
     // A map from a file's path, to the package it belongs to.
     map_cache: Mutex<HashMap<PathBuf, String>>,
 }
 
 impl PackageMap {
-    fn new(manifest_path: &Path) -> PackageMap {
-        PackageMap {
-            package_paths: Self::discover_package_paths(manifest_path),
-            map_cache: Mutex::new(HashMap::new()),
-        }
+    pub(crate) fn new(manifest_path: &Path) -> PackageMap {
+        let (package_paths, rev_deps) = Self::discover_packages(manifest_path);
+        PackageMap { package_paths, rev_deps, map_cache: Mutex::new(HashMap::new()) }
     }
 
-    // Finds each package in the workspace and record the root directory and package name.
-    fn discover_package_paths(manifest_path: &Path) -> HashMap<PathBuf, String> {
+    // Finds each package in the workspace and record the root directory and
+    // package name, along with the workspace packages depending on it.
+    fn discover_packages(
+        manifest_path: &Path,
+    ) -> (HashMap<PathBuf, String>, HashMap<String, HashSet<String>>) {
         trace!("read metadata {:?}", manifest_path);
-        cargo_metadata::MetadataCommand::new()
-            .manifest_path(manifest_path)
-            .exec()
+        let meta = match cargo_metadata::MetadataCommand::new().manifest_path(manifest_path).exec()
+        {
+            Ok(meta) => meta,
+            Err(_) => return Default::default(),
+        };
+        let members: Vec<_> = meta.workspace_members.iter().map(|id| &meta[id]).collect();
+
+        let package_paths = members
             .iter()
-            .flat_map(|meta| meta.workspace_members.iter().map(move |id| &meta[id]))
             .filter_map(|pkg| {
                 let dir = pkg.manifest_path.parent()?.to_path_buf();
                 Some((dir, pkg.name.clone()))
             })
-            .collect()
+            .collect();
+
+        let mut rev_deps = HashMap::<_, HashSet<_>>::new();
+        for pkg in &members {
+            for dep in &pkg.dependencies {
+                if members.iter().any(|member| member.name == dep.name) {
+                    rev_deps.entry(dep.name.clone()).or_default().insert(pkg.name.clone());
+                }
+            }
+        }
+
+        (package_paths, rev_deps)
     }
 
     /// Given modified set of files, returns a set of corresponding dirty packages.
-    fn compute_dirty_packages<T: AsRef<Path> + fmt::Debug>(
+    pub(crate) fn compute_dirty_packages<T: AsRef<Path> + fmt::Debug>(
         &self,
         modified_files: &[T],
     ) -> HashSet<String> {
         modified_files.iter().filter_map(|p| self.map(p.as_ref())).collect()
     }
 
+    /// Extends a set of packages with all the workspace packages which
+    /// (transitively) depend on them.
+    pub(crate) fn with_rev_deps(&self, mut packages: HashSet<String>) -> HashSet<String> {
+        let mut to_process: Vec<_> = packages.iter().cloned().collect();
+        while let Some(package) = to_process.pop() {
+            for rev_dep in self.rev_deps.get(&package).into_iter().flatten() {
+                if packages.insert(rev_dep.clone()) {
+                    to_process.push(rev_dep.clone());
+                }
+            }
+        }
+        packages
+    }
+
     // Maps a file to the package which it belongs to.
     // We do this by walking up the directory tree from `path` until we get to
     // one of the recorded package root directories.
@@ -466,35 +842,30 @@ impl PackageMap {
 
 impl From<&Unit> for UnitKey {
     fn from(unit: &Unit) -> UnitKey {
-        UnitKey { pkg_id: unit.pkg.package_id(), target: unit.target.clone(), mode: unit.mode }
+        UnitKey::new(unit.pkg.package_id(), &unit.target, unit.mode)
     }
 }
 
-#[derive(Hash, PartialEq, Eq, Debug, Clone)]
-/// An owned version of `cargo::core::Unit`.
+#[derive(Hash, PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+/// An owned version of `cargo::core::Unit`, keeping what the build plan needs.
 pub(crate) struct OwnedUnit {
-    pub(crate) id: PackageId,
-    pub(crate) target: Target,
-    pub(crate) profile: Profile,
-    pub(crate) kind: CompileKind,
-    pub(crate) mode: CompileMode,
+    pub(crate) key: UnitKey,
+    /// The root source file of the unit's target, if it has one.
+    pub(crate) src_path: Option<PathBuf>,
 }
 
 impl From<&Unit> for OwnedUnit {
     fn from(unit: &Unit) -> OwnedUnit {
         OwnedUnit {
-            id: unit.pkg.package_id().to_owned(),
-            target: unit.target.clone(),
-            profile: unit.profile,
-            kind: unit.kind,
-            mode: unit.mode,
+            key: UnitKey::from(unit),
+            src_path: unit.target.src_path().path().map(Path::to_path_buf),
         }
     }
 }
 
 impl From<&OwnedUnit> for UnitKey {
     fn from(unit: &OwnedUnit) -> UnitKey {
-        UnitKey { pkg_id: unit.id, target: unit.target.clone(), mode: unit.mode }
+        unit.key.clone()
     }
 }
 
@@ -574,6 +945,129 @@ impl BuildGraph for CargoPlan {
     }
 
     fn prepare_work<T: AsRef<Path> + std::fmt::Debug>(&self, files: &[T]) -> WorkStatus {
-        CargoPlan::prepare_work(self, files)
+        CargoPlan::prepare_work(self, files, &HashSet::new(), BuildScope::Workspace)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dirty_packages_and_rev_deps() {
+        let set = |names: &[&str]| names.iter().map(|&n| n.to_owned()).collect::<HashSet<_>>();
+        // `app` depends on `core` through `util`, `tool` only on `core`.
+        let map = PackageMap {
+            package_paths: vec![
+                (PathBuf::from("/ws/core"), "core".to_owned()),
+                (PathBuf::from("/ws/util"), "util".to_owned()),
+                (PathBuf::from("/ws/app"), "app".to_owned()),
+                (PathBuf::from("/ws/tool"), "tool".to_owned()),
+            ]
+            .into_iter()
+            .collect(),
+            rev_deps: vec![
+                ("core".to_owned(), set(&["util", "tool"])),
+                ("util".to_owned(), set(&["app"])),
+            ]
+            .into_iter()
+            .collect(),
+            map_cache: Mutex::default(),
+        };
+
+        let dirty = |files: &[&str]| map.compute_dirty_packages(files);
+        assert_eq!(dirty(&["/ws/util/src/lib.rs"]), set(&["util"]));
+        assert_eq!(dirty(&["/ws/core/src/lib.rs", "/elsewhere/lib.rs"]), set(&["core"]));
+
+        assert_eq!(map.with_rev_deps(dirty(&["/ws/app/src/main.rs"])), set(&["app"]));
+        assert_eq!(map.with_rev_deps(dirty(&["/ws/util/src/lib.rs"])), set(&["util", "app"]));
+        assert_eq!(map.with_rev_deps(set(&["core"])), set(&["core", "util", "tool", "app"]));
+        assert_eq!(map.with_rev_deps(set(&["tool"])), set(&["tool"]));
+    }
+
+    #[test]
+    fn saved_plan_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest_path = dir.path().join("Cargo.toml");
+        fs::write(&manifest_path, "[package]").unwrap();
+
+        let key = UnitKey {
+            pkg_id: "foo 0.1.0".to_owned(),
+            target: "foo".to_owned(),
+            kind: UnitKind::Lib,
+            mode: UnitMode::Check,
+        };
+        let mut cmd = process("rustc");
+        cmd.arg("--crate-name").arg("foo").env("CARGO_PKG_NAME", "foo");
+        let mut plan = CargoPlan {
+            package_map: Some(PackageMap {
+                package_paths: HashMap::new(),
+                rev_deps: HashMap::new(),
+                map_cache: Mutex::default(),
+            }),
+            ..Default::default()
+        };
+        let src_path = Some(dir.path().join("src/lib.rs"));
+        plan.units.insert(key.clone(), OwnedUnit { key: key.clone(), src_path });
+        plan.dep_graph.insert(key.clone(), HashSet::new());
+        plan.compiler_jobs.insert(key.clone(), cmd);
+        plan.save(dir.path(), &manifest_path, "options", Some(dir.path())).unwrap();
+
+        assert!(CargoPlan::load(dir.path(), &manifest_path, "other options").is_none());
+        let (loaded, cwd) = CargoPlan::load(dir.path(), &manifest_path, "options").unwrap();
+        assert!(loaded.restored);
+        assert_eq!(cwd.as_deref(), Some(dir.path()));
+        assert_eq!(loaded.units, plan.units);
+        assert!(loaded.rev_dep_graph.contains_key(&key));
+        assert_eq!(loaded.compiler_jobs[&key].get_args(), plan.compiler_jobs[&key].get_args());
+        assert_eq!(loaded.compiler_jobs[&key].get_envs(), plan.compiler_jobs[&key].get_envs());
+
+        // Generating a lock file invalidates the saved plan.
+        fs::write(dir.path().join("Cargo.lock"), "").unwrap();
+        assert!(CargoPlan::load(dir.path(), &manifest_path, "options").is_none());
+    }
+
+    #[test]
+    fn saved_plan_tracks_build_scripts() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest_path = dir.path().join("Cargo.toml");
+        fs::write(&manifest_path, "[package]").unwrap();
+        let (build_script, out_dir) = (dir.path().join("build.rs"), dir.path().join("out"));
+        fs::write(&build_script, "fn main() {}").unwrap();
+        fs::create_dir(&out_dir).unwrap();
+
+        let key = |target: &str, kind| UnitKey {
+            pkg_id: "foo 0.1.0".to_owned(),
+            target: target.to_owned(),
+            kind,
+            mode: UnitMode::Check,
+        };
+        let (build_key, lib_key) =
+            (key("build-script-build", UnitKind::CustomBuild), key("foo", UnitKind::Lib));
+        let mut cmd = process("rustc");
+        cmd.arg("--crate-name").arg("foo").env("OUT_DIR", &out_dir);
+        let mut plan = CargoPlan {
+            package_map: Some(PackageMap {
+                package_paths: HashMap::new(),
+                rev_deps: HashMap::new(),
+                map_cache: Mutex::default(),
+            }),
+            ..Default::default()
+        };
+        let src_path = Some(build_script.clone());
+        plan.units.insert(build_key.clone(), OwnedUnit { key: build_key, src_path });
+        plan.compiler_jobs.insert(lib_key, cmd);
+        let save = |plan: &CargoPlan| plan.save(dir.path(), &manifest_path, "options", None);
+        let is_loaded = || CargoPlan::load(dir.path(), &manifest_path, "options").is_some();
+
+        save(&plan).unwrap();
+        assert!(is_loaded());
+        // Cleaning the build script's output invalidates the saved plan...
+        fs::remove_dir(&out_dir).unwrap();
+        assert!(!is_loaded());
+        // ...as does removing the build script.
+        save(&plan).unwrap();
+        fs::remove_file(&build_script).unwrap();
+        assert!(!is_loaded());
     }
 }
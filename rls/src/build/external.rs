@@ -413,9 +413,11 @@ impl BuildGraph for ExternalPlan {
         let dirties = self.dirties_transitive(files);
         let topo = self.topological_sort(dirties);
 
+        let keys: Vec<_> = topo.iter().map(|unit| unit.key()).collect();
         let cmds = topo.into_iter().map(|unit| unit.command.clone()).collect();
+        let rev_deps = |key: &u64| self.rev_deps.get(key).into_iter().flatten();
 
-        WorkStatus::Execute(JobQueue::with_commands(cmds))
+        WorkStatus::Execute(JobQueue::with_dependencies(&keys, cmds, rev_deps))
     }
 }
 
@@ -1,6 +1,8 @@
 //! Running builds as-needed for the server to answer questions.
 
 use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fmt;
 use std::io::{self, Write};
 use std::mem;
 use std::path::{Path, PathBuf};
@@ -14,12 +16,15 @@ use log::{debug, info, trace};
 use rls_data::Analysis;
 use rls_vfs::Vfs;
 
+use ::cargo::util::important_paths;
+
+use self::cargo_plan::{CargoPlan, PackageMap};
 use self::environment::EnvironmentLock;
 use self::plan::{BuildGraph, BuildPlan, WorkStatus};
 pub use self::plan::{Crate, Edition};
 use crate::actions::post_build::PostBuildHandler;
 use crate::actions::progress::{ProgressNotifier, ProgressUpdate};
-use crate::config::Config;
+use crate::config::{BuildScope, Config};
 use crate::lsp_data::Range;
 
 mod cargo;
@@ -35,12 +40,11 @@ mod rustc;
 ///
 /// The IDE will request builds quickly (possibly on every keystroke), there is
 /// no point running every one. We also avoid running more than one build at once.
-/// We cannot cancel builds. It might be worth running builds in parallel or
-/// canceling a started build.
 ///
-/// High priority builds are started 'straightaway' (builds cannot be interrupted).
-/// Normal builds are started after a timeout. A new build request cancels any
-/// pending build requests.
+/// High priority builds are started 'straightaway'. Normal builds are started
+/// after a timeout. A new build request cancels any pending build requests,
+/// and interrupts a build which is running rustc directly (see `JobQueue`)
+/// before its next compilation unit. Cargo builds cannot be interrupted.
 ///
 /// From the client's point of view, a build request is not guaranteed to cause
 /// a build. However, a build is guaranteed to happen and that build will begin
@@ -78,10 +82,19 @@ struct Internals {
     env_lock: Arc<EnvironmentLock>,
     /// Set of files that were modified since last build.
     dirty_files: Arc<Mutex<HashMap<PathBuf, FileVersion>>>,
+    /// Files opened by the client, whose packages are built when building
+    /// only part of the workspace (see `BuildScope`).
+    open_files: Mutex<HashSet<PathBuf>>,
+    /// Packages explicitly requested to be built, in addition to the ones
+    /// picked by the `BuildScope`.
+    requested_packages: Mutex<HashSet<String>>,
     vfs: Arc<Vfs>,
     // This lock should only be held transiently.
     config: Arc<Mutex<Config>>,
     building: AtomicBool,
+    /// Set when a newer build is requested while one is in progress, so that
+    /// the running build stops early and reports `BuildResult::Squashed`.
+    cancelled: AtomicBool,
     /// A list of threads blocked on the current build queue. They should be
     /// resumed when there are no builds to run.
     blocked: Mutex<Vec<thread::Thread>>,
@@ -95,7 +108,7 @@ pub enum BuildResult {
     /// contains current directory at the time, emitted raw diagnostics,
     /// Analysis data and list of input files to the compilation.
     /// Final bool is true if and only if compiler's exit code would be 0.
-    Success(PathBuf, Vec<String>, Vec<Analysis>, HashMap<PathBuf, HashSet<Crate>>, bool),
+    Success(PathBuf, Vec<CompilerMessage>, Vec<Analysis>, HashMap<PathBuf, HashSet<Crate>>, bool),
     /// Build was coalesced with another build.
     Squashed,
     /// There was an error attempting to build.
@@ -111,6 +124,43 @@ pub enum BuildResult {
     },
 }
 
+/// A raw JSON diagnostic emitted by the compiler, along with the
+/// configuration of the crate which emitted it.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct CompilerMessage {
+    pub message: String,
+    pub profile: BuildProfile,
+}
+
+/// The configuration a crate was checked in.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum BuildProfile {
+    /// Checked without `cfg(test)`.
+    Normal,
+    /// Checked with `cfg(test)`, i.e., as a test harness.
+    Test,
+}
+
+impl BuildProfile {
+    /// Returns the configuration of a compiler invocation with given `args`.
+    pub(crate) fn of_args<S: AsRef<OsStr>>(args: &[S]) -> BuildProfile {
+        if args.iter().any(|arg| arg.as_ref() == "--test") {
+            BuildProfile::Test
+        } else {
+            BuildProfile::Normal
+        }
+    }
+}
+
+impl fmt::Display for BuildProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildProfile::Normal => write!(f, "not cfg(test)"),
+            BuildProfile::Test => write!(f, "cfg(test)"),
+        }
+    }
+}
+
 /// Priority for a build request.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum BuildPriority {
@@ -196,6 +246,13 @@ impl Build {
 
     // Returns `true` if the build is waiting and where it should be impossible for one to
     // be in progress.
+    fn is_in_progress(&self) -> bool {
+        match *self {
+            Build::InProgress => true,
+            _ => false,
+        }
+    }
+
     fn is_pending_fresh(&self) -> bool {
         match *self {
             Build::Pending(_) => true,
@@ -272,6 +329,12 @@ impl BuildQueue {
         };
 
         let mut queued = self.queued.lock().unwrap();
+        // The running build is now obsolete, so stop it as soon as possible.
+        // This must be checked before pushing, which replaces the marker.
+        if queued.0.is_in_progress() || queued.1.is_in_progress() {
+            trace!("cancelling the build in progress");
+            self.internals.cancelled.store(true, Ordering::SeqCst);
+        }
         Self::push_build(&mut queued, build);
 
         // Need to spawn while holding the lock on queued so that we don't race.
@@ -345,7 +408,7 @@ impl BuildQueue {
             // Find the next build to run, or terminate if there are no builds.
             let build = {
                 let mut queued = queued.lock().unwrap();
-                if queued.1.is_pending_fresh() {
+                let build = if queued.1.is_pending_fresh() {
                     let mut build = Build::InProgress;
                     mem::swap(&mut queued.1, &mut build);
                     build.try_into_pending().unwrap()
@@ -355,7 +418,10 @@ impl BuildQueue {
                     build.try_into_pending().unwrap()
                 } else {
                     return;
-                }
+                };
+                // Only builds requested after this one can cancel it.
+                internals.cancelled.store(false, Ordering::SeqCst);
+                build
             };
 
             // Normal priority threads sleep before starting up.
@@ -402,13 +468,13 @@ impl BuildQueue {
                 &build.built_files,
                 progress_sender,
             );
-            // Assert that the build was not squashed.
-            if let BuildResult::Squashed = result {
-                unreachable!();
-            }
 
             let mut pbh = build.pbh;
-            {
+            // Threads blocked on a cancelled build wait for the build which
+            // cancelled it instead.
+            if let BuildResult::Squashed = result {
+                trace!("build cancelled");
+            } else {
                 let mut blocked = internals.blocked.lock().unwrap();
                 pbh.blocked_threads.extend(blocked.drain(..));
             }
@@ -434,6 +500,51 @@ impl BuildQueue {
         trace!("Marking file as dirty: {:?} ({})", file, version);
         self.internals.dirty_files.lock().unwrap().insert(file, version);
     }
+
+    /// Records that a given file was opened by the client. With
+    /// `BuildScope::OpenFiles`, the package of the file is included in the
+    /// following builds if the build plan doesn't include it yet, and `true`
+    /// is returned so that the caller requests a build.
+    pub fn mark_file_open(&self, file: PathBuf) -> bool {
+        let scope = self.internals.config.lock().unwrap().build_scope;
+        let package = match self.internals.compilation_cx.lock().unwrap().build_plan {
+            BuildPlan::Cargo(ref plan) if scope == BuildScope::OpenFiles => {
+                plan.unbuilt_package(&file)
+            }
+            _ => None,
+        };
+        self.internals.open_files.lock().unwrap().insert(file);
+
+        match package {
+            Some(package) => {
+                trace!("opened a file of package {}, which isn't built yet", package);
+                self.internals.requested_packages.lock().unwrap().insert(package);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Includes a given workspace package in the following builds, or returns
+    /// why it can't be built. The package is only added to the build plan;
+    /// the caller should request a build.
+    pub fn request_package(&self, package: &str) -> Result<(), String> {
+        match self.internals.compilation_cx.lock().unwrap().build_plan {
+            BuildPlan::Cargo(ref plan) => match plan.has_package(package) {
+                Some(true) => {}
+                Some(false) => {
+                    return Err(format!("`{}` is not a package of the workspace", package));
+                }
+                None => return Err("the project hasn't been built yet".to_owned()),
+            },
+            BuildPlan::External(_) => {
+                return Err("the project is built with an external build command".to_owned());
+            }
+        }
+
+        self.internals.requested_packages.lock().unwrap().insert(package.to_owned());
+        Ok(())
+    }
 }
 
 impl Internals {
@@ -443,10 +554,13 @@ impl Internals {
             vfs,
             config,
             dirty_files: Arc::new(Mutex::new(HashMap::new())),
+            open_files: Mutex::default(),
+            requested_packages: Mutex::default(),
             // Since environment is global mutable state and we can run multiple server
             // instances, be sure to use a global lock to ensure env var consistency
             env_lock: EnvironmentLock::get(),
             building: AtomicBool::new(false),
+            cancelled: AtomicBool::new(false),
             blocked: Mutex::new(vec![]),
             last_build_duration: RwLock::default(),
         }
@@ -465,13 +579,16 @@ impl Internals {
         // Check if the build directory changed and update it.
         {
             let mut compilation_cx = self.compilation_cx.lock().unwrap();
+            let mut restored = false;
             if compilation_cx.build_dir.as_ref().map_or(true, |dir| dir != new_build_dir) {
-                // We'll need to re-run cargo in this case.
+                // We'll need to re-run cargo in this case...
                 assert!(priority.is_cargo());
                 (*compilation_cx).build_dir = Some(new_build_dir.to_owned());
+                // ...unless a previous RLS saved a build plan that's still valid.
+                restored = self.restore_plan(&mut compilation_cx, new_build_dir);
             }
 
-            compilation_cx.needs_rebuild = priority.is_cargo();
+            compilation_cx.needs_rebuild = priority.is_cargo() && !restored;
         }
 
         let result = self.build(progress_sender);
@@ -511,6 +628,8 @@ impl Internals {
         // do this so we can load changed code from the VFS, rather than from
         // disk).
 
+        self.discover_packages();
+
         // If the build plan has already been cached, use it, unless Cargo
         // has to be specifically rerun (e.g., when build scripts changed).
         let work = {
@@ -539,14 +658,19 @@ impl Internals {
                 }
             // Fall back to Cargo.
             } else {
+                let requested = self.requested_packages.lock().unwrap().clone();
+                let scope = self.config.lock().unwrap().build_scope;
                 // Cargo plan is recreated and `needs_rebuild` reset if we run `cargo::cargo()`.
                 match cx.build_plan {
                     BuildPlan::External(_) => WorkStatus::NeedsCargo(PackageArg::Default),
                     BuildPlan::Cargo(ref plan) => {
-                        match plan.prepare_work(&modified) {
+                        match plan.prepare_work(&modified, &requested, scope) {
                             // Don't reuse the plan if we need to rebuild.
                             WorkStatus::Execute(_) if needs_rebuild => {
-                                WorkStatus::NeedsCargo(PackageArg::Default)
+                                self.scoped_package_arg(plan, scope, &modified, requested)
+                            }
+                            WorkStatus::NeedsCargo(PackageArg::Default) => {
+                                self.scoped_package_arg(plan, scope, &modified, requested)
                             }
                             work => work,
                         }
@@ -556,20 +680,152 @@ impl Internals {
         };
         trace!("specified work: {:#?}", work);
 
-        let result = match work {
-            WorkStatus::NeedsCargo(package_arg) => cargo::cargo(self, package_arg, progress_sender),
-            WorkStatus::Execute(job_queue) => job_queue.execute(self, progress_sender),
+        // With `BuildScope::OpenFiles`, there's nothing to build until a file
+        // is opened.
+        if let WorkStatus::NeedsCargo(PackageArg::Packages(ref packages)) = work {
+            if packages.is_empty() {
+                let build_dir = self.compilation_cx.lock().unwrap().build_dir.clone().unwrap();
+                return BuildResult::Success(build_dir, vec![], vec![], HashMap::new(), true);
+            }
+        }
+
+        let (result, ran_cargo) = match work {
+            WorkStatus::NeedsCargo(package_arg) => {
+                (cargo::cargo(self, package_arg, progress_sender), true)
+            }
+            WorkStatus::Execute(job_queue) => {
+                let (result, ran_every_job) = job_queue.execute(self, progress_sender);
+                // A restored plan rebuilds all of its units, which are only
+                // analyzed once every one of them ran, even with errors.
+                if ran_every_job {
+                    if let BuildPlan::Cargo(ref mut plan) =
+                        self.compilation_cx.lock().unwrap().build_plan
+                    {
+                        plan.restored = false;
+                    }
+                }
+                (result, false)
+            }
         };
 
         if let BuildResult::Success(.., true) = result {
             let elapsed = start.elapsed();
             *self.last_build_duration.write().unwrap() = Some(elapsed);
             info!("build finished in {:.1?}", elapsed);
+            if ran_cargo {
+                self.save_plan();
+            }
         }
 
         result
     }
 
+    // Returns the RLS target directory, the root manifest and the Cargo
+    // options which a saved build plan for `build_dir` has to match.
+    fn saved_plan_location(&self, build_dir: &Path) -> Option<(PathBuf, PathBuf, String)> {
+        let config = self.config.lock().unwrap();
+        if config.build_command.is_some() {
+            return None;
+        }
+        let target_dir = config.target_dir.as_ref().clone()?;
+        let manifest_path = important_paths::find_root_manifest_for_wd(build_dir).ok()?;
+        Some((target_dir, manifest_path, cargo::plan_options(&config)))
+    }
+
+    // Uses the build plan saved by a previous RLS for `build_dir`, if it's
+    // still up to date. Returns `true` if a plan was restored.
+    fn restore_plan(&self, cx: &mut CompilationContext, build_dir: &Path) -> bool {
+        let (target_dir, manifest_path, options) = match self.saved_plan_location(build_dir) {
+            Some(location) => location,
+            None => return false,
+        };
+        match CargoPlan::load(&target_dir, &manifest_path, &options) {
+            Some((plan, cwd)) => {
+                info!("reusing the build plan saved in {}", target_dir.display());
+                cx.build_plan = BuildPlan::Cargo(plan);
+                cx.cwd = cwd;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Saves the build plan after Cargo successfully prepared it.
+    fn save_plan(&self) {
+        let cx = self.compilation_cx.lock().unwrap();
+        let build_dir = cx.build_dir.clone().unwrap();
+        let cwd = cx.cwd.clone();
+        let plan = match cx.build_plan {
+            BuildPlan::Cargo(ref plan) => plan,
+            BuildPlan::External(_) => return,
+        };
+
+        if let Some((target_dir, manifest_path, options)) = self.saved_plan_location(&build_dir) {
+            if let Err(e) = plan.save(&target_dir, &manifest_path, &options, cwd.as_deref()) {
+                debug!("couldn't save the build plan: {}", e);
+            }
+        }
+    }
+
+    /// With `BuildScope::OpenFiles`, the first build needs the packages of
+    /// the workspace before Cargo prepares a build plan. They are discovered
+    /// with `cargo metadata`, so the compilation context isn't locked then.
+    fn discover_packages(&self) {
+        {
+            let config = self.config.lock().unwrap();
+            if config.build_scope != BuildScope::OpenFiles || config.build_command.is_some() {
+                return;
+            }
+        }
+        let build_dir = {
+            let cx = self.compilation_cx.lock().unwrap();
+            match cx.build_plan {
+                BuildPlan::Cargo(ref plan) if !plan.knows_packages() => {
+                    cx.build_dir.clone().unwrap()
+                }
+                _ => return,
+            }
+        };
+        let manifest_path = match important_paths::find_root_manifest_for_wd(&build_dir) {
+            Ok(manifest_path) => manifest_path,
+            Err(_) => return,
+        };
+
+        let package_map = PackageMap::new(&manifest_path);
+        if let BuildPlan::Cargo(ref mut plan) = self.compilation_cx.lock().unwrap().build_plan {
+            if !plan.knows_packages() {
+                plan.set_package_map(package_map);
+            }
+        }
+    }
+
+    /// Returns the packages Cargo should build when it would otherwise build
+    /// its default ones. With `BuildScope::OpenFiles`, these are only the
+    /// packages with open or modified files, their reverse dependencies and
+    /// the explicitly requested packages.
+    fn scoped_package_arg(
+        &self,
+        plan: &CargoPlan,
+        scope: BuildScope,
+        modified: &[PathBuf],
+        requested: HashSet<String>,
+    ) -> WorkStatus {
+        if scope != BuildScope::OpenFiles {
+            return WorkStatus::NeedsCargo(PackageArg::Default);
+        }
+
+        let mut files: Vec<_> = self.open_files.lock().unwrap().iter().cloned().collect();
+        files.extend(modified.iter().cloned());
+        let mut packages = match plan.scoped_packages(&files) {
+            Some(packages) => packages,
+            None => return WorkStatus::NeedsCargo(PackageArg::Default),
+        };
+        packages.extend(requested);
+        trace!("packages in build scope: {:?}", packages);
+
+        WorkStatus::NeedsCargo(PackageArg::Packages(packages))
+    }
+
     /// Returns a pre-build wait time facilitating build debouncing.
     ///
     /// Uses client configured value, or attempts to infer an appropriate duration.
@@ -638,3 +894,17 @@ fn dont_auto_tune_build_wait_configured() {
     *i.last_build_duration.write().unwrap() = Some(Duration::from_millis(70));
     assert_eq!(i.build_wait(), Duration::from_millis(350));
 }
+
+#[test]
+fn cancelled_job_queue_is_squashed() {
+    let i = Internals::new(Arc::new(Vfs::new()), Arc::default());
+    i.compilation_cx.lock().unwrap().build_dir = Some(PathBuf::from("."));
+    i.cancelled.store(true, Ordering::SeqCst);
+
+    let jobs = plan::JobQueue::with_commands(vec![::cargo::util::process("rustc")]);
+    let (progress_sender, _) = channel();
+    match jobs.execute(&i, progress_sender) {
+        (BuildResult::Squashed, false) => {}
+        result => panic!("expected a squashed build, got {:?}", result),
+    }
+}
@@ -5,12 +5,14 @@
 //! * Cargo - used when we run Cargo in-process and intercept it
 //! * External - dependency graph between invocations
 
+use std::borrow::Borrow;
 use std::collections::{HashMap, HashSet};
 use std::ffi::OsStr;
 use std::hash::Hash;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::Sender;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use cargo::util::ProcessBuilder;
 use log::trace;
@@ -74,8 +76,14 @@ impl BuildPlan {
     }
 }
 
+/// Compiler invocations to run, stored as a stack: jobs that have to run
+/// first are *last*.
 #[derive(Debug)]
-pub(crate) struct JobQueue(Vec<ProcessBuilder>);
+pub(crate) struct JobQueue {
+    jobs: Vec<ProcessBuilder>,
+    /// Indices of the jobs which have to finish before a given job can start.
+    deps: Vec<Vec<usize>>,
+}
 
 /// Returns an immediately next argument to the one specified in a given
 /// ProcessBuilder (or `None` if the searched or the next argument could not be found).
@@ -91,132 +99,311 @@ fn proc_argument_value<T: AsRef<OsStr>>(prc: &ProcessBuilder, key: T) -> Option<
 }
 
 impl JobQueue {
+    /// Creates a queue whose jobs run one after another.
     pub(crate) fn with_commands(jobs: Vec<ProcessBuilder>) -> JobQueue {
-        JobQueue(jobs)
+        let deps = (0..jobs.len()).map(|i| if i + 1 < jobs.len() { vec![i + 1] } else { vec![] });
+        JobQueue { deps: deps.collect(), jobs }
     }
 
-    pub(crate) fn dequeue(&mut self) -> Option<ProcessBuilder> {
-        self.0.pop()
+    /// Creates a queue from a topologically sorted stack of `units`, where
+    /// each job only waits for the units it depends on, as given by the
+    /// reverse dependencies of every unit.
+    pub(crate) fn with_dependencies<K, F, I>(
+        units: &[K],
+        jobs: Vec<ProcessBuilder>,
+        rev_deps: F,
+    ) -> JobQueue
+    where
+        K: Eq + Hash,
+        F: Fn(&K) -> I,
+        I: IntoIterator,
+        I::Item: Borrow<K>,
+    {
+        assert_eq!(units.len(), jobs.len());
+        JobQueue { deps: dependencies(units, rev_deps), jobs }
     }
 
     /// Performs a rustc build using cached compiler invocations.
+    ///
+    /// Jobs whose dependencies have been built run concurrently, on at most
+    /// `Config::jobs` threads. In-process compilations still hold the global
+    /// `EnvironmentLock` while they run, so only out-of-process ones actually
+    /// overlap. Diagnostics and analysis are merged in the queue's order,
+    /// regardless of which jobs finish first.
+    ///
+    /// Returns `BuildResult::Squashed` if the build is cancelled by a newer
+    /// one, which is checked before every compilation unit. The build plan is
+    /// only read here, so it stays valid for the next build. Along with the
+    /// result, returns `true` if every job ran, even if some failed to compile.
     pub(super) fn execute(
-        mut self,
+        self,
         internals: &Internals,
         progress_sender: Sender<ProgressUpdate>,
-    ) -> BuildResult {
+    ) -> (BuildResult, bool) {
         // TODO: In case of an empty job queue we shouldn't be here, since the
         // returned results will replace currently held diagnostics/analyses.
         // Either allow to return a BuildResult::Squashed here or just delegate
         // to Cargo (which we do currently) in `prepare_work`
-        assert!(!self.0.is_empty());
+        assert!(!self.jobs.is_empty());
 
-        let mut compiler_messages = vec![];
-        let mut analyses = vec![];
-        let mut input_files = HashMap::<_, HashSet<_>>::new();
-        let (build_dir, mut cwd) = {
+        let (build_dir, cwd) = {
             let comp_cx = internals.compilation_cx.lock().unwrap();
             (comp_cx.build_dir.clone().expect("no build directory"), comp_cx.cwd.clone())
         };
+        let threads =
+            internals.config.lock().unwrap().jobs.map_or_else(num_cpus::get, |n| n as usize);
+        let progress_sender = Mutex::new(progress_sender);
+        let started = AtomicUsize::new(0);
+
+        let results = run_parallel(
+            &self.deps,
+            threads,
+            |idx| {
+                if internals.cancelled.load(Ordering::SeqCst) {
+                    trace!("Build cancelled, skipping unit {}", idx);
+                    return BuildResult::Squashed;
+                }
+                let job = &self.jobs[idx];
+                trace!("Executing: {:#?}", job);
+                let mut args: Vec<_> = job
+                    .get_args()
+                    .iter()
+                    .cloned()
+                    .map(|x| x.into_string().expect("cannot stringify job args"))
+                    .collect();
+
+                let program =
+                    job.get_program().clone().into_string().expect("cannot stringify job program");
+                args.insert(0, program.clone());
+
+                // Needed to parse rustc diagnostics
+                if args.iter().find(|x| x.as_str() == "--error-format=json").is_none() {
+                    args.push("--error-format=json".to_owned());
+                }
 
-        // Go through cached compiler invocations sequentially, collecting each
-        // invocation's compiler messages for diagnostics and analysis data
-        while let Some(job) = self.dequeue() {
-            trace!("Executing: {:#?}", job);
-            let mut args: Vec<_> = job
-                .get_args()
-                .iter()
-                .cloned()
-                .map(|x| x.into_string().expect("cannot stringify job args"))
-                .collect();
-
-            let program =
-                job.get_program().clone().into_string().expect("cannot stringify job program");
-            args.insert(0, program.clone());
-
-            // Needed to parse rustc diagnostics
-            if args.iter().find(|x| x.as_str() == "--error-format=json").is_none() {
-                args.push("--error-format=json".to_owned());
-            }
-
-            if args.iter().find(|x| x.as_str() == "--sysroot").is_none() {
-                let sysroot = super::rustc::current_sysroot()
-                    .expect("need to specify SYSROOT env var or use rustup or multirust");
+                if args.iter().find(|x| x.as_str() == "--sysroot").is_none() {
+                    let sysroot = super::rustc::current_sysroot()
+                        .expect("need to specify SYSROOT env var or use rustup or multirust");
 
-                let config = internals.config.lock().unwrap();
-                if config.sysroot.is_none() {
-                    args.push("--sysroot".to_owned());
-                    args.push(sysroot);
+                    let config = internals.config.lock().unwrap();
+                    if config.sysroot.is_none() {
+                        args.push("--sysroot".to_owned());
+                        args.push(sysroot);
+                    }
                 }
-            }
 
-            // Send a window/progress notification.
-            {
-                let crate_name = proc_argument_value(&job, "--crate-name").and_then(OsStr::to_str);
-                let update = match crate_name {
-                    Some(name) => {
-                        let cfg_test = job.get_args().iter().any(|arg| arg == "--test");
-                        ProgressUpdate::Message(if cfg_test {
-                            format!("{} cfg(test)", name)
-                        } else {
-                            name.to_owned()
-                        })
-                    }
-                    None => {
-                        // divide by zero is avoided by earlier assert!
-                        let percentage = compiler_messages.len() as f64 / self.0.len() as f64;
-                        ProgressUpdate::Percentage(percentage)
+                // Send a window/progress notification.
+                {
+                    let crate_name =
+                        proc_argument_value(job, "--crate-name").and_then(OsStr::to_str);
+                    let update = match crate_name {
+                        Some(name) => {
+                            let cfg_test = job.get_args().iter().any(|arg| arg == "--test");
+                            ProgressUpdate::Message(if cfg_test {
+                                format!("{} cfg(test)", name)
+                            } else {
+                                name.to_owned()
+                            })
+                        }
+                        None => {
+                            // divide by zero is avoided by earlier assert!
+                            let started = started.load(Ordering::SeqCst);
+                            ProgressUpdate::Percentage(started as f64 / self.jobs.len() as f64)
+                        }
+                    };
+
+                    let progress_sender = progress_sender.lock().unwrap();
+                    progress_sender.send(update).expect("Failed to send progress update");
+                }
+                started.fetch_add(1, Ordering::SeqCst);
+
+                match super::rustc::rustc(
+                    &internals.vfs,
+                    &args,
+                    job.get_envs(),
+                    job.get_cwd().or_else(|| cwd.as_deref()),
+                    &build_dir,
+                    Arc::clone(&internals.config),
+                    &internals.env_lock.as_facade(),
+                    Some(&internals.cancelled),
+                ) {
+                    BuildResult::Err(cause, _) => {
+                        let cmd = format!("{} {}", program, args.join(" "));
+                        BuildResult::Err(cause, Some(cmd))
                     }
-                };
-
-                progress_sender.send(update).expect("Failed to send progress update");
-            }
+                    result => result,
+                }
+            },
+            // A failed compilation doesn't error out the build as a whole,
+            // but there's no point in starting any more units.
+            |result| match result {
+                BuildResult::Success(.., success) => *success,
+                _ => false,
+            },
+        );
+
+        let ran_every_job =
+            results.iter().all(|result| matches!(result, Some(BuildResult::Success(..))));
 
-            match super::rustc::rustc(
-                &internals.vfs,
-                &args,
-                job.get_envs(),
-                job.get_cwd().or_else(|| cwd.as_deref()),
-                &build_dir,
-                Arc::clone(&internals.config),
-                &internals.env_lock.as_facade(),
-            ) {
-                BuildResult::Success(c, mut messages, mut analysis, files, success) => {
+        let mut compiler_messages = vec![];
+        let mut analyses = vec![];
+        let mut input_files = HashMap::<_, HashSet<_>>::new();
+        let mut last_cwd = None;
+        let mut success = true;
+        // Merge in the order of the queue, so that the diagnostics don't
+        // depend on which units happened to finish first.
+        for result in results.into_iter().rev() {
+            match result {
+                Some(BuildResult::Success(c, mut messages, mut analysis, files, ok)) => {
                     compiler_messages.append(&mut messages);
                     analyses.append(&mut analysis);
                     for (file, inputs) in files {
                         input_files.entry(file).or_default().extend(inputs);
                     }
-
-                    cwd = Some(c);
-
-                    // This compilation failed, but the build as a whole does not
-                    // need to error out.
-                    if !success {
-                        return BuildResult::Success(
-                            cwd.unwrap(),
-                            compiler_messages,
-                            analyses,
-                            input_files,
-                            false,
-                        );
-                    }
-                }
-                BuildResult::Err(cause, _) => {
-                    let cmd = format!("{} {}", program, args.join(" "));
-                    return BuildResult::Err(cause, Some(cmd));
+                    last_cwd = Some(c);
+                    success &= ok;
                 }
-                _ => {}
+                Some(BuildResult::Squashed) => return (BuildResult::Squashed, false),
+                Some(err @ BuildResult::Err(..)) => return (err, false),
+                Some(_) => {}
+                // The unit wasn't started, because another one failed.
+                None => success = false,
             }
         }
 
-        BuildResult::Success(
-            cwd.unwrap_or_else(|| PathBuf::from(".")),
+        let result = BuildResult::Success(
+            last_cwd.or(cwd).unwrap_or_else(|| PathBuf::from(".")),
             compiler_messages,
             analyses,
             input_files,
-            true,
-        )
+            success,
+        );
+        (result, ran_every_job)
+    }
+}
+
+/// Returns the dependencies of every unit in a topologically sorted stack of
+/// `units`, as indices into it, given the reverse dependencies of each unit.
+/// Dependencies which aren't part of `units` are ignored.
+fn dependencies<K, F, I>(units: &[K], rev_deps: F) -> Vec<Vec<usize>>
+where
+    K: Eq + Hash,
+    F: Fn(&K) -> I,
+    I: IntoIterator,
+    I::Item: Borrow<K>,
+{
+    let indices: HashMap<&K, usize> = units.iter().enumerate().map(|(i, k)| (k, i)).collect();
+    let mut deps = vec![vec![]; units.len()];
+    for (idx, unit) in units.iter().enumerate() {
+        for rev_dep in rev_deps(unit) {
+            let rev_dep: &K = rev_dep.borrow();
+            if let Some(&rev_idx) = indices.get(rev_dep) {
+                deps[rev_idx].push(idx);
+            }
+        }
+    }
+    for unit_deps in &mut deps {
+        unit_deps.sort();
+        unit_deps.dedup();
+    }
+    deps
+}
+
+/// Runs `run` for every job on at most `threads` threads, starting a job only
+/// once all of its `deps` have finished. No more jobs are started after
+/// `proceed` returns `false` for a result.
+///
+/// Returns the results in the order of `deps`, with `None` for jobs which
+/// were not run.
+fn run_parallel<T, F, P>(deps: &[Vec<usize>], threads: usize, run: F, proceed: P) -> Vec<Option<T>>
+where
+    T: Send,
+    F: Fn(usize) -> T + Sync,
+    P: Fn(&T) -> bool + Sync,
+{
+    let mut rev_deps = vec![vec![]; deps.len()];
+    for (idx, unit_deps) in deps.iter().enumerate() {
+        for &dep in unit_deps {
+            rev_deps[dep].push(idx);
+        }
+    }
+
+    let scheduler = Scheduler {
+        rev_deps,
+        state: Mutex::new(SchedulerState {
+            waiting: deps.iter().map(Vec::len).collect(),
+            results: deps.iter().map(|_| None).collect(),
+            stopped: false,
+        }),
+        run,
+        proceed,
+    };
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads.max(1))
+        .thread_name(|num| format!("rls-build-{}", num))
+        .build()
+        .expect("Failed to start build threads");
+    pool.scope(|scope| {
+        // Start from the end of the stack, like a sequential build would.
+        for idx in (0..deps.len()).rev().filter(|&idx| deps[idx].is_empty()) {
+            scheduler.spawn(scope, idx);
+        }
+    });
+
+    scheduler.state.into_inner().unwrap().results
+}
+
+struct Scheduler<T, F, P> {
+    rev_deps: Vec<Vec<usize>>,
+    state: Mutex<SchedulerState<T>>,
+    run: F,
+    proceed: P,
+}
+
+struct SchedulerState<T> {
+    /// Number of unfinished dependencies of every job.
+    waiting: Vec<usize>,
+    results: Vec<Option<T>>,
+    stopped: bool,
+}
+
+impl<T, F, P> Scheduler<T, F, P>
+where
+    T: Send,
+    F: Fn(usize) -> T + Sync,
+    P: Fn(&T) -> bool + Sync,
+{
+    /// Runs the job `idx` on the pool, followed by every job it unblocks.
+    fn spawn<'s>(&'s self, scope: &rayon::Scope<'s>, idx: usize) {
+        scope.spawn(move |scope| {
+            if self.state.lock().unwrap().stopped {
+                return;
+            }
+            let result = (self.run)(idx);
+            let proceed = (self.proceed)(&result);
+
+            let ready: Vec<_> = {
+                let mut state = self.state.lock().unwrap();
+                state.results[idx] = Some(result);
+                state.stopped |= !proceed;
+                if state.stopped {
+                    return;
+                }
+                let waiting = &mut state.waiting;
+                self.rev_deps[idx]
+                    .iter()
+                    .cloned()
+                    .filter(|&rev_dep| {
+                        waiting[rev_dep] -= 1;
+                        waiting[rev_dep] == 0
+                    })
+                    .collect()
+            };
+            for rev_dep in ready {
+                self.spawn(scope, rev_dep);
+            }
+        });
     }
 }
 
@@ -255,3 +442,57 @@ impl std::convert::TryFrom<&str> for Edition {
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Barrier;
+
+    #[test]
+    fn dependencies_of_queue() {
+        // `core` is built first, then `a` and `b`, which `bin` depends on.
+        let units = ["bin", "b", "a", "core"];
+        let rev_deps: HashMap<_, Vec<_>> =
+            vec![("core", vec!["a", "b", "unrelated"]), ("a", vec!["bin"]), ("b", vec!["bin"])]
+                .into_iter()
+                .collect();
+
+        let deps = dependencies(&units, |unit| rev_deps.get(unit).cloned().unwrap_or_default());
+        assert_eq!(deps, [vec![1, 2], vec![3], vec![3], vec![]]);
+    }
+
+    #[test]
+    fn run_parallel_independent_units() {
+        let deps = [vec![1, 2], vec![3], vec![3], vec![]];
+        let running = AtomicUsize::new(0);
+        let max_running = AtomicUsize::new(0);
+        let finished = Mutex::new(vec![]);
+        // Units 1 and 2 only finish once both of them are running.
+        let both_running = Barrier::new(2);
+
+        let run = |idx: usize| {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            max_running.fetch_max(now, Ordering::SeqCst);
+            if idx == 1 || idx == 2 {
+                both_running.wait();
+            }
+            running.fetch_sub(1, Ordering::SeqCst);
+
+            let mut finished = finished.lock().unwrap();
+            assert!(deps[idx].iter().all(|dep| finished.contains(dep)));
+            finished.push(idx);
+            idx * 10
+        };
+        let results = run_parallel(&deps, 2, run, |_| true);
+
+        assert_eq!(results, [Some(0), Some(10), Some(20), Some(30)]);
+        assert_eq!(max_running.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn run_parallel_stops_after_failure() {
+        let deps = [vec![1], vec![2], vec![]];
+        let results = run_parallel(&deps, 4, |idx| idx, |&idx| idx != 1);
+        assert_eq!(results, [None, Some(1), Some(2)]);
+    }
+}
@@ -13,6 +13,7 @@ use std::io;
 use std::mem;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 
 use log::trace;
@@ -30,10 +31,11 @@ use self::rustc_span::edition::Edition as RustcEdition;
 use self::rustc_span::source_map::{FileLoader, RealFileLoader};
 use crate::build::environment::{Environment, EnvironmentLockFacade};
 use crate::build::plan::{Crate, Edition};
-use crate::build::{BufWriter, BuildResult};
+use crate::build::{BufWriter, BuildProfile, BuildResult, CompilerMessage};
 use crate::config::{ClippyPreference, Config};
 
-// Runs a single instance of Rustc.
+// Runs a single instance of Rustc. Returns `BuildResult::Squashed` if
+// `cancelled` is set while an out-of-process compilation is running.
 pub(crate) fn rustc(
     vfs: &Vfs,
     args: &[String],
@@ -42,6 +44,7 @@ pub(crate) fn rustc(
     build_dir: &Path,
     rls_config: Arc<Mutex<Config>>,
     env_lock: &EnvironmentLockFacade,
+    cancelled: Option<&AtomicBool>,
 ) -> BuildResult {
     trace!(
         "rustc - args: `{:?}`, envs: {:?}, cwd: {:?}, build dir: {:?}",
@@ -51,6 +54,10 @@ pub(crate) fn rustc(
         build_dir
     );
 
+    if cancelled.map_or(false, |c| c.load(Ordering::SeqCst)) {
+        return BuildResult::Squashed;
+    }
+
     let changed = vfs.get_cached_files();
 
     let mut envs = envs.clone();
@@ -64,6 +71,11 @@ pub(crate) fn rustc(
         config.clippy_preference
     };
 
+    // Also emit item signatures, which are used to provide signature help.
+    let save_config = rls_data::config::Config { signatures: true, ..Default::default() };
+    let save_config = serde_json::to_string(&save_config).unwrap();
+    envs.insert(String::from("RUST_SAVE_ANALYSIS_CONFIG"), Some(OsString::from(save_config)));
+
     let lock_environment = |envs, cwd| {
         let (guard, _) = env_lock.lock();
         Environment::push_with_lock(envs, cwd, guard)
@@ -73,10 +85,15 @@ pub(crate) fn rustc(
         "RLS_OUT_OF_PROCESS",
     ) {
         #[cfg(feature = "ipc")]
-        Ok(..) => run_out_of_process(changed.clone(), &args, &envs, clippy_preference)
-            .unwrap_or_else(|_| {
-                run_in_process(changed, &args, clippy_preference, lock_environment(&envs, cwd))
-            }),
+        Ok(..) => {
+            match run_out_of_process(changed.clone(), &args, &envs, clippy_preference, cancelled) {
+                Ok(Some(result)) => result,
+                Ok(None) => return BuildResult::Squashed,
+                Err(()) => {
+                    run_in_process(changed, &args, clippy_preference, lock_environment(&envs, cwd))
+                }
+            }
+        }
         #[cfg(not(feature = "ipc"))]
         Ok(..) => {
             log::warn!("Support for out-of-process compilation was not compiled. Rebuild with 'ipc' feature enabled");
@@ -87,7 +104,11 @@ pub(crate) fn rustc(
 
     let stderr = String::from_utf8(stderr).unwrap();
     log::debug!("rustc - stderr: {}", &stderr);
-    let stderr_json_msgs: Vec<_> = stderr.lines().map(String::from).collect();
+    let profile = BuildProfile::of_args(args);
+    let stderr_json_msgs: Vec<_> = stderr
+        .lines()
+        .map(|message| CompilerMessage { message: message.to_owned(), profile })
+        .collect();
 
     let analysis = analysis.map(|analysis| vec![analysis]).unwrap_or_else(Vec::new);
     log::debug!("rustc: analysis read successfully?: {}", !analysis.is_empty());
@@ -107,13 +128,18 @@ pub struct CompilationResult {
     input_files: HashMap<PathBuf, HashSet<Crate>>,
 }
 
+/// Runs the compilation in a child process, which is killed if `cancelled` is
+/// set before it exits. Returns `Ok(None)` in that case.
 #[cfg(feature = "ipc")]
 fn run_out_of_process(
     changed: HashMap<PathBuf, String>,
     args: &[String],
     envs: &BTreeMap<String, Option<OsString>>,
     clippy_preference: ClippyPreference,
-) -> Result<CompilationResult, ()> {
+    cancelled: Option<&AtomicBool>,
+) -> Result<Option<CompilationResult>, ()> {
+    use std::process::Stdio;
+
     let analysis = Arc::default();
     let input_files = Arc::default();
 
@@ -126,29 +152,73 @@ fn run_out_of_process(
         .and_then(|x| x.to_str().map(String::from))
         .expect("Couldn't set executable for RLS rustc shim");
 
-    let output = Command::new(rustc_shim)
+    let child = Command::new(rustc_shim)
         .env(crate::RUSTC_SHIM_ENV_VAR_NAME, "1")
         .env("RLS_IPC_ENDPOINT", ipc_server.endpoint())
         .env("RLS_CLIPPY_PREFERENCE", clippy_preference.to_string())
         .args(args.iter().skip(1))
         .envs(envs.iter().filter_map(|(k, v)| v.as_ref().map(|v| (k, v))))
-        .output()
-        .map_err(|_| ());
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn();
 
-    let result = match &output {
-        Ok(output) if output.status.code() == Some(0) => Ok(()),
-        _ => Err(()),
-    };
     // NOTE: Make sure that we pass JSON error format
-    let stderr = output.map(|out| out.stderr).unwrap_or_default();
+    let output = match child {
+        Ok(child) => wait_unless_cancelled(child, cancelled),
+        Err(_) => Some((false, vec![])),
+    };
 
     ipc_server.close();
 
+    let (success, stderr) = match output {
+        Some(output) => output,
+        None => return Ok(None),
+    };
+    let result = if success { Ok(()) } else { Err(()) };
+
     let input_files = unwrap_shared(input_files, "Other ref dropped by closed IPC server");
     let analysis = unwrap_shared(analysis, "Other ref dropped by closed IPC server");
     // FIXME(#25): given that we are running the compiler directly, there is no need
     // to serialize the error messages -- we should pass them in memory.
-    Ok(CompilationResult { result, stderr, analysis, input_files })
+    Ok(Some(CompilationResult { result, stderr, analysis, input_files }))
+}
+
+/// Waits for a compiler process to exit, returning whether it succeeded and
+/// its stderr. Kills it and returns `None` if `cancelled` is set first.
+#[cfg(feature = "ipc")]
+fn wait_unless_cancelled(
+    mut child: std::process::Child,
+    cancelled: Option<&AtomicBool>,
+) -> Option<(bool, Vec<u8>)> {
+    use std::io::Read;
+    use std::thread;
+    use std::time::Duration;
+
+    // Read stderr on another thread, so that the child doesn't block on a
+    // full pipe while we poll it.
+    let stderr = child.stderr.take().map(|mut stderr| {
+        thread::spawn(move || {
+            let mut buf = vec![];
+            let _ = stderr.read_to_end(&mut buf);
+            buf
+        })
+    });
+
+    let success = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status.code() == Some(0),
+            Ok(None) if cancelled.map_or(false, |c| c.load(Ordering::SeqCst)) => {
+                trace!("rustc - killing cancelled compilation");
+                let _ = child.kill();
+                let _ = child.wait();
+                return None;
+            }
+            Ok(None) => thread::sleep(Duration::from_millis(20)),
+            Err(_) => break false,
+        }
+    };
+    let stderr = stderr.and_then(|reader| reader.join().ok()).unwrap_or_default();
+    Some((success, stderr))
 }
 
 fn run_in_process(
@@ -145,8 +145,15 @@ pub struct Config {
     pub features: Vec<String>,
     pub all_features: bool,
     pub no_default_features: bool,
+    /// Number of parallel jobs, for Cargo and for rebuilding independent
+    /// crates from the cached build plan. Defaults to the number of CPUs.
     pub jobs: Option<u32>,
     pub all_targets: bool,
+    /// Which packages of the workspace are built.
+    pub build_scope: BuildScope,
+    /// Whether crates are checked only in the configuration picked by
+    /// `cfg_test`, or both with and without `cfg(test)`.
+    pub build_profiles: BuildProfiles,
     /// Enables use of Racer for `textDocument/completion` requests.
     ///
     /// Enabled also enables racer fallbacks for hover and go-to-definition functionality
@@ -165,6 +172,12 @@ pub struct Config {
     /// local variable declaration. When set to false, the content is only available when
     /// holding the `Ctrl` key in some editors.
     pub show_hover_context: bool,
+    /// Show inlay hints for the inferred types of local variables and for
+    /// parameter names at call sites (see the `rls/inlayHints` request).
+    pub inlay_hints: bool,
+    /// Format the enclosing item or statement when `}` or `;` is typed
+    /// (requires unstable features).
+    pub format_on_type: bool,
     /// Use provided rustfmt binary instead of the statically linked one.
     /// (requires unstable features).
     pub rustfmt_path: Option<String>,
@@ -198,10 +211,14 @@ impl Default for Config {
             no_default_features: false,
             jobs: None,
             all_targets: true,
+            build_scope: BuildScope::default(),
+            build_profiles: BuildProfiles::default(),
             racer_completion: true,
             clippy_preference: ClippyPreference::default(),
             full_docs: Inferrable::Inferred(false),
             show_hover_context: true,
+            inlay_hints: true,
+            format_on_type: false,
             rustfmt_path: None,
             build_command: None,
             use_crate_blacklist: None,
@@ -305,6 +322,7 @@ impl Config {
             self.cfg_test = false;
             self.rustfmt_path = None;
             self.build_command = None;
+            self.format_on_type = false;
         }
     }
 
@@ -369,6 +387,40 @@ impl Default for ClippyPreference {
     }
 }
 
+/// Which packages of a workspace are built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BuildScope {
+    /// Build the packages Cargo builds by default.
+    Workspace,
+    /// Only build the packages with open files and the packages depending on
+    /// them, adding packages as their files are edited (or on request).
+    OpenFiles,
+}
+
+impl Default for BuildScope {
+    fn default() -> Self {
+        BuildScope::Workspace
+    }
+}
+
+/// Which `cfg(test)` configurations of the crates are checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BuildProfiles {
+    /// Only check the configuration picked by `cfg_test`.
+    Single,
+    /// Check crates both with and without `cfg(test)`, merging their analysis
+    /// and tagging the diagnostics of a single configuration with it.
+    Both,
+}
+
+impl Default for BuildProfiles {
+    fn default() -> Self {
+        BuildProfiles::Single
+    }
+}
+
 /// Permissive deserialization for `ClippyPreference`
 /// "opt-in", "Optin" -> `ClippyPreference::OptIn`
 impl FromStr for ClippyPreference {
@@ -536,3 +588,31 @@ fn blacklist_default() {
         Config::try_deserialize(&value, &mut Default::default(), &mut vec![], &mut vec![]).unwrap();
     assert_eq!(&*config.crate_blacklist.as_ref().0, &["serde".to_string()]);
 }
+
+#[test]
+fn build_scope_deserialize() {
+    let config = |value| {
+        Config::try_deserialize(&value, &mut Default::default(), &mut vec![], &mut vec![]).unwrap()
+    };
+    assert_eq!(config(serde_json::json!({})).build_scope, BuildScope::Workspace);
+    assert_eq!(
+        config(serde_json::json!({"buildScope": "open_files"})).build_scope,
+        BuildScope::OpenFiles
+    );
+    assert_eq!(
+        config(serde_json::json!({"build_scope": "workspace"})).build_scope,
+        BuildScope::Workspace
+    );
+}
+
+#[test]
+fn build_profiles_deserialize() {
+    let config = |value| {
+        Config::try_deserialize(&value, &mut Default::default(), &mut vec![], &mut vec![]).unwrap()
+    };
+    assert_eq!(config(serde_json::json!({})).build_profiles, BuildProfiles::Single);
+    assert_eq!(
+        config(serde_json::json!({"buildProfiles": "both"})).build_profiles,
+        BuildProfiles::Both
+    );
+}
@@ -300,6 +300,10 @@ impl Default for InitializationOptions {
 pub struct ClientCapabilities {
     pub code_completion_has_snippet_support: bool,
     pub related_information_support: bool,
+    pub code_action_literal_support: bool,
+    pub resource_create_support: bool,
+    pub resource_rename_support: bool,
+    pub prepare_rename_support: bool,
 }
 
 impl ClientCapabilities {
@@ -328,6 +332,282 @@ impl ClientCapabilities {
             .copied()
             .unwrap_or(false);
 
-        ClientCapabilities { code_completion_has_snippet_support, related_information_support }
+        let code_action_literal_support = params
+            .capabilities
+            .text_document
+            .as_ref()
+            .and_then(|doc| doc.code_action.as_ref())
+            .map(|action| action.code_action_literal_support.is_some())
+            .unwrap_or(false);
+
+        let prepare_rename_support = params
+            .capabilities
+            .text_document
+            .as_ref()
+            .and_then(|doc| doc.rename.as_ref())
+            .and_then(|rename| rename.prepare_support)
+            .unwrap_or(false);
+
+        let resource_operation_support = |kind: ResourceOperationKind| {
+            params
+                .capabilities
+                .workspace
+                .as_ref()
+                .and_then(|workspace| workspace.workspace_edit.as_ref())
+                .map(|edit| {
+                    edit.document_changes == Some(true)
+                        && edit
+                            .resource_operations
+                            .as_ref()
+                            .map_or(false, |ops| ops.contains(&kind))
+                })
+                .unwrap_or(false)
+        };
+        let resource_create_support = resource_operation_support(ResourceOperationKind::Create);
+        let resource_rename_support = resource_operation_support(ResourceOperationKind::Rename);
+
+        ClientCapabilities {
+            code_completion_has_snippet_support,
+            related_information_support,
+            code_action_literal_support,
+            resource_create_support,
+            resource_rename_support,
+            prepare_rename_support,
+        }
     }
 }
+
+/* ------  Extensions to the protocol not (yet) supported by `lsp_types` ------ */
+
+/// `ServerCapabilities` extended with the capabilities of requests which are
+/// not supported by `lsp_types`.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExtendedServerCapabilities {
+    #[serde(flatten)]
+    pub capabilities: ServerCapabilities,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub call_hierarchy_provider: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub type_hierarchy_provider: Option<bool>,
+    /// `lsp_types::ServerCapabilities` does not have this (yet).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub document_link_provider: Option<DocumentLinkOptions>,
+    /// The legend of the tokens returned by `rls/semanticTokens`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub semantic_tokens_legend: Option<SemanticTokensLegend>,
+}
+
+/// The result of the `initialize` request, with our extended capabilities.
+#[derive(Debug, Serialize)]
+pub struct ExtendedInitializeResult {
+    pub capabilities: ExtendedServerCapabilities,
+}
+
+/// The `textDocument/prepareCallHierarchy` request.
+#[derive(Debug)]
+pub enum CallHierarchyPrepare {}
+
+impl LSPRequest for CallHierarchyPrepare {
+    type Params = TextDocumentPositionParams;
+    type Result = Option<Vec<CallHierarchyItem>>;
+    const METHOD: &'static str = "textDocument/prepareCallHierarchy";
+}
+
+/// The `callHierarchy/incomingCalls` request.
+#[derive(Debug)]
+pub enum CallHierarchyIncomingCalls {}
+
+impl LSPRequest for CallHierarchyIncomingCalls {
+    type Params = CallHierarchyCallsParams;
+    type Result = Option<Vec<CallHierarchyIncomingCall>>;
+    const METHOD: &'static str = "callHierarchy/incomingCalls";
+}
+
+/// The `callHierarchy/outgoingCalls` request.
+#[derive(Debug)]
+pub enum CallHierarchyOutgoingCalls {}
+
+impl LSPRequest for CallHierarchyOutgoingCalls {
+    type Params = CallHierarchyCallsParams;
+    type Result = Option<Vec<CallHierarchyOutgoingCall>>;
+    const METHOD: &'static str = "callHierarchy/outgoingCalls";
+}
+
+/// A function or method in the call hierarchy.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallHierarchyItem {
+    pub name: String,
+    pub kind: SymbolKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    pub uri: Url,
+    /// The whole item, including its body.
+    pub range: Range,
+    /// The name of the item.
+    pub selection_range: Range,
+}
+
+/// Parameters of both the incoming and outgoing calls requests.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct CallHierarchyCallsParams {
+    pub item: CallHierarchyItem,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallHierarchyIncomingCall {
+    /// The function which makes the calls.
+    pub from: CallHierarchyItem,
+    /// The ranges of the calls, in `from`.
+    pub from_ranges: Vec<Range>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallHierarchyOutgoingCall {
+    /// The function which is called.
+    pub to: CallHierarchyItem,
+    /// The ranges of the calls, in the item passed to the request.
+    pub from_ranges: Vec<Range>,
+}
+
+/// The `textDocument/prepareTypeHierarchy` request.
+#[derive(Debug)]
+pub enum TypeHierarchyPrepare {}
+
+impl LSPRequest for TypeHierarchyPrepare {
+    type Params = TextDocumentPositionParams;
+    type Result = Option<Vec<TypeHierarchyItem>>;
+    const METHOD: &'static str = "textDocument/prepareTypeHierarchy";
+}
+
+/// The `typeHierarchy/supertypes` request.
+#[derive(Debug)]
+pub enum TypeHierarchySupertypes {}
+
+impl LSPRequest for TypeHierarchySupertypes {
+    type Params = TypeHierarchyParams;
+    type Result = Option<Vec<TypeHierarchyItem>>;
+    const METHOD: &'static str = "typeHierarchy/supertypes";
+}
+
+/// The `typeHierarchy/subtypes` request.
+#[derive(Debug)]
+pub enum TypeHierarchySubtypes {}
+
+impl LSPRequest for TypeHierarchySubtypes {
+    type Params = TypeHierarchyParams;
+    type Result = Option<Vec<TypeHierarchyItem>>;
+    const METHOD: &'static str = "typeHierarchy/subtypes";
+}
+
+/// A type or trait in the type hierarchy.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TypeHierarchyItem {
+    pub name: String,
+    pub kind: SymbolKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    pub uri: Url,
+    /// The whole item, including its body.
+    pub range: Range,
+    /// The name of the item.
+    pub selection_range: Range,
+    /// The impl blocks relating this item to the item of the request, if any.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub data: Vec<Location>,
+}
+
+/// Parameters of both the supertypes and subtypes requests.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct TypeHierarchyParams {
+    pub item: TypeHierarchyItem,
+}
+
+/// The `rls/inlayHints` request, for the inferred types of local variables
+/// and the names of parameters at call sites.
+#[derive(Debug)]
+pub enum InlayHints {}
+
+impl LSPRequest for InlayHints {
+    type Params = InlayHintsParams;
+    type Result = Vec<InlayHint>;
+    const METHOD: &'static str = "rls/inlayHints";
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlayHintsParams {
+    pub text_document: TextDocumentIdentifier,
+    /// The visible range, hints are only computed for this range.
+    pub range: Range,
+}
+
+/// A hint to be displayed inline at `position`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct InlayHint {
+    pub position: Position,
+    pub label: String,
+    pub kind: InlayHintKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum InlayHintKind {
+    /// The inferred type of a variable, displayed after its name.
+    Type,
+    /// The name of a parameter, displayed before the argument.
+    Parameter,
+}
+
+/// The `rls.buildPackage` command. It's sent like any other command, as a
+/// `workspace/executeCommand` request, but it's handled on the main thread
+/// since it requests a build.
+#[derive(Debug)]
+pub enum BuildPackage {}
+
+impl LSPRequest for BuildPackage {
+    type Params = ExecuteCommandParams;
+    type Result = Option<serde_json::Value>;
+    const METHOD: &'static str = "workspace/executeCommand";
+}
+
+impl BuildPackage {
+    pub const COMMAND: &'static str = "rls.buildPackage";
+}
+
+/// The `rls/semanticTokens` request, for the semantic classification of the
+/// identifiers, macros and lifetimes of a document.
+#[derive(Debug)]
+pub enum SemanticTokensRequest {}
+
+impl LSPRequest for SemanticTokensRequest {
+    type Params = SemanticTokensParams;
+    type Result = SemanticTokens;
+    const METHOD: &'static str = "rls/semanticTokens";
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticTokensParams {
+    pub text_document: TextDocumentIdentifier,
+}
+
+/// The tokens of a document. Each token is encoded as five integers: the
+/// line and start character (relative to the previous token), the length,
+/// the index of the token type in the legend and a bit set of modifiers.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct SemanticTokens {
+    pub data: Vec<u32>,
+}
+
+/// The token types and modifiers used by `rls/semanticTokens`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticTokensLegend {
+    pub token_types: Vec<String>,
+    pub token_modifiers: Vec<String>,
+}
@@ -130,6 +130,9 @@ impl Package {
     pub fn lib_root(self, project: &ProjectModel) -> Option<&Path> {
         project.get(self).lib.as_ref().map(|p| p.0.as_path())
     }
+    pub fn lib_name(self, project: &ProjectModel) -> Option<&str> {
+        project.get(self).lib.as_ref().map(|p| p.1.as_str())
+    }
 }
 
 // We use the following wrappers to teach Racer about the structure
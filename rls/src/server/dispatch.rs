@@ -87,12 +87,27 @@ macro_rules! define_dispatch_request_enum {
 define_dispatch_request_enum!(
     Completion,
     Definition,
+    TypeDefinition,
+    FoldingRange,
+    SelectionRange,
+    CallHierarchyPrepare,
+    CallHierarchyIncomingCalls,
+    CallHierarchyOutgoingCalls,
+    TypeHierarchyPrepare,
+    TypeHierarchySupertypes,
+    TypeHierarchySubtypes,
+    InlayHints,
+    SemanticTokensRequest,
+    DocumentLinkRequest,
+    OnTypeFormatting,
     References,
     WorkspaceSymbol,
     Symbols,
     Hover,
+    SignatureHelp,
     Implementation,
     DocumentHighlight,
+    PrepareRename,
     Rename,
     CodeAction,
     ResolveCompletion,
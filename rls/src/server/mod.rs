@@ -2,11 +2,12 @@
 //! interactions (for example, to add support for handling new types of
 //! requests).
 
-use crate::actions::{notifications, requests, ActionContext};
+use crate::actions::{notifications, requests, semantic_tokens, ActionContext};
 use crate::config::{Config, DEPRECATED_OPTIONS};
 use crate::lsp_data;
 use crate::lsp_data::{
-    InitializationOptions, LSPNotification, LSPRequest, MessageType, ShowMessageParams,
+    ExtendedInitializeResult, ExtendedServerCapabilities, InitializationOptions, LSPNotification,
+    LSPRequest, MessageType, ShowMessageParams,
 };
 use crate::server::dispatch::Dispatcher;
 pub use crate::server::dispatch::{RequestAction, DEFAULT_REQUEST_TIMEOUT};
@@ -24,9 +25,11 @@ pub use lsp_types::notification::{Exit as ExitNotification, ShowMessage};
 pub use lsp_types::request::Initialize as InitializeRequest;
 pub use lsp_types::request::Shutdown as ShutdownRequest;
 use lsp_types::{
-    CodeActionProviderCapability, CodeLensOptions, CompletionOptions, ExecuteCommandOptions,
-    ImplementationProviderCapability, InitializeParams, InitializeResult, RenameProviderCapability,
-    ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind,
+    CodeActionProviderCapability, CodeLensOptions, CompletionOptions, DocumentLinkOptions,
+    ExecuteCommandOptions, FoldingRangeProviderCapability, GenericCapability,
+    ImplementationProviderCapability, InitializeParams, RenameOptions, RenameProviderCapability,
+    ServerCapabilities, SignatureHelpOptions, TextDocumentSyncCapability, TextDocumentSyncKind,
+    TypeDefinitionProviderCapability,
 };
 use rls_analysis::AnalysisHost;
 use rls_vfs::Vfs;
@@ -38,7 +41,7 @@ mod dispatch;
 mod io;
 mod message;
 
-const NOT_INITIALIZED_CODE: ErrorCode = ErrorCode::ServerError(-32002);
+pub(crate) const NOT_INITIALIZED_CODE: ErrorCode = ErrorCode::ServerError(-32002);
 
 /// Runs the Rust Language Server.
 pub fn run_server(analysis: Arc<AnalysisHost>, vfs: Arc<Vfs>) -> i32 {
@@ -176,13 +179,13 @@ impl BlockingRequestAction for InitializeRequest {
         maybe_notify_deprecated_configs(&out, &deprecated);
         maybe_notify_duplicated_configs(&out, &dups);
 
-        let result = InitializeResult { capabilities: server_caps(ctx) };
+        let capabilities = lsp_data::ClientCapabilities::new(&params);
+        let result = ExtendedInitializeResult { capabilities: server_caps(ctx, &capabilities) };
 
         // Send response early before `ctx.init` to enforce
         // initialize-response-before-all-other-messages constraint.
         result.send(id, &out);
 
-        let capabilities = lsp_data::ClientCapabilities::new(&params);
         ctx.init(get_root_path(&params), init_options, capabilities, &out).unwrap();
 
         Ok(NoResponse)
@@ -303,6 +306,30 @@ impl<O: Output> LsService<O> {
             }
         }
 
+        // `rls.buildPackage` requests a build, which can only be done from the
+        // main thread, so unlike other commands it's a blocking request.
+        if msg.method == <requests::BuildPackage as LSPRequest>::METHOD {
+            let request: Request<requests::BuildPackage> = msg.parse_as_request()?;
+            if request.params.command.starts_with(requests::BuildPackage::COMMAND) {
+                self.wait_for_concurrent_jobs();
+
+                let req_id = request.id.clone();
+                match request.blocking_dispatch(&mut self.ctx, &self.output) {
+                    Ok(res) => res.send(req_id, &self.output),
+                    Err(ResponseError::Empty) => self.output.failure_message(
+                        req_id,
+                        ErrorCode::InternalError,
+                        "An unknown error occurred",
+                    ),
+                    Err(ResponseError::Message(code, msg)) => {
+                        debug!("error handling {}: {}", requests::BuildPackage::COMMAND, msg);
+                        self.output.failure_message(req_id, code, msg)
+                    }
+                }
+                return Ok(());
+            }
+        }
+
         // Notifications and blocking requests are handled immediately on the
         // main thread. They will never be dropped.
         // Blocking requests wait for all non-blocking requests to complete,
@@ -331,14 +358,29 @@ impl<O: Output> LsService<O> {
                 requests::Formatting,
                 requests::RangeFormatting,
                 requests::ResolveCompletion,
+                requests::PrepareRename,
                 requests::Rename,
                 requests::CodeAction,
                 requests::DocumentHighlight,
                 requests::Implementation,
                 requests::Symbols,
                 requests::Hover,
+                requests::SignatureHelp,
                 requests::WorkspaceSymbol,
                 requests::Definition,
+                requests::TypeDefinition,
+                requests::FoldingRange,
+                requests::SelectionRange,
+                requests::CallHierarchyPrepare,
+                requests::CallHierarchyIncomingCalls,
+                requests::CallHierarchyOutgoingCalls,
+                requests::TypeHierarchyPrepare,
+                requests::TypeHierarchySupertypes,
+                requests::TypeHierarchySubtypes,
+                requests::InlayHints,
+                requests::SemanticTokensRequest,
+                requests::DocumentLinkRequest,
+                requests::OnTypeFormatting,
                 requests::References,
                 requests::Completion,
                 requests::CodeLensRequest;
@@ -417,8 +459,11 @@ pub enum ServerStateChange {
     Break { exit_code: i32 },
 }
 
-fn server_caps(ctx: &ActionContext) -> ServerCapabilities {
-    ServerCapabilities {
+fn server_caps(
+    ctx: &ActionContext,
+    client_capabilities: &lsp_data::ClientCapabilities,
+) -> ExtendedServerCapabilities {
+    let capabilities = ServerCapabilities {
         text_document_sync: Some(TextDocumentSyncCapability::Kind(
             TextDocumentSyncKind::Incremental,
         )),
@@ -428,7 +473,7 @@ fn server_caps(ctx: &ActionContext) -> ServerCapabilities {
             trigger_characters: Some(vec![".".to_string(), ":".to_string()]),
         }),
         definition_provider: Some(true),
-        type_definition_provider: None,
+        type_definition_provider: Some(TypeDefinitionProviderCapability::Simple(true)),
         implementation_provider: Some(ImplementationProviderCapability::Simple(true)),
         references_provider: Some(true),
         document_highlight_provider: Some(true),
@@ -443,9 +488,24 @@ fn server_caps(ctx: &ActionContext) -> ServerCapabilities {
             commands: vec![
                 format!("rls.applySuggestion-{}", ctx.pid()),
                 format!("rls.deglobImports-{}", ctx.pid()),
+                format!("rls.extractFunction-{}", ctx.pid()),
+                format!("rls.extractVariable-{}", ctx.pid()),
+                format!("rls.inlineLocal-{}", ctx.pid()),
+                format!("rls.inlineFunction-{}", ctx.pid()),
+                format!("rls.organizeImports-{}", ctx.pid()),
+                format!("rls.generate-{}", ctx.pid()),
+                format!("rls.changeSignature-{}", ctx.pid()),
+                format!("rls.moveModule-{}", ctx.pid()),
+                format!("rls.buildPackage-{}", ctx.pid()),
             ],
         }),
-        rename_provider: Some(RenameProviderCapability::Simple(true)),
+        // Rename options may only be sent to clients supporting
+        // `textDocument/prepareRename`.
+        rename_provider: Some(if client_capabilities.prepare_rename_support {
+            RenameProviderCapability::Options(RenameOptions { prepare_provider: Some(true) })
+        } else {
+            RenameProviderCapability::Simple(true)
+        }),
         color_provider: None,
 
         // These are supported if the `unstable_features` option is set.
@@ -455,11 +515,21 @@ fn server_caps(ctx: &ActionContext) -> ServerCapabilities {
 
         code_lens_provider: Some(CodeLensOptions { resolve_provider: Some(false) }),
         document_on_type_formatting_provider: None,
-        signature_help_provider: None,
+        signature_help_provider: Some(SignatureHelpOptions {
+            trigger_characters: Some(vec!["(".to_string(), ",".to_string()]),
+        }),
 
-        folding_range_provider: None,
+        folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
         workspace: None,
-        selection_range_provider: None,
+        selection_range_provider: Some(GenericCapability::default()),
+    };
+
+    ExtendedServerCapabilities {
+        capabilities,
+        call_hierarchy_provider: Some(true),
+        type_hierarchy_provider: Some(true),
+        document_link_provider: Some(DocumentLinkOptions { resolve_provider: Some(false) }),
+        semantic_tokens_legend: Some(semantic_tokens::legend()),
     }
 }
 
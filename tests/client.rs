@@ -6,7 +6,7 @@ use lsp_types::{notification::*, request::*, *};
 use serde::de::Deserialize;
 use serde_json::json;
 
-use crate::support::project_builder::{project, ProjectBuilder};
+use crate::support::project_builder::{project, Project, ProjectBuilder};
 use crate::support::{basic_bin_manifest, fixtures_dir};
 
 #[allow(dead_code)]
@@ -1760,6 +1760,145 @@ fn client_rename() {
     assert_eq!(result.changes, Some(changes));
 }
 
+#[test]
+fn client_prepare_rename() {
+    let p = ProjectBuilder::try_from_fixture(fixtures_dir().join("common")).unwrap().build();
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    let opts = json!({"settings": {"rust": { "all_targets": false } } });
+    rls.request::<Initialize>(0, initialize_params_with_opts(root_path, opts));
+
+    rls.wait_for_indexing();
+
+    let uri = Url::from_file_path(p.root().join("src/main.rs")).unwrap();
+    let params = |line, character| TextDocumentPositionParams {
+        position: Position { line, character },
+        text_document: TextDocumentIdentifier { uri: uri.clone() },
+    };
+
+    let result = rls.request::<PrepareRenameRequest>(42, params(12, 29));
+    let range = Range {
+        start: Position { line: 12, character: 27 },
+        end: Position { line: 12, character: 32 },
+    };
+    assert_eq!(result, Some(PrepareRenameResponse::Range(range)));
+
+    // `println` is defined in the standard library, so the request fails
+    // with the reason.
+    rls.send(json!({
+        "jsonrpc": "2.0",
+        "id": 43,
+        "method": PrepareRenameRequest::METHOD,
+        "params": params(12, 6),
+    }));
+    let msg = rls.wait_for_message(|msg| msg["id"] == 43);
+    assert_eq!(msg["result"], serde_json::Value::Null);
+    // `InvalidRequest`
+    assert_eq!(msg["error"]["code"], -32600);
+    assert!(msg["error"]["message"].as_str().unwrap().starts_with("Cannot rename: "));
+}
+
+fn rename_project(name: &str) -> Project {
+    project(name)
+        .file("Cargo.toml", &basic_bin_manifest("foo"))
+        .file(
+            "src/main.rs",
+            "mod bar;\n\
+             \n\
+             fn first() {}\n\
+             fn second() {}\n\
+             fn main() { first(); second(); bar::baz(); }\n",
+        )
+        .file("src/bar.rs", "pub fn baz() {}\n")
+        .build()
+}
+
+fn rename_params(uri: Url, line: u64, character: u64, new_name: &str) -> RenameParams {
+    RenameParams {
+        text_document_position: TextDocumentPositionParams {
+            position: Position { line, character },
+            text_document: TextDocumentIdentifier { uri },
+        },
+        new_name: new_name.to_owned(),
+    }
+}
+
+#[test]
+fn client_rename_module_file() {
+    let p = rename_project("rename_module_file");
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    let opts = json!({"settings": {"rust": { "all_targets": false } } });
+    let capabilities = json!({
+        "workspace": {
+            "workspaceEdit": { "documentChanges": true, "resourceOperations": ["rename"] }
+        },
+        "window": { "progress": true },
+    });
+    rls.request::<Initialize>(
+        0,
+        InitializeParams {
+            capabilities: serde_json::from_value(capabilities).unwrap(),
+            ..initialize_params_with_opts(root_path, opts)
+        },
+    );
+
+    rls.wait_for_indexing();
+
+    let uri = Url::from_file_path(p.root().join("src/main.rs")).unwrap();
+    let result = rls.request::<Rename>(42, rename_params(uri.clone(), 0, 4, "qux")).unwrap();
+
+    let operations = match result.document_changes {
+        Some(DocumentChanges::Operations(operations)) => operations,
+        changes => panic!("Expected document change operations, got {:?}", changes),
+    };
+    assert_eq!(operations.len(), 2);
+    match &operations[0] {
+        DocumentChangeOperation::Edit(TextDocumentEdit { text_document, edits }) => {
+            assert_eq!(text_document.uri, uri);
+            assert!(edits.iter().all(|edit| edit.new_text == "qux"));
+            let mut ranges: Vec<_> = edits.iter().map(|edit| edit.range).collect();
+            ranges.sort_by_key(|range| range.start.line);
+            let decl = Range::new(Position::new(0, 4), Position::new(0, 7));
+            let path = Range::new(Position::new(4, 31), Position::new(4, 34));
+            assert_eq!(ranges, vec![decl, path]);
+        }
+        op => panic!("Expected the edits of main.rs, got {:?}", op),
+    }
+    assert_eq!(
+        operations[1],
+        DocumentChangeOperation::Op(ResourceOp::Rename(RenameFile {
+            old_uri: Url::from_file_path(p.root().join("src/bar.rs")).unwrap(),
+            new_uri: Url::from_file_path(p.root().join("src/qux.rs")).unwrap(),
+            options: None,
+        }))
+    );
+}
+
+#[test]
+fn client_rename_conflict() {
+    let p = rename_project("rename_conflict");
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    let opts = json!({"settings": {"rust": { "all_targets": false } } });
+    rls.request::<Initialize>(0, initialize_params_with_opts(root_path, opts));
+
+    rls.wait_for_indexing();
+
+    let uri = Url::from_file_path(p.root().join("src/main.rs")).unwrap();
+    let result = rls.request::<Rename>(42, rename_params(uri, 2, 3, "second")).unwrap();
+
+    assert_eq!(result, WorkspaceEdit { changes: None, document_changes: None });
+    assert!(rls.messages().iter().any(|msg| {
+        msg["method"] == ShowMessage::METHOD
+            && msg["params"]["message"]
+                == "Rename failed: `second` is already defined in this scope"
+    }));
+}
+
 #[test]
 fn client_reformat() {
     let p = ProjectBuilder::try_from_fixture(fixtures_dir().join("reformat")).unwrap().build();